@@ -0,0 +1,351 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::{
+    cache, pagination,
+    sanitize::Sanitizer,
+    settings::{Layout, Scrape},
+};
+
+/* Everything specific to one site's markup and URL layout */
+#[derive(Debug)]
+pub struct Adapter {
+    pub name: &'static str,
+    pub base_url: String,
+    /* Every base URL serving this site, in failover order; base_url is the one in use */
+    pub mirrors: Vec<String>,
+    /* Torrent paths under the torrent directory, without extension, that replace the URL layout */
+    pub placements: BTreeMap<String, String>,
+    pub sanitizer: Sanitizer,
+    pub layout: Layout,
+    page_numbers: Selector,
+    links: Selector,
+    torrent_regex: Regex,
+    page_path: String,
+    headers: Vec<(&'static str, &'static str)>,
+}
+
+/* Browser-like headers sent with every request; values may use {base_url}, {host} and {fetch_site} */
+#[derive(Debug, Clone)]
+pub struct Headers {
+    base_url: String,
+    template: Vec<(String, String)>,
+}
+
+impl Headers {
+    /* Settings replace headers of the same name and append the rest, keeping the template's order */
+    pub fn merge(&mut self, overrides: &BTreeMap<String, String>) {
+        for (name, value) in overrides {
+            let existing = self
+                .template
+                .iter_mut()
+                .find(|(existing, _)| existing.eq_ignore_ascii_case(name));
+            match existing {
+                Some((_, existing)) => *existing = value.clone(),
+                None => self.template.push((name.clone(), value.clone())),
+            }
+        }
+    }
+
+    pub fn render(&self, url: &str) -> Result<HeaderMap> {
+        let host = host(url);
+        let base_host = host(&self.base_url);
+        let site = base_host.trim_start_matches("www.");
+        let fetch_site = match host {
+            _ if host == base_host => "same-origin",
+            _ if host == site || host.ends_with(&format!(".{site}")) => "same-site",
+            _ => "cross-site",
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.template {
+            let value = value
+                .replace("{base_url}", &self.base_url)
+                .replace("{host}", host)
+                .replace("{fetch_site}", fetch_site);
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(&value)?,
+            );
+        }
+
+        Ok(headers)
+    }
+
+    /* With a step's own headers over the template */
+    pub fn render_with(
+        &self,
+        url: &str,
+        overrides: &BTreeMap<String, String>,
+    ) -> Result<HeaderMap> {
+        if overrides.is_empty() {
+            return self.render(url);
+        }
+
+        let mut headers = self.clone();
+        headers.merge(overrides);
+
+        headers.render(url)
+    }
+}
+
+fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_scheme, rest)| rest);
+
+    rest.split('/').next().unwrap_or(rest)
+}
+
+impl Adapter {
+    pub fn ptorrents() -> Self {
+        Self {
+            name: "ptorrents",
+            base_url: "http://www.ptorrents.com".to_string(),
+            mirrors: Vec::new(),
+            placements: BTreeMap::new(),
+            sanitizer: Sanitizer::default(),
+            layout: Layout::default(),
+            page_numbers: Selector::parse("a.page-numbers").unwrap(),
+            links: Selector::parse("a[href]").unwrap(),
+            torrent_regex: Regex::new(
+                r"^https://d\.ptorrents\.com/(.+)/\[ptorrents.com\]\.(.+)\.torrent$",
+            )
+            .unwrap(),
+            page_path: "/page/{page}".to_string(),
+            /* The CDN in front of d.ptorrents.com drops requests that do not look like Chrome */
+            headers: vec![
+                (
+                    "Accept",
+                    "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8",
+                ),
+                ("Accept-Language", "en-US,en;q=0.9"),
+                ("Referer", "{base_url}/"),
+                ("Sec-Fetch-Dest", "document"),
+                ("Sec-Fetch-Mode", "navigate"),
+                ("Sec-Fetch-Site", "{fetch_site}"),
+                ("Sec-Fetch-User", "?1"),
+                ("Upgrade-Insecure-Requests", "1"),
+            ],
+        }
+    }
+
+    pub fn find(name: &str) -> Option<Self> {
+        [Self::ptorrents()]
+            .into_iter()
+            .find(|adapter| adapter.name == name)
+    }
+
+    pub fn apply(&mut self, scrape: &Scrape) -> Result<()> {
+        let selector = |selector: &str| {
+            Selector::parse(selector)
+                .map_err(|error| anyhow!("Invalid selector {selector}: {error}"))
+        };
+
+        if let Some(page_numbers) = &scrape.page_numbers {
+            self.page_numbers = selector(page_numbers)?;
+        }
+        if let Some(links) = &scrape.links {
+            self.links = selector(links)?;
+        }
+        if let Some(torrent_regex) = &scrape.torrent_regex {
+            self.torrent_regex = Regex::new(torrent_regex)
+                .with_context(|| format!("Invalid torrent regex {torrent_regex}"))?;
+            if self.torrent_regex.captures_len() < 3 {
+                bail!("Torrent regex {torrent_regex} must capture the directory and the file name");
+            }
+        }
+        if let Some(page_path) = &scrape.page_path {
+            if !page_path.contains("{page}") {
+                bail!("Page path {page_path} has no {{page}} placeholder");
+            }
+            self.page_path = page_path.clone();
+        }
+
+        Ok(())
+    }
+
+    pub fn host(&self) -> &str {
+        host(&self.base_url)
+    }
+
+    pub fn headers(&self) -> Headers {
+        let template = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        Headers {
+            base_url: self.base_url.clone(),
+            template,
+        }
+    }
+
+    pub fn set_scheme(&mut self, scheme: &str) {
+        let rest = self
+            .base_url
+            .split_once("://")
+            .map_or(self.base_url.as_str(), |(_scheme, rest)| rest);
+        self.base_url = format!("{scheme}://{rest}");
+    }
+
+    pub fn page_url(&self, page: usize) -> String {
+        let path = self.page_path.replace("{page}", &page.to_string());
+
+        format!("{}{path}", self.base_url)
+    }
+
+    pub fn entry_url(&self, entry: &str) -> String {
+        format!("{}/{entry}", self.base_url)
+    }
+
+    pub fn max_pages(&self, html: &Html) -> Result<usize> {
+        pagination::from_markup(html, &self.page_numbers).context("Failed to find page numbers")
+    }
+
+    /* Like max_pages, but probes page URLs through exists when the markup has no page count */
+    pub fn discover_max_pages(
+        &self,
+        html: &Html,
+        exists: impl FnMut(usize) -> Result<bool>,
+    ) -> Result<usize> {
+        pagination::discover(html, &self.page_numbers, exists)
+    }
+
+    /* Where the entry page is cached, with the scraped link made safe as a path */
+    pub fn entry_path(&self, html_path: &str, entry: &str) -> String {
+        self.layout
+            .entry_path(html_path, &self.sanitizer.path(entry))
+    }
+
+    pub fn entry_links(&self, html: &Html) -> Vec<String> {
+        self.links(html, ".html")
+    }
+
+    pub fn torrent_links(&self, html: &Html) -> Vec<String> {
+        self.links(html, ".torrent")
+    }
+
+    fn links(&self, html: &Html, pat: &str) -> Vec<String> {
+        html.select(&self.links)
+            .filter_map(|e| e.value().attr("href"))
+            .map(String::from)
+            .filter(|s| s.ends_with(pat))
+            .map(|s| self.relative(&s))
+            .collect()
+    }
+
+    /* Links are stored without the base URL, whichever mirror and scheme served them */
+    fn relative(&self, url: &str) -> String {
+        let hosts = std::iter::once(self.base_url.as_str())
+            .chain(self.mirrors.iter().map(String::as_str))
+            .map(host);
+        for host in hosts {
+            let path = ["https", "http"]
+                .iter()
+                .find_map(|scheme| url.strip_prefix(&format!("{scheme}://{host}")));
+            if let Some(path) = path {
+                return path.to_string();
+            }
+        }
+
+        url.to_string()
+    }
+
+    pub fn torrent_name<'a>(&self, url: &'a str) -> Option<&'a str> {
+        let captures = self.torrent_regex.captures(url)?;
+
+        captures.get(2).map(|m| m.as_str())
+    }
+
+    pub fn torrent_path(&self, torrents_path: &str, url: &str) -> Option<String> {
+        if let Some(relative) = self.placements.get(url) {
+            return Some(cache::join(
+                torrents_path,
+                &[&format!("{relative}.TORRENT")],
+            ));
+        }
+
+        let captures = self.torrent_regex.captures(url)?;
+        let path = self.sanitizer.path(captures.get(1)?.as_str());
+        let name = self.sanitizer.component(captures.get(2)?.as_str());
+
+        Some(cache::join(
+            torrents_path,
+            &[&path, &format!("{name}.TORRENT")],
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Expected {
+    max_pages: usize,
+    entries: usize,
+    torrents: usize,
+}
+
+/* Fixtures mirror the HTML cache: INDEX.HTML, PAGES/ and ENTRIES/, plus an expected.json */
+pub fn test(adapter: &Adapter, fixtures: &str) -> Result<()> {
+    let fixtures = Path::new(fixtures);
+    let expected = fs::read_to_string(fixtures.join("expected.json"))?;
+    let expected = serde_json::from_str::<Expected>(&expected)?;
+
+    let index = fs::read_to_string(fixtures.join("INDEX.HTML"))?;
+    let max_pages = adapter.max_pages(&Html::parse_document(&index))?;
+
+    let mut entries = BTreeSet::new();
+    for contents in read_fixtures(&fixtures.join("PAGES"))? {
+        entries.extend(adapter.entry_links(&Html::parse_document(&contents)));
+    }
+
+    let mut torrents = BTreeSet::new();
+    for contents in read_fixtures(&fixtures.join("ENTRIES"))? {
+        torrents.extend(adapter.torrent_links(&Html::parse_document(&contents)));
+    }
+
+    let unmapped = torrents
+        .iter()
+        .filter(|url| adapter.torrent_path(".", url).is_none())
+        .count();
+
+    let checks = [
+        ("max pages", expected.max_pages, max_pages),
+        ("entries", expected.entries, entries.len()),
+        ("torrents", expected.torrents, torrents.len()),
+        ("unmapped torrents", 0, unmapped),
+    ];
+
+    println!("{:<20}  {:>8}  {:>8}", "CHECK", "EXPECTED", "ACTUAL");
+    for (name, expected, actual) in checks {
+        let status = if expected == actual { "ok" } else { "FAILED" };
+        println!("{name:<20}  {expected:>8}  {actual:>8}  {status}");
+    }
+
+    let failed = checks.iter().filter(|(_, e, a)| e != a).count();
+    if failed > 0 {
+        bail!("Adapter {} failed {failed} fixture checks", adapter.name);
+    }
+
+    println!("Adapter {} passed all fixture checks", adapter.name);
+
+    Ok(())
+}
+
+fn read_fixtures(dir: &Path) -> Result<Vec<String>> {
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| Ok(fs::read_to_string(entry.path())?))
+        .collect()
+}
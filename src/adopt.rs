@@ -0,0 +1,93 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::Result;
+use kdam::{rayon::prelude::*, BarExt, TqdmParallelIterator};
+use tracing::warn;
+use walkdir::WalkDir;
+
+use crate::{adapter::Adapter, bencode, config::Config, events, xref::normalize};
+
+/* Registers torrents downloaded by hand so the crawler treats them as already archived */
+pub fn adopt(
+    adapter: &Adapter,
+    dir: &str,
+    base_path: &str,
+    torrents_path: &str,
+    config: &mut Config,
+) -> Result<()> {
+    let names = config
+        .torrents
+        .iter()
+        .filter_map(|url| {
+            let name = adapter.torrent_name(url)?;
+            Some((normalize(name), url.clone()))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let files = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| {
+            let extension = path.extension().and_then(|e| e.to_str());
+            extension.is_some_and(|e| e.eq_ignore_ascii_case("torrent"))
+        })
+        .collect::<Vec<_>>();
+
+    let mut bar = events::bar(files.len());
+    bar.write(format!("Adopting {} torrents from {dir}...", files.len()))?;
+
+    let adopted = files
+        .into_par_iter()
+        .tqdm_with_bar(bar)
+        .filter_map(|path| {
+            let bytes = fs::read(&path).ok()?;
+            let Ok(info_hash) = bencode::info_hash(&bytes) else {
+                warn!(path = %path.display(), "Failed to parse torrent");
+                return None;
+            };
+
+            let name = bencode::decode(&bytes).ok().and_then(|torrent| {
+                let name = torrent.get("info")?.get("name")?.as_str()?;
+                Some(name.to_string())
+            });
+            let stem = path.file_stem().and_then(|s| s.to_str()).map(String::from);
+
+            let url = [stem, name]
+                .into_iter()
+                .flatten()
+                .find_map(|name| names.get(&normalize(&name)).cloned());
+
+            Some((path.display().to_string(), info_hash, url))
+        })
+        .collect::<Vec<_>>();
+
+    let mut matched = 0;
+    for (source, info_hash, url) in adopted {
+        if let Some(url) = url {
+            if let Some(path) = adapter.torrent_path(torrents_path, &url) {
+                if !Path::new(&path).exists() {
+                    if let Some(parent) = Path::new(&path).parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::copy(&source, &path)?;
+                }
+            }
+
+            config.introduce([&url]);
+            config.infohashes.insert(url, info_hash.clone());
+            matched += 1;
+        }
+
+        config.adopted.insert(info_hash, source);
+    }
+
+    println!(
+        "Adopted {} torrents ({matched} matched to scraped entries)",
+        config.adopted.len()
+    );
+    config.save(base_path)?;
+
+    Ok(())
+}
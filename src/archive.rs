@@ -0,0 +1,175 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Result};
+use chrono::Utc;
+use tracing::warn;
+
+use crate::{
+    adapter::Adapter,
+    cache,
+    config::{Archived, Config},
+    retention::{self, DAY},
+    settings::Retention,
+};
+
+/* Moves or deletes the files of old entries, and marks them archived in the state so they are not fetched again */
+pub fn archive(
+    adapter: &Adapter,
+    config: &mut Config,
+    settings: &Retention,
+    base_path: &str,
+    html_path: &str,
+    torrents_path: &str,
+    dry_run: bool,
+) -> Result<()> {
+    let keep_entries = settings.keep_entries_newer_than.as_deref();
+    let keep_entries = keep_entries.map(retention::parse_age).transpose()?;
+    let keep_html = settings.keep_html_days.map(|days| DAY * days as u32);
+    if keep_entries.is_none() && keep_html.is_none() {
+        bail!("Nothing to archive by; set --keep-entries-newer-than or --keep-html-days");
+    }
+
+    let now = SystemTime::now();
+    let mut planned = Vec::new();
+    for entry in config
+        .entries
+        .iter()
+        .filter(|entry| !config.tombstones.contains(*entry))
+        .filter(|entry| !config.archived.get(*entry).is_some_and(|a| a.torrents))
+    {
+        let path = adapter.entry_path(html_path, entry);
+        let Some(age) = age(config, entry, &path, now) else {
+            continue;
+        };
+
+        let torrents = keep_entries.is_some_and(|keep| age > keep);
+        let html = keep_html.is_some_and(|keep| age > keep) && cache::exists(&path);
+        if torrents || html {
+            let days = age.as_secs() / DAY.as_secs();
+            let what = if torrents { "entry" } else { "html" };
+            println!("{days:>6}d  {what:<6}  {entry}");
+            planned.push((entry.clone(), path, torrents));
+        }
+    }
+
+    let entries = planned.iter().filter(|(_, _, torrents)| *torrents).count();
+    if dry_run {
+        println!(
+            "Would archive {entries} entries and the HTML of {} more",
+            planned.len() - entries
+        );
+        return Ok(());
+    }
+
+    let to = settings.archive_to.as_deref();
+    let mut files = 0;
+    let mut archived = [0, 0];
+    let mut failed = 0;
+    for (entry, path, torrents) in planned {
+        let mut paths = cache::locate(&path).into_iter().collect::<Vec<_>>();
+        if torrents {
+            let links = config.links.get(&entry).into_iter().flatten();
+            let links = links.filter_map(|url| adapter.torrent_path(torrents_path, url));
+            paths.extend(links.filter(|path| Path::new(path).exists()));
+        }
+
+        let mut complete = true;
+        for path in paths {
+            match evict(&path, base_path, to) {
+                Ok(()) => files += 1,
+                Err(error) => {
+                    warn!(path, %error, "Failed to archive file");
+                    complete = false;
+                }
+            }
+        }
+
+        /* Entries with a file left behind are tried again by the next `archive` */
+        match complete {
+            true => {
+                archived[usize::from(torrents)] += 1;
+                let record = Archived {
+                    at: Some(Utc::now()),
+                    to: to.map(str::to_string),
+                    torrents,
+                };
+                config.archived.insert(entry, record);
+            }
+            false => failed += 1,
+        }
+    }
+
+    config.save(base_path)?;
+
+    let verb = if to.is_some() { "moved" } else { "deleted" };
+    let [html, entries] = archived;
+    println!("Archived {entries} entries and the HTML of {html} more, {verb} {files} files");
+    if failed > 0 {
+        println!("Failed to archive {failed} entries, see the log");
+    }
+
+    Ok(())
+}
+
+/* When the entry was saved, by its cached page, or by when it was last fetched once that is gone */
+fn age(config: &Config, entry: &str, path: &str, now: SystemTime) -> Option<Duration> {
+    let saved = cache::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .or_else(|| {
+            let fetched = config.fetched.get(entry)?.at?;
+            Some(SystemTime::from(fetched))
+        })?;
+
+    now.duration_since(saved).ok()
+}
+
+/* Moves path to the same place under to, or deletes it without one */
+fn evict(path: &str, base_path: &str, to: Option<&str>) -> Result<()> {
+    let Some(to) = to else {
+        fs::remove_file(path)?;
+        return Ok(());
+    };
+
+    let source = Path::new(path);
+    let relative = match source.strip_prefix(base_path) {
+        Ok(relative) => relative,
+        Err(_error) => Path::new(source.file_name().unwrap_or_default()),
+    };
+    let target = Path::new(to).join(relative);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    /* A rename cannot cross filesystems, which an archive disk usually is */
+    if fs::rename(source, &target).is_err() {
+        fs::copy(source, &target)?;
+        fs::remove_file(source)?;
+    }
+
+    Ok(())
+}
+
+/* Torrent links only archived entries link to, which step 7 skips */
+pub fn archived_torrents(config: &Config) -> HashSet<&String> {
+    let kept = config
+        .links
+        .iter()
+        .filter(|(entry, _links)| !config.archived.get(*entry).is_some_and(|a| a.torrents))
+        .flat_map(|(_entry, links)| links)
+        .collect::<HashSet<_>>();
+
+    config
+        .archived
+        .iter()
+        .filter(|(_entry, archived)| archived.torrents)
+        .filter_map(|(entry, _archived)| config.links.get(entry))
+        .flatten()
+        .filter(|url| !kept.contains(url))
+        .collect()
+}
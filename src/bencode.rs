@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use sha1::{Digest, Sha1};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Bytes(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Value> {
+    let (value, end) = parse(bytes, 0)?;
+    if end != bytes.len() {
+        bail!("Trailing data after bencoded value at byte {end}");
+    }
+
+    Ok(value)
+}
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write(value, &mut bytes);
+
+    bytes
+}
+
+/* Dictionaries are BTreeMaps, so keys come out in the sorted order bencode requires */
+fn write(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Int(int) => out.extend(format!("i{int}e").as_bytes()),
+        Value::Bytes(bytes) => {
+            out.extend(format!("{}:", bytes.len()).as_bytes());
+            out.extend(bytes);
+        }
+        Value::List(list) => {
+            out.push(b'l');
+            for value in list {
+                write(value, out);
+            }
+            out.push(b'e');
+        }
+        Value::Dict(dict) => {
+            out.push(b'd');
+            for (key, value) in dict {
+                write(&Value::Bytes(key.clone()), out);
+                write(value, out);
+            }
+            out.push(b'e');
+        }
+    }
+}
+
+/* The infohash covers the info dictionary exactly as stored, so hash its raw span rather than a re-encoding */
+pub fn info_hash(bytes: &[u8]) -> Result<String> {
+    if bytes.first() != Some(&b'd') {
+        bail!("Torrent is not a dictionary");
+    }
+
+    let mut pos = 1;
+    while bytes.get(pos) != Some(&b'e') {
+        let (key, start) = parse(bytes, pos)?;
+        let (_value, end) = parse(bytes, start)?;
+
+        if key == Value::Bytes(b"info".to_vec()) {
+            let digest = Sha1::digest(&bytes[start..end]);
+            return Ok(format!("{digest:x}"));
+        }
+
+        pos = end;
+    }
+
+    bail!("Torrent has no info dictionary")
+}
+
+fn parse(bytes: &[u8], pos: usize) -> Result<(Value, usize)> {
+    match bytes.get(pos).context("Unexpected end of bencoded data")? {
+        b'i' => {
+            let end = find(bytes, pos + 1, b'e')?;
+            let int = std::str::from_utf8(&bytes[pos + 1..end])?.parse()?;
+
+            Ok((Value::Int(int), end + 1))
+        }
+        b'l' => {
+            let mut list = Vec::new();
+            let mut pos = pos + 1;
+            while bytes.get(pos) != Some(&b'e') {
+                let (value, end) = parse(bytes, pos)?;
+                list.push(value);
+                pos = end;
+            }
+
+            Ok((Value::List(list), pos + 1))
+        }
+        b'd' => {
+            let mut dict = BTreeMap::new();
+            let mut pos = pos + 1;
+            while bytes.get(pos) != Some(&b'e') {
+                let (Value::Bytes(key), start) = parse(bytes, pos)? else {
+                    bail!("Dictionary key at byte {pos} is not a string");
+                };
+                let (value, end) = parse(bytes, start)?;
+                dict.insert(key, value);
+                pos = end;
+            }
+
+            Ok((Value::Dict(dict), pos + 1))
+        }
+        b'0'..=b'9' => {
+            let colon = find(bytes, pos, b':')?;
+            let len = std::str::from_utf8(&bytes[pos..colon])?.parse::<usize>()?;
+            let start = colon + 1;
+            let end = start + len;
+            if end > bytes.len() {
+                bail!("String at byte {pos} runs past the end of the data");
+            }
+
+            Ok((Value::Bytes(bytes[start..end].to_vec()), end))
+        }
+        byte => bail!("Unexpected byte {byte:#04x} at {pos}"),
+    }
+}
+
+fn find(bytes: &[u8], pos: usize, needle: u8) -> Result<usize> {
+    bytes[pos..]
+        .iter()
+        .position(|byte| *byte == needle)
+        .map(|offset| pos + offset)
+        .context("Unterminated bencoded value")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(text: &str) -> Value {
+        Value::Bytes(text.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn decodes_every_kind() {
+        let value = decode(b"d4:listli1ei-2ee4:name4:spame").unwrap();
+
+        assert_eq!(
+            value.get("list"),
+            Some(&Value::List(vec![Value::Int(1), Value::Int(-2)]))
+        );
+        assert_eq!(value.get("name").and_then(Value::as_str), Some("spam"));
+        assert_eq!(value.get("missing"), None);
+    }
+
+    #[test]
+    fn encodes_keys_sorted() {
+        let mut dict = BTreeMap::new();
+        dict.insert(b"zebra".to_vec(), Value::Int(0));
+        dict.insert(b"apple".to_vec(), bytes(""));
+
+        assert_eq!(encode(&Value::Dict(dict)), b"d5:apple0:5:zebrai0ee");
+    }
+
+    #[test]
+    fn round_trips() {
+        let raw = b"d8:announce9:udp://x:14:infod6:lengthi42e4:name1:aee";
+
+        assert_eq!(encode(&decode(raw).unwrap()), raw);
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        assert!(decode(b"i1ei2e").is_err());
+        assert!(decode(b"5:abc").is_err());
+        assert!(decode(b"li1e").is_err());
+        assert!(decode(b"di1ei2ee").is_err());
+        assert!(decode(b"x").is_err());
+        assert!(decode(b"").is_err());
+    }
+
+    #[test]
+    fn hashes_the_info_dictionary_as_stored() {
+        /* Keys out of order, so a re-encoding would hash differently */
+        let info = b"d4:name1:a6:lengthi42ee";
+        let torrent = [b"d8:announce3:url4:info".as_slice(), info, b"e"].concat();
+
+        assert_eq!(
+            info_hash(&torrent).unwrap(),
+            format!("{:x}", Sha1::digest(info))
+        );
+        assert!(info_hash(b"d8:announce3:urle").is_err());
+        assert!(info_hash(b"li1ee").is_err());
+    }
+}
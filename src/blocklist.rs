@@ -0,0 +1,87 @@
+use std::{collections::BTreeMap, fs};
+
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+
+use crate::config::Config;
+
+/* Regexes, or globs after "glob:", one per line and matched case-insensitively against entry URLs, titles and torrent URLs */
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl Blocklist {
+    pub fn load(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+
+        let mut patterns = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let source = match line.strip_prefix("glob:") {
+                Some(glob) => glob_to_regex(glob.trim()),
+                None => line.to_string(),
+            };
+            let regex = RegexBuilder::new(&source)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("Invalid blocklist pattern {line}"))?;
+            patterns.push((line.to_string(), regex));
+        }
+
+        Ok(Self { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /* The line of the first pattern matching any of texts */
+    pub fn matches(&self, texts: &[&str]) -> Option<&str> {
+        self.patterns
+            .iter()
+            .find(|(_line, regex)| texts.iter().any(|text| regex.is_match(text)))
+            .map(|(line, _regex)| line.as_str())
+    }
+
+    /* Records newly blocked entries and torrents in the state, so they are never weighed again */
+    pub fn apply(&self, config: &mut Config) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+
+        let mut blocked = BTreeMap::new();
+        for entry in &config.entries {
+            let title = config.metadata.get(entry).map(|m| m.title.as_str());
+            let links = config.links.get(entry).into_iter().flatten();
+
+            let pattern = self.matches(&[entry.as_str(), title.unwrap_or_default()]);
+            if let Some(pattern) = pattern {
+                if !config.blocked.contains_key(entry) {
+                    blocked.insert(entry.clone(), pattern.to_string());
+                }
+            }
+
+            /* Torrents of a blocked entry go with it */
+            for url in links.filter(|url| !config.blocked.contains_key(*url)) {
+                if let Some(pattern) = pattern.or_else(|| self.matches(&[url.as_str()])) {
+                    blocked.insert(url.clone(), pattern.to_string());
+                }
+            }
+        }
+
+        let count = blocked.len();
+        config.blocked.extend(blocked);
+
+        count
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let regex = regex::escape(glob).replace(r"\*", ".*").replace(r"\?", ".");
+
+    format!("^{regex}$")
+}
@@ -0,0 +1,93 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+
+use crate::{
+    cache::{self, Compression},
+    settings::BrowserSettings,
+};
+
+/* Renders pages whose links only exist after JavaScript runs; needs the browser feature */
+pub struct Renderer {
+    #[cfg(feature = "browser")]
+    browser: headless_chrome::Browser,
+    #[cfg(feature = "browser")]
+    settings: BrowserSettings,
+    #[cfg(feature = "browser")]
+    user_agent: String,
+    compression: Compression,
+}
+
+impl Renderer {
+    #[cfg(feature = "browser")]
+    pub fn new(
+        settings: &BrowserSettings,
+        user_agent: &str,
+        compression: Compression,
+    ) -> Result<Self> {
+        use std::{path::PathBuf, time::Duration};
+
+        use headless_chrome::{Browser, LaunchOptions};
+
+        let options = LaunchOptions::default_builder()
+            .path(settings.path.as_ref().map(PathBuf::from))
+            .proxy_server(settings.proxy.as_deref())
+            .idle_browser_timeout(Duration::from_secs(settings.timeout * 2))
+            .build()?;
+
+        Ok(Self {
+            browser: Browser::new(options)?,
+            settings: settings.clone(),
+            user_agent: user_agent.to_string(),
+            compression,
+        })
+    }
+
+    #[cfg(not(feature = "browser"))]
+    pub fn new(
+        _settings: &BrowserSettings,
+        _user_agent: &str,
+        _compression: Compression,
+    ) -> Result<Self> {
+        anyhow::bail!("browser.enabled is set, but this build lacks the browser feature")
+    }
+
+    #[cfg(feature = "browser")]
+    fn render(&self, url: &str) -> Result<String> {
+        use std::{thread, time::Duration};
+
+        let tab = self.browser.new_tab()?;
+        tab.set_default_timeout(Duration::from_secs(self.settings.timeout));
+        tab.set_user_agent(&self.user_agent, None, None)?;
+        tab.navigate_to(url)?.wait_until_navigated()?;
+
+        /* Scripts that add links after the load event get this long to finish */
+        thread::sleep(Duration::from_millis(self.settings.settle));
+        let contents = tab.get_content()?;
+        let _ = tab.close(false);
+
+        Ok(contents)
+    }
+
+    #[cfg(not(feature = "browser"))]
+    fn render(&self, _url: &str) -> Result<String> {
+        unreachable!("Renderer cannot be built without the browser feature")
+    }
+
+    /* Rendered pages are cached like fetched ones, so each is only rendered once */
+    pub fn fetch(&self, url: &str, path: &str) -> Result<String> {
+        if cache::exists(path) {
+            return Ok(cache::read_to_string(path)?);
+        }
+
+        let contents = self.render(url)?;
+        let (file, bytes) = cache::encode(path, contents.clone().into_bytes(), self.compression)?;
+        if let Some(parent) = Path::new(&file).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(file, bytes)?;
+        cache::discard_other(path, self.compression);
+
+        Ok(contents)
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use kdam::BarExt;
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::{config::Config, download, events, settings::Layout};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+}
+
+/* Joins parts that use / whatever the platform onto base, with the platform's separator and no empty components */
+pub fn join(base: &str, parts: &[&str]) -> String {
+    let mut path = Path::new(base).components().collect::<PathBuf>();
+    for part in parts {
+        path.extend(part.split('/').filter(|component| !component.is_empty()));
+    }
+
+    path.to_string_lossy().into_owned()
+}
+
+/* The form join gives a path, which is how paths are keyed in the state */
+pub fn normalize(path: &str) -> String {
+    join(path, &[])
+}
+
+/* HTML cache files keep their logical path everywhere; a gzipped one is stored beside it as .gz */
+fn gz_path(path: &str) -> String {
+    format!("{path}.gz")
+}
+
+pub fn locate(path: &str) -> Option<String> {
+    [path.to_string(), gz_path(path)]
+        .into_iter()
+        .find(|path| Path::new(path).exists())
+}
+
+pub fn exists(path: &str) -> bool {
+    locate(path).is_some()
+}
+
+pub fn metadata(path: &str) -> io::Result<fs::Metadata> {
+    fs::metadata(locate(path).as_deref().unwrap_or(path))
+}
+
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    if Path::new(path).exists() {
+        return fs::read(path);
+    }
+
+    let mut bytes = Vec::new();
+    GzDecoder::new(File::open(gz_path(path))?).read_to_end(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+/* Pages are read as served, with anything that is not UTF-8 replaced */
+pub fn read_to_string(path: &str) -> io::Result<String> {
+    Ok(String::from_utf8_lossy(&read(path)?).into_owned())
+}
+
+/* Real pages are tens of kilobytes even gzipped; anything this small is an error or placeholder body */
+const MIN_BYTES: u64 = 512;
+
+/* Cached and worth keeping: saved with a success status, where known, and not suspiciously small */
+pub fn is_valid(path: &str, status: Option<u16>) -> bool {
+    let size = metadata(path).map(|metadata| metadata.len()).ok();
+
+    size.is_some_and(|size| size >= MIN_BYTES) && status.is_none_or(|status| status / 100 == 2)
+}
+
+/* Drops a copy left in the other format, which would otherwise shadow or outlive the new one */
+pub fn discard_other(path: &str, compression: Compression) {
+    let other = match compression {
+        Compression::None => gz_path(path),
+        Compression::Gzip => path.to_string(),
+    };
+
+    let _ = fs::remove_file(other);
+}
+
+pub fn remove(path: &str) -> bool {
+    let plain = fs::remove_file(path).is_ok();
+    let gzipped = fs::remove_file(gz_path(path)).is_ok();
+
+    plain || gzipped
+}
+
+/* Returns where and what to write for a logical path */
+pub fn encode(
+    path: &str,
+    bytes: Vec<u8>,
+    compression: Compression,
+) -> io::Result<(String, Vec<u8>)> {
+    match compression {
+        Compression::None => Ok((path.to_string(), bytes)),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&bytes)?;
+
+            Ok((gz_path(path), encoder.finish()?))
+        }
+    }
+}
+
+/* Drops cached pages that are error bodies, challenges or truncated, so the run that follows fetches them again */
+pub fn revalidate(
+    config: &mut Config,
+    layout: &Layout,
+    html_path: &str,
+    dry_run: bool,
+) -> Result<usize> {
+    let paths = WalkDir::new(html_path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let path = entry.path().to_str()?;
+
+            Some(path.strip_suffix(".gz").unwrap_or(path).to_string())
+        })
+        .collect::<BTreeSet<_>>();
+
+    let bad = paths
+        .into_iter()
+        .filter(|path| {
+            let status = config.statuses.get(path).copied();
+            !is_valid(path, status)
+                || read_to_string(path).map_or(true, |text| download::is_challenge(&text))
+        })
+        .collect::<Vec<_>>();
+
+    for path in &bad {
+        match config.statuses.get(path) {
+            Some(status) => println!("{path} (HTTP {status})"),
+            None => println!("{path}"),
+        }
+    }
+
+    if dry_run {
+        println!("Would drop {} bad cached pages", bad.len());
+        return Ok(bad.len());
+    }
+
+    for path in &bad {
+        remove(path);
+        config.validators.remove(path);
+        config.statuses.remove(path);
+    }
+
+    /* Step 3 only fetches pages when the count grows, so let it start over; unchanged pages answer 304 */
+    let pages = layout.pages_path(html_path);
+    if bad.iter().any(|path| Path::new(path).starts_with(&pages)) {
+        config.max_pages = 0;
+    }
+
+    println!("Dropped {} bad cached pages", bad.len());
+
+    Ok(bad.len())
+}
+
+/* Rewrites every cached page into the configured format */
+pub fn migrate(html_path: &str, compression: Compression) -> Result<()> {
+    let paths = WalkDir::new(html_path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let path = entry.path().to_str()?.to_string();
+            let gzipped = path.ends_with(".gz");
+            let logical = path.strip_suffix(".gz").unwrap_or(&path).to_string();
+
+            (gzipped != (compression == Compression::Gzip)).then_some(logical)
+        })
+        .collect::<Vec<_>>();
+
+    let mut bar = events::bar(paths.len());
+    bar.write(format!(
+        "Migrating {} cached pages to {compression:?}...",
+        paths.len()
+    ))?;
+
+    for path in &paths {
+        let (target, bytes) = encode(path, read(path)?, compression)?;
+        fs::write(target, bytes)?;
+        discard_other(path, compression);
+
+        bar.update(1)?;
+    }
+
+    println!("Migrated {} cached pages", paths.len());
+
+    Ok(())
+}
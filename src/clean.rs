@@ -0,0 +1,116 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use walkdir::WalkDir;
+
+use crate::{adapter::Adapter, bencode, config::Config};
+
+/* Finds cached files the state no longer references, and torrents that cannot be used */
+pub fn clean(
+    adapter: &Adapter,
+    config: &mut Config,
+    base_path: &str,
+    html_path: &str,
+    torrents_path: &str,
+    delete: bool,
+) -> Result<()> {
+    let pages = (1..=config.max_pages)
+        .map(|page| PathBuf::from(adapter.layout.page_path(html_path, page)))
+        .collect::<HashSet<_>>();
+    let entries = config
+        .entries
+        .iter()
+        .filter(|entry| !config.tombstones.contains(*entry))
+        .map(|entry| PathBuf::from(adapter.entry_path(html_path, entry)))
+        .collect::<HashSet<_>>();
+    let torrents = config
+        .torrents
+        .iter()
+        .filter_map(|url| adapter.torrent_path(torrents_path, url))
+        .map(PathBuf::from)
+        .collect::<HashSet<_>>();
+    let media = config
+        .media
+        .values()
+        .flatten()
+        .map(PathBuf::from)
+        .collect::<HashSet<_>>();
+
+    let mut found = Vec::new();
+    for path in files(&adapter.layout.pages_path(html_path)) {
+        if !pages.contains(&logical(&path)) {
+            found.push(("stale page", path));
+        }
+    }
+    for path in files(&adapter.layout.entries_path(html_path)) {
+        if !entries.contains(&logical(&path)) {
+            found.push(("orphaned entry", path));
+        }
+    }
+    for path in files(torrents_path) {
+        if media.contains(&path) {
+            continue;
+        }
+        if !torrents.contains(&path) {
+            found.push(("orphaned torrent", path));
+            continue;
+        }
+
+        let bytes = fs::read(&path)?;
+        if bytes.is_empty() {
+            found.push(("empty torrent", path));
+        } else if bencode::decode(&bytes).is_err() {
+            found.push(("corrupt torrent", path));
+        }
+    }
+
+    for (reason, path) in &found {
+        println!("{reason:<16}  {}", path.display());
+    }
+
+    if !delete {
+        println!(
+            "Found {} files to clean, pass --delete to remove them",
+            found.len()
+        );
+        return Ok(());
+    }
+
+    for (_reason, path) in &found {
+        fs::remove_file(path)?;
+    }
+
+    let removed = found
+        .into_iter()
+        .map(|(_reason, path)| path)
+        .collect::<HashSet<_>>();
+    let kept = |path: &String| !removed.contains(Path::new(path));
+    config.checksums.retain(|path, _| kept(path));
+    config.validators.retain(|path, _| kept(path));
+    config.save(base_path)?;
+
+    println!("Deleted {} files", removed.len());
+
+    Ok(())
+}
+
+fn files(dir: &str) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/* Gzipped cache files are matched against the logical path they stand for */
+fn logical(path: &Path) -> PathBuf {
+    match path.extension().is_some_and(|extension| extension == "gz") {
+        true => path.with_extension(""),
+        false => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,162 @@
+use std::{sync::Mutex, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use kdam::BarExt;
+use reqwest::{blocking::Client, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::{events, settings::ClientSettings, summary::Tally};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Api {
+    #[default]
+    Qbittorrent,
+    Transmission,
+}
+
+/* A torrent client's web API, which fetches each torrent itself so nothing is written locally */
+pub struct TorrentClient<'a> {
+    settings: &'a ClientSettings,
+    url: &'a str,
+    http: Client,
+    session: Mutex<Option<String>>,
+}
+
+impl<'a> TorrentClient<'a> {
+    pub fn new(settings: &'a ClientSettings) -> Result<Self> {
+        let url = settings
+            .url
+            .as_deref()
+            .context("Direct mode needs a client url in torrents.toml")?;
+        let http = Client::builder()
+            .cookie_store(true)
+            .timeout(Duration::from_secs(30))
+            .build()?;
+
+        Ok(Self {
+            settings,
+            url: url.trim_end_matches('/'),
+            http,
+            session: Mutex::new(None),
+        })
+    }
+
+    /* Returns the URLs the client accepted */
+    pub fn send(&self, urls: Vec<String>, text: String) -> Result<(Tally, Vec<String>)> {
+        self.login()?;
+
+        let mut bar = events::bar(urls.len());
+        bar.write(text)?;
+
+        let mut tally = Tally {
+            requested: urls.len(),
+            ..Tally::default()
+        };
+        let mut sent = Vec::new();
+        for url in urls {
+            match self.add(&url) {
+                Ok(()) => {
+                    tally.succeeded += 1;
+                    sent.push(url);
+                }
+                Err(error) => {
+                    warn!(url, %error, "Failed to send torrent to client");
+                    tally.failed += 1;
+                }
+            }
+
+            bar.update(1)?;
+        }
+
+        Ok((tally, sent))
+    }
+
+    fn login(&self) -> Result<()> {
+        let Api::Qbittorrent = self.settings.api else {
+            return Ok(());
+        };
+
+        let form = [
+            (
+                "username",
+                self.settings.username.as_deref().unwrap_or_default(),
+            ),
+            (
+                "password",
+                self.settings.password.as_deref().unwrap_or_default(),
+            ),
+        ];
+        let response = self
+            .http
+            .post(format!("{}/api/v2/auth/login", self.url))
+            .form(&form)
+            .send()?;
+
+        if response.text()?.trim() != "Ok." {
+            bail!("qBittorrent rejected the login");
+        }
+
+        Ok(())
+    }
+
+    fn add(&self, url: &str) -> Result<()> {
+        match self.settings.api {
+            Api::Qbittorrent => {
+                let response = self
+                    .http
+                    .post(format!("{}/api/v2/torrents/add", self.url))
+                    .form(&[("urls", url)])
+                    .send()?;
+
+                if response.text()?.trim() != "Ok." {
+                    bail!("qBittorrent refused {url}");
+                }
+            }
+            Api::Transmission => {
+                let body = json!({
+                    "method": "torrent-add",
+                    "arguments": { "filename": url },
+                });
+                let response = self.rpc(&body)?;
+
+                let result = response.get("result").and_then(Value::as_str);
+                if result != Some("success") {
+                    bail!("Transmission refused {url}: {}", result.unwrap_or_default());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /* Transmission answers 409 with the session id to use, once per session */
+    fn rpc(&self, body: &Value) -> Result<Value> {
+        for _ in 0..2 {
+            let mut request = self.http.post(self.url).json(body);
+            if let Some(username) = &self.settings.username {
+                request = request.basic_auth(username, self.settings.password.as_ref());
+            }
+            if let Some(session) = self.session.lock().unwrap().as_ref() {
+                request = request.header("X-Transmission-Session-Id", session);
+            }
+
+            let response = request.send()?;
+            if response.status() == StatusCode::CONFLICT {
+                let session = response
+                    .headers()
+                    .get("X-Transmission-Session-Id")
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from);
+                *self.session.lock().unwrap() = session;
+                continue;
+            }
+
+            return Ok(response.error_for_status()?.json()?);
+        }
+
+        bail!("Transmission did not accept the session id")
+    }
+}
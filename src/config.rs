@@ -1,22 +1,112 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     ffi::OsStr,
-    fs::File,
-    io::{Read, Seek, Write},
-    path::PathBuf,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
 };
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::{
+    cache,
+    download::{DeadLetter, Validator},
+    metadata::Metadata,
+    proxy::Anonymity,
+    summary::LastRun,
+    xref::Sources,
+};
+
+pub const VERSION: u32 = 2;
+
+/* Rotated copies kept as TORRENTS.JSON.1 (newest) to TORRENTS.JSON.N */
+const BACKUPS: usize = 3;
 
 #[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
+    pub version: u32,
+    /* Run that last saved this state */
+    pub run_id: Option<String>,
+    pub last_run: Option<LastRun>,
+    /* Scheme the site was last seen enforcing, through a redirect or HSTS */
+    pub scheme: Option<String>,
+    /* Base URL that last answered, so runs skipping step 2 stay on it */
+    pub mirror: Option<String>,
     pub max_pages: usize,
     pub entries: Vec<String>,
     pub torrents: Vec<String>,
+    /* Entry links last scraped from each listing page */
+    pub pages: BTreeMap<usize, Vec<String>>,
+    /* Contents hash of each listing page when it was last scraped */
+    pub page_hashes: BTreeMap<usize, String>,
+    /* Torrent links scraped from each entry page */
+    pub links: BTreeMap<String, Vec<String>>,
+    /* Title, language and kind scraped from each entry page */
+    pub metadata: BTreeMap<String, Metadata>,
+    /* ETag and Last-Modified of each cached file, keyed by path */
+    pub validators: BTreeMap<String, Validator>,
+    /* When each entry page was last fetched, and the hash of what it held when last scraped */
+    pub fetched: BTreeMap<String, Fetched>,
+    /* Files the last run gave up on, with the error of their final attempt */
+    pub dead_letters: Vec<DeadLetter>,
+    /* HTTP status each cached file was saved with, keyed by path */
+    pub statuses: BTreeMap<String, u16>,
+    /* Templated path of each torrent under the torrent directory, keyed by URL */
+    pub placements: BTreeMap<String, String>,
+    /* Checksum of each archived torrent, keyed by path */
+    pub checksums: BTreeMap<String, String>,
+    /* Size of each archived torrent when it was hashed, keyed by path */
+    pub sizes: BTreeMap<String, u64>,
+    /* Infohash of each torrent, keyed by URL */
+    pub infohashes: BTreeMap<String, String>,
+    /* Source path of torrents registered by `adopt`, keyed by infohash */
+    pub adopted: BTreeMap<String, String>,
+    /* Description and image files archived beside each entry's torrents, which clean leaves alone */
+    pub media: BTreeMap<String, Vec<String>>,
+    /* Torrent links already handed to the torrent client in direct mode */
+    pub sent: BTreeSet<String>,
+    /* Anonymity of each proxy at its last check */
+    pub anonymity: BTreeMap<String, Anonymity>,
+    /* Entries and torrent links matched by the blocklist, with the pattern that matched */
+    pub blocked: BTreeMap<String, String>,
+    /* Entries skipped for having fewer seeders than limits.min_seeders, with the count last scraped */
+    pub dead: BTreeMap<String, u64>,
+    /* Duplicate entries the release family policy passes over, with the entry kept instead */
+    pub superseded: BTreeMap<String, String>,
+    /* Entries retired by `apply-retention`, which the crawler no longer fetches */
+    pub tombstones: BTreeSet<String>,
+    /* Entries whose files `archive` moved away or deleted, which the crawler no longer fetches */
+    pub archived: BTreeMap<String, Archived>,
+    /* Run that first recorded each entry and torrent link */
+    pub introduced: BTreeMap<String, String>,
+    /* Torrent links carrying the same content, across this and other archives */
+    pub sources: Sources,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Fetched {
+    pub at: Option<DateTime<Utc>>,
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Archived {
+    pub at: Option<DateTime<Utc>>,
+    /* Directory the files were moved under, or None when they were deleted */
+    pub to: Option<String>,
+    /* Whether the torrents went along with the HTML */
+    pub torrents: bool,
 }
 
 impl Config {
-    pub fn get_path(base_path: &String) -> Result<PathBuf> {
+    pub fn get_path(base_path: &str) -> Result<PathBuf> {
         let mut path = std::env::current_exe()?;
         path.set_file_name("TORRENTS");
         path.set_extension("JSON");
@@ -28,31 +118,239 @@ impl Config {
         Ok(PathBuf::from(base_path).join(file_name))
     }
 
-    pub fn load(base_path: &String) -> Result<Self> {
+    fn backup_path(path: &Path, index: usize) -> PathBuf {
+        let mut path = path.as_os_str().to_owned();
+        path.push(format!(".{index}"));
+
+        PathBuf::from(path)
+    }
+
+    /* Attributes records seen for the first time to the current run */
+    pub fn introduce<'a>(&mut self, keys: impl IntoIterator<Item = &'a String>) {
+        let Some(run_id) = &self.run_id else {
+            return;
+        };
+
+        for key in keys {
+            self.introduced
+                .entry(key.clone())
+                .or_insert_with(|| run_id.clone());
+        }
+    }
+
+    /* The state as it stood when the last run started, for `diff` */
+    pub fn snapshot_path(base_path: &str) -> Result<PathBuf> {
+        Ok(Self::get_path(base_path)?.with_extension("JSON.PREVIOUS"))
+    }
+
+    /* Falls back to the newest readable backup, so a corrupted file does not wipe the crawl */
+    pub fn load(base_path: &str) -> Result<Self> {
         let path = Self::get_path(base_path)?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
 
-        let mut file = File::open(path)?;
-        let mut text = String::new();
-        file.read_to_string(&mut text)?;
-        file.rewind()?;
+        let backups = (1..=BACKUPS).map(|index| Self::backup_path(&path, index));
+        let mut last_error = None;
+        for candidate in std::iter::once(path.clone()).chain(backups) {
+            if !candidate.exists() {
+                continue;
+            }
 
-        let config = serde_json::from_str(&text)?;
+            match Self::read(&candidate) {
+                Ok(config) => {
+                    if candidate != path {
+                        warn!(path = %candidate.display(), "Recovered state from backup");
+                    }
+
+                    return Ok(config);
+                }
+                Err(error) => {
+                    warn!(path = %candidate.display(), %error, "Failed to read state");
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.unwrap()).context(format!("No readable state at {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /* Upgrades state written by older versions as it goes */
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut value = serde_json::from_str::<Value>(text)?;
+
+        let version = version(&value);
+        if version > VERSION {
+            warn!(
+                version,
+                supported = VERSION,
+                "State was written by a newer version"
+            );
+        }
+        for from in version..VERSION {
+            migrate(&mut value, from)?;
+        }
+
+        let config = serde_json::from_value(value)?;
 
         Ok(config)
     }
 
-    pub fn save(&mut self, base_path: &String) -> Result<()> {
+    /* Writes beside the state file and renames over it, so a crash leaves either the old or the new state */
+    pub fn save(&mut self, base_path: &str) -> Result<()> {
         let path = Self::get_path(base_path)?;
-
-        let mut file = File::options()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(path)?;
+        let temp_path = path.with_extension("JSON.tmp");
+        self.version = self.version.max(VERSION);
 
         let content = serde_json::to_string_pretty(&self)?;
+        let mut file = File::create(&temp_path)?;
         file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &path)?;
 
         Ok(())
     }
+
+    /* Once per run that writes, before its first save, so every backup is the state some earlier run left behind */
+    pub fn rotate(base_path: &str) -> Result<()> {
+        let path = Self::get_path(base_path)?;
+        if !path.exists() {
+            return Ok(());
+        }
+
+        for index in (1..BACKUPS).rev() {
+            let backup_path = Self::backup_path(&path, index);
+            if backup_path.exists() {
+                fs::rename(&backup_path, Self::backup_path(&path, index + 1))?;
+            }
+        }
+        fs::copy(&path, Self::backup_path(&path, 1))?;
+
+        Ok(())
+    }
+
+    pub fn snapshot(&self, base_path: &str) -> Result<()> {
+        let content = serde_json::to_string_pretty(&self)?;
+        fs::write(Self::snapshot_path(base_path)?, content)?;
+
+        Ok(())
+    }
+}
+
+/* The version the raw JSON was written at, 0 for files that predate the field */
+pub fn version(value: &Value) -> u32 {
+    value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32
+}
+
+/* Each step upgrades the raw JSON by one version, before it is deserialized */
+pub fn migrate(value: &mut Value, from: u32) -> Result<()> {
+    let Some(object) = value.as_object_mut() else {
+        bail!("State is not a JSON object")
+    };
+
+    for (field, value) in object.iter_mut() {
+        migrate_field(field, value, from)?;
+    }
+    object.insert("version".to_string(), Value::from(from + 1));
+
+    Ok(())
+}
+
+/* One top-level field's part of the step from version from, so `state migrate` can upgrade the state as it streams past */
+pub fn migrate_field(field: &str, value: &mut Value, from: u32) -> Result<()> {
+    match from {
+        /* Version 0 files predate the field and need nothing else */
+        0 => {}
+        /* Paths are built component by component now, which drops doubled separators and uses the platform's own */
+        1 if ["validators", "statuses", "checksums", "sizes"].contains(&field) => {
+            if let Value::Object(paths) = value {
+                *paths = std::mem::take(paths)
+                    .into_iter()
+                    .map(|(path, value)| (cache::normalize(&path), value))
+                    .collect();
+            }
+        }
+        1 => {}
+        _ => bail!("No migration from state version {from}"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_version_0_state() {
+        let config = Config::parse(r#"{"entries": ["a"], "max_pages": 3}"#).unwrap();
+
+        assert_eq!(config.version, VERSION);
+        assert_eq!(config.entries, ["a"]);
+        assert_eq!(config.max_pages, 3);
+    }
+
+    #[test]
+    fn normalizes_paths_from_version_1() {
+        let path = cache::join("base", &["HTML", "PAGES", "1.HTML"]);
+        let doubled = path.replace(std::path::MAIN_SEPARATOR, "//");
+        let text = format!(
+            r#"{{"version": 1, "statuses": {{"{doubled}": 200}}, "sizes": {{"{doubled}": 9}}}}"#
+        );
+        let config = Config::parse(&text).unwrap();
+
+        assert_eq!(config.statuses.get(&path), Some(&200));
+        assert_eq!(config.sizes.get(&path), Some(&9));
+    }
+
+    #[test]
+    fn migrates_one_version_at_a_time() {
+        let mut value = serde_json::json!({"version": 0});
+        migrate(&mut value, 0).unwrap();
+
+        assert_eq!(version(&value), 1);
+        assert!(migrate(&mut value, VERSION).is_err());
+        assert!(migrate(&mut serde_json::json!([]), 0).is_err());
+    }
+
+    #[test]
+    fn reads_current_state_unchanged() {
+        let config = Config {
+            version: VERSION,
+            entries: vec!["a".to_string()],
+            ..Config::default()
+        };
+        let text = serde_json::to_string(&config).unwrap();
+
+        assert_eq!(Config::parse(&text).unwrap().entries, ["a"]);
+        assert_eq!(version(&serde_json::from_str(&text).unwrap()), VERSION);
+    }
+
+    #[test]
+    fn rotates_once_however_often_a_run_saves() {
+        let base = std::env::temp_dir().join(format!("torrents-rotate-{}", std::process::id()));
+        fs::create_dir_all(&base).unwrap();
+        let base_path = base.to_string_lossy();
+        let path = Config::get_path(&base_path).unwrap();
+        fs::write(&path, r#"{"entries": ["before"]}"#).unwrap();
+
+        Config::rotate(&base_path).unwrap();
+        let mut config = Config::load(&base_path).unwrap();
+        for entry in ["a", "b", "c", "d"] {
+            config.entries.push(entry.to_string());
+            config.save(&base_path).unwrap();
+        }
+        let first = fs::read_to_string(Config::backup_path(&path, 1));
+        let second = Config::backup_path(&path, 2).exists();
+        let saved = Config::load(&base_path);
+        fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(first.unwrap(), r#"{"entries": ["before"]}"#);
+        assert!(!second);
+        assert_eq!(saved.unwrap().entries, ["before", "a", "b", "c", "d"]);
+    }
 }
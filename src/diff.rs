@@ -0,0 +1,95 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::config::Config;
+
+/* Torrent links of an entry present in both states that differ between them */
+#[derive(Debug, Serialize)]
+struct Changed<'a> {
+    entry: &'a str,
+    added: Vec<&'a str>,
+    removed: Vec<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct Diff<'a> {
+    added: Vec<&'a str>,
+    removed: Vec<&'a str>,
+    changed: Vec<Changed<'a>>,
+}
+
+/* Compares the latest crawl against the state the previous run started from */
+pub fn diff(previous: &Config, current: &Config, json: bool) -> Result<()> {
+    let before = previous
+        .entries
+        .iter()
+        .map(String::as_str)
+        .collect::<BTreeSet<_>>();
+    let after = current
+        .entries
+        .iter()
+        .map(String::as_str)
+        .collect::<BTreeSet<_>>();
+
+    let mut changed = Vec::new();
+    for entry in before.intersection(&after) {
+        let (old, new) = (links(previous, entry), links(current, entry));
+        if old == new {
+            continue;
+        }
+
+        changed.push(Changed {
+            entry: *entry,
+            added: new.difference(&old).copied().collect(),
+            removed: old.difference(&new).copied().collect(),
+        });
+    }
+
+    let diff = Diff {
+        added: after.difference(&before).copied().collect(),
+        removed: before.difference(&after).copied().collect(),
+        changed,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    for entry in &diff.added {
+        println!("+ {entry}");
+    }
+    for entry in &diff.removed {
+        println!("- {entry}");
+    }
+    for changed in &diff.changed {
+        println!("~ {}", changed.entry);
+        for url in &changed.added {
+            println!("    + {url}");
+        }
+        for url in &changed.removed {
+            println!("    - {url}");
+        }
+    }
+
+    println!(
+        "{} new, {} removed, {} changed entries",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len()
+    );
+
+    Ok(())
+}
+
+fn links<'a>(config: &'a Config, entry: &str) -> BTreeSet<&'a str> {
+    config
+        .links
+        .get(entry)
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect()
+}
@@ -0,0 +1,295 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use reqwest::{blocking::Client, Url};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{settings::Dns, tls};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/* Record types asked for, IPv4 first */
+const A: u16 = 1;
+const AAAA: u16 = 28;
+
+#[derive(Debug, Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    kind: u16,
+    data: String,
+}
+
+/* Addresses for the hosts of the site and its mirrors, fed to every client; hosts that fail are left to the system resolver */
+pub fn resolve(
+    settings: &Dns,
+    base_urls: &[String],
+    tls: &tls::Policy,
+) -> Result<Vec<(String, SocketAddr)>> {
+    if settings.resolver.is_none() && settings.addresses.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut hosts = match settings.hosts.is_empty() {
+        true => base_urls
+            .iter()
+            .filter_map(|url| Url::parse(url).ok()?.host_str().map(str::to_string))
+            .collect::<Vec<_>>(),
+        false => settings.hosts.clone(),
+    };
+    hosts.extend(settings.addresses.keys().cloned());
+    hosts.sort();
+    hosts.dedup();
+
+    let doh = Client::builder().timeout(TIMEOUT);
+    let doh = tls.apply(doh).build()?;
+
+    let mut resolved = Vec::new();
+    for host in hosts {
+        let address = match settings.addresses.get(&host) {
+            Some(address) => address
+                .parse::<IpAddr>()
+                .with_context(|| format!("Invalid address {address} for {host}"))
+                .map(Some),
+            None => match &settings.resolver {
+                Some(resolver) => lookup(&doh, resolver, &host),
+                None => Ok(None),
+            },
+        };
+
+        match address {
+            Ok(Some(address)) => {
+                info!(host, %address, "Resolved host");
+                resolved.push((host, SocketAddr::new(address, 0)));
+            }
+            Ok(None) => warn!(
+                host,
+                "Resolver has no address for host, using the system resolver"
+            ),
+            Err(error) => warn!(host, %error, "Failed to resolve host, using the system resolver"),
+        }
+    }
+
+    Ok(resolved)
+}
+
+/* DoH when the resolver is an https URL, and plain DNS over UDP to it otherwise */
+fn lookup(doh: &Client, resolver: &str, host: &str) -> Result<Option<IpAddr>> {
+    for kind in [A, AAAA] {
+        let addresses = match resolver.starts_with("https://") {
+            true => query_doh(doh, resolver, host, kind)?,
+            false => query_udp(resolver, host, kind)?,
+        };
+        if let Some(address) = addresses.into_iter().next() {
+            return Ok(Some(address));
+        }
+    }
+
+    Ok(None)
+}
+
+/* The JSON flavour of DoH, which Cloudflare, Google and Quad9 all serve */
+fn query_doh(client: &Client, endpoint: &str, host: &str, kind: u16) -> Result<Vec<IpAddr>> {
+    let response = client
+        .get(endpoint)
+        .query(&[("name", host), ("type", &kind.to_string())])
+        .header("Accept", "application/dns-json")
+        .send()?
+        .error_for_status()?
+        .json::<DohResponse>()?;
+    if response.status != 0 {
+        bail!("Resolver answered with DNS status {}", response.status);
+    }
+
+    let addresses = response
+        .answer
+        .iter()
+        .filter(|answer| answer.kind == kind)
+        .filter_map(|answer| answer.data.parse().ok())
+        .collect();
+
+    Ok(addresses)
+}
+
+/* One question over UDP; the server may be given as "9.9.9.9" or "9.9.9.9:53" */
+fn query_udp(server: &str, host: &str, kind: u16) -> Result<Vec<IpAddr>> {
+    let server = match server.parse::<SocketAddr>() {
+        Ok(server) => server,
+        Err(_error) => SocketAddr::new(server.parse::<IpAddr>()?, 53),
+    };
+
+    let id = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos() as u16;
+    let query = question(id, host, kind)?;
+
+    let bind = match server {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.send_to(&query, server)?;
+
+    let mut response = [0; 1500];
+    let length = socket.recv(&mut response)?;
+
+    answers(&response[..length], id)
+}
+
+/* A query for one record of host, recursion desired */
+fn question(id: u16, host: &str, kind: u16) -> Result<Vec<u8>> {
+    let mut query = Vec::new();
+    query.extend(id.to_be_bytes());
+    query.extend([0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("Invalid host name {host}");
+        }
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(kind.to_be_bytes());
+    query.extend(1u16.to_be_bytes());
+
+    Ok(query)
+}
+
+/* The A and AAAA records of the answer to question id; any other record is skipped */
+fn answers(response: &[u8], id: u16) -> Result<Vec<IpAddr>> {
+    if response.len() < 12 || response[..2] != id.to_be_bytes() {
+        bail!("Resolver sent an answer to another question");
+    }
+    let rcode = response[3] & 0x0f;
+    if rcode != 0 {
+        bail!("Resolver answered with DNS status {rcode}");
+    }
+
+    let count = u16::from_be_bytes([response[6], response[7]]);
+    let mut position = skip_name(response, 12)? + 4;
+    let mut addresses = Vec::new();
+    for _answer in 0..count {
+        position = skip_name(response, position)?;
+        let field = |offset: usize| -> Result<u16> {
+            let bytes = response
+                .get(position + offset..position + offset + 2)
+                .context("Resolver sent a truncated answer")?;
+            Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+        let record = field(0)?;
+        let length = field(8)? as usize;
+        let data = response
+            .get(position + 10..position + 10 + length)
+            .context("Resolver sent a truncated answer")?;
+
+        match (record, data.len()) {
+            (A, 4) => addresses.push(IpAddr::V4(Ipv4Addr::new(
+                data[0], data[1], data[2], data[3],
+            ))),
+            (AAAA, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                addresses.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+        position += 10 + length;
+    }
+
+    Ok(addresses)
+}
+
+/* Position after a name, whether spelled out in labels or ending in a pointer to an earlier one */
+fn skip_name(message: &[u8], mut position: usize) -> Result<usize> {
+    loop {
+        let length = *message
+            .get(position)
+            .context("Resolver sent a truncated answer")?;
+        match length {
+            0 => return Ok(position + 1),
+            length if length & 0xc0 == 0xc0 => return Ok(position + 2),
+            length => position += 1 + length as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* The question for example.com, then the records given, each named by a pointer back to it */
+    fn response(id: u16, rcode: u8, records: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut response = question(id, "example.com", A).unwrap();
+        response[2] = 0x81;
+        response[3] = 0x80 | rcode;
+        response[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for (kind, data) in records {
+            response.extend([0xc0, 12]);
+            response.extend(kind.to_be_bytes());
+            response.extend(1u16.to_be_bytes());
+            response.extend(300u32.to_be_bytes());
+            response.extend((data.len() as u16).to_be_bytes());
+            response.extend(*data);
+        }
+
+        response
+    }
+
+    #[test]
+    fn builds_a_question() {
+        let query = question(0x1234, "example.com.", AAAA).unwrap();
+
+        assert_eq!(&query[..2], &[0x12, 0x34]);
+        assert_eq!(&query[12..25], b"\x07example\x03com\x00");
+        assert_eq!(&query[25..], &[0, 28, 0, 1]);
+        assert!(question(1, "bad..host", A).is_err());
+        assert!(question(1, &"x".repeat(64), A).is_err());
+    }
+
+    #[test]
+    fn reads_a_and_aaaa_records() {
+        let cname = b"\x03www\x00".as_slice();
+        let v4: &[u8] = &[1, 2, 3, 4];
+        let v6 = Ipv6Addr::LOCALHOST.octets();
+        let response = response(7, 0, &[(5, cname), (A, v4), (AAAA, v6.as_slice())]);
+
+        assert_eq!(
+            answers(&response, 7).unwrap(),
+            vec![
+                IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_answers() {
+        assert!(answers(&response(7, 0, &[]), 8).is_err());
+        assert!(answers(&response(7, 3, &[]), 7).is_err());
+        assert!(answers(&[0, 7], 7).is_err());
+
+        let v4: &[u8] = &[1, 2, 3, 4];
+        let mut truncated = response(7, 0, &[(A, v4)]);
+        truncated.truncate(truncated.len() - 2);
+        assert!(answers(&truncated, 7).is_err());
+    }
+
+    #[test]
+    fn skips_names() {
+        let message = b"\x03www\x07example\x03com\x00\xc0\x00\x02";
+
+        assert_eq!(skip_name(message, 0).unwrap(), 17);
+        assert_eq!(skip_name(message, 17).unwrap(), 19);
+        assert!(skip_name(b"\x05ab", 0).is_err());
+    }
+}
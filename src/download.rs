@@ -0,0 +1,935 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::Read,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use crossbeam_queue::{ArrayQueue, SegQueue};
+use kdam::{
+    rayon::{current_num_threads, prelude::*, ThreadPoolBuilder},
+    tqdm, BarExt,
+};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header::{
+        HeaderMap, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        LAST_MODIFIED, STRICT_TRANSPORT_SECURITY,
+    },
+    StatusCode,
+};
+use retry::delay::{jitter, Exponential};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, debug_span, info, warn, Span};
+
+use crate::{
+    adapter::Headers,
+    cache::{self, Compression},
+    events,
+    proxy::{Exit, Health},
+    settings::{Policy, Retry},
+    summary::Tally,
+    throttle::{self, Throttle},
+    tor::{self, Tor},
+    writer::Writer,
+};
+
+pub type File = (String, String);
+
+const DEFAULT_MAX_FILE_SIZE: u64 = 64 << 20;
+
+/* The site refused the exit, as opposed to the request failing in transit */
+#[derive(Debug)]
+pub struct Banned(pub String);
+
+impl fmt::Display for Banned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Banned while requesting {}", self.0)
+    }
+}
+
+impl std::error::Error for Banned {}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DeadLetter {
+    pub url: String,
+    pub attempts: usize,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Validator {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validator {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+
+        let validator = Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+
+        (validator.etag.is_some() || validator.last_modified.is_some()).then_some(validator)
+    }
+
+    fn apply(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        request
+    }
+}
+
+pub struct Downloader {
+    /* Grows when the pool is replenished mid-run; exits leave it by being retired */
+    exits: RwLock<Vec<Arc<Exit>>>,
+    pub tor: Option<Tor>,
+    cooldown: Duration,
+    max_attempts: usize,
+    max_consecutive_failures: usize,
+    workers: Option<usize>,
+    cooldowns: Mutex<HashMap<String, Instant>>,
+    health: Mutex<HashMap<String, Health>>,
+    retired: Mutex<HashSet<String>>,
+    dead_letters: Mutex<Vec<DeadLetter>>,
+    validators: Mutex<BTreeMap<String, Validator>>,
+    statuses: Mutex<BTreeMap<String, u16>>,
+    /* Responses of the current run by HTTP status */
+    responses: Mutex<BTreeMap<u16, u64>>,
+    unchanged: Mutex<HashSet<String>>,
+    fetched: Mutex<HashSet<String>>,
+    upgraded: Mutex<HashSet<String>>,
+    writer: Writer,
+    compression: Compression,
+    pub throttle: Throttle,
+    max_file_size: u64,
+    headers: Headers,
+}
+
+impl Downloader {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        exits: Vec<Exit>,
+        tor: Option<Tor>,
+        cooldown: Duration,
+        retry: &Retry,
+        workers: Option<usize>,
+        validators: BTreeMap<String, Validator>,
+        statuses: BTreeMap<String, u16>,
+        writer: Writer,
+        compression: Compression,
+        throttle: Throttle,
+        max_file_size: Option<u64>,
+        headers: Headers,
+    ) -> Self {
+        Self {
+            exits: RwLock::new(exits.into_iter().map(Arc::new).collect()),
+            tor,
+            cooldown,
+            max_attempts: retry.max_attempts,
+            max_consecutive_failures: retry.max_consecutive_failures,
+            workers,
+            cooldowns: Mutex::new(HashMap::new()),
+            health: Mutex::new(HashMap::new()),
+            retired: Mutex::new(HashSet::new()),
+            dead_letters: Mutex::new(Vec::new()),
+            validators: Mutex::new(validators),
+            statuses: Mutex::new(statuses),
+            responses: Mutex::new(BTreeMap::new()),
+            unchanged: Mutex::new(HashSet::new()),
+            fetched: Mutex::new(HashSet::new()),
+            upgraded: Mutex::new(HashSet::new()),
+            writer,
+            compression,
+            throttle,
+            max_file_size: max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE),
+            headers,
+        }
+    }
+
+    pub fn exits(&self) -> Vec<Arc<Exit>> {
+        self.exits.read().unwrap().clone()
+    }
+
+    /* Adds exits with addresses not yet in the pool, returning how many */
+    pub fn add_exits(&self, exits: Vec<Exit>) -> usize {
+        let mut pool = self.exits.write().unwrap();
+        let mut added = 0;
+        for exit in exits {
+            if pool.iter().all(|known| known.address != exit.address) {
+                pool.push(Arc::new(exit));
+                added += 1;
+            }
+        }
+
+        added
+    }
+
+    pub fn is_retired(&self, exit: &Exit) -> bool {
+        self.retired.lock().unwrap().contains(&exit.address)
+    }
+
+    pub fn health(&self) -> HashMap<String, Health> {
+        self.health.lock().unwrap().clone()
+    }
+
+    pub fn validators(&self) -> BTreeMap<String, Validator> {
+        self.validators.lock().unwrap().clone()
+    }
+
+    pub fn statuses(&self) -> BTreeMap<String, u16> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    pub fn responses(&self) -> BTreeMap<u16, u64> {
+        self.responses.lock().unwrap().clone()
+    }
+
+    /* Whether path holds a good cached copy, which need not be fetched again */
+    pub fn is_cached(&self, path: &str) -> bool {
+        let status = self.statuses.lock().unwrap().get(path).copied();
+
+        cache::is_valid(path, status)
+    }
+
+    /* Whether path was fetched or confirmed unchanged during the current run */
+    pub fn is_fetched(&self, path: &str) -> bool {
+        self.fetched.lock().unwrap().contains(path)
+    }
+
+    /* Whether the server answered 304 for this path during the current run */
+    pub fn is_unchanged(&self, path: &str) -> bool {
+        self.unchanged.lock().unwrap().contains(path)
+    }
+
+    /* Whether host redirected to https or sent HSTS during the current run */
+    pub fn is_upgraded(&self, host: &str) -> bool {
+        self.upgraded.lock().unwrap().contains(host)
+    }
+
+    pub fn save_files(
+        &self,
+        files: Vec<File>,
+        total: usize,
+        text: String,
+        policy: &Policy,
+    ) -> Result<Tally> {
+        let rotation = Rotation::new(self, policy);
+        let exits = self.usable_exits(policy).len();
+        if exits == 0 && total > 0 {
+            warn!("No exit is allowed by the schedule policy");
+        }
+
+        /* Each file carries its attempt count and the exit that last failed it */
+        let queue = ArrayQueue::new(total);
+        let _ = files
+            .into_par_iter()
+            .try_for_each(|msg| queue.push((msg, 0, None)));
+        let succeeded = AtomicUsize::new(0);
+        let failed = AtomicUsize::new(0);
+        let workers = self.workers().min(total.max(1));
+
+        info!(total, workers, "Fetching files");
+        let mut bar = events::bar(total);
+        bar.desc = exits.to_string();
+        bar.write(text)?;
+
+        /* One overall bar, with a status line below it for each worker that is running */
+        let bar = Mutex::new(bar);
+        let slots = Mutex::new(Vec::new());
+
+        let step = Span::current();
+        let pool = ThreadPoolBuilder::new().num_threads(workers).build()?;
+        pool.install(|| {
+            (0..workers).into_par_iter().for_each(|_worker| {
+                let slot = take_slot(&slots);
+                let mut status = tqdm!(
+                    position = slot as u16 + 1,
+                    leave = false,
+                    disable = events::json()
+                );
+
+                while let Some((msg, attempts, failed_on)) = queue.pop() {
+                    let exit = match rotation.next(failed_on.as_deref()) {
+                        Some(Ok(exit)) => exit,
+                        Some(Err(wait)) => {
+                            queue.push((msg, attempts, failed_on)).unwrap();
+                            thread::sleep(wait);
+                            continue;
+                        }
+                        None => {
+                            queue.push((msg, attempts, failed_on)).unwrap();
+                            break;
+                        }
+                    };
+
+                    {
+                        let mut bar = bar.lock().unwrap();
+                        bar.postfix = format!(
+                            "{}/s, {}",
+                            throttle::format_bytes(self.throttle.speed()),
+                            throttle::format_bytes(self.throttle.bytes() as f64)
+                        );
+                        let _ = bar.update_to(total - queue.len());
+                    }
+
+                    status.desc = exit.address.clone();
+                    status.postfix = format!("attempt {} {}", attempts + 1, msg.0);
+                    let _ = status.refresh();
+
+                    let _entered = step.enter();
+                    let result = self.attempt(&exit, &msg, policy);
+                    rotation.record(&exit, result.is_ok());
+                    match result {
+                        Ok(_contents) => {
+                            succeeded.fetch_add(1, Ordering::Relaxed);
+                            let _ = status.update(1);
+                        }
+                        Err(_error) if attempts + 1 < self.max_attempts => {
+                            let failed_on = Some(exit.address.clone());
+                            queue.push((msg, attempts + 1, failed_on)).unwrap();
+                        }
+                        Err(error) => {
+                            self.give_up(msg, attempts + 1, &error);
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+
+                let _ = status.clear();
+                slots.lock().unwrap()[slot] = false;
+            })
+        });
+
+        /* Left over when every exit was retired */
+        while let Some((msg, attempts, _failed_on)) = queue.pop() {
+            self.give_up(msg, attempts, &anyhow!("Every exit was retired"));
+            failed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let unwritten = self.writer.flush();
+
+        Ok(Tally {
+            requested: total,
+            succeeded: succeeded.into_inner().saturating_sub(unwritten),
+            failed: failed.into_inner() + unwritten,
+            skipped: 0,
+        })
+    }
+
+    /* Like save_files, but takes files as they arrive until the sender hangs up, handing each saved one to on_saved */
+    pub fn stream_files(
+        &self,
+        receiver: Receiver<File>,
+        policy: &Policy,
+        on_saved: impl Fn(&File, &str) + Sync,
+    ) -> Tally {
+        const POLL: Duration = Duration::from_millis(100);
+
+        let rotation = Rotation::new(self, policy);
+        if self.usable_exits(policy).is_empty() {
+            warn!("No exit is allowed by the schedule policy");
+        }
+
+        let receiver = Mutex::new(receiver);
+        let retries = SegQueue::new();
+        /* Files taken from the channel and not yet saved or given up on, which may still come back as retries */
+        let in_flight = AtomicUsize::new(0);
+        let requested = AtomicUsize::new(0);
+        let succeeded = AtomicUsize::new(0);
+        let failed = AtomicUsize::new(0);
+
+        let step = Span::current();
+        thread::scope(|scope| {
+            for _worker in 0..self.workers() {
+                scope.spawn(|| loop {
+                    let next = match retries.pop() {
+                        Some(next) => next,
+                        None => match receiver.lock().unwrap().recv_timeout(POLL) {
+                            Ok(msg) => {
+                                requested.fetch_add(1, Ordering::Relaxed);
+                                in_flight.fetch_add(1, Ordering::Relaxed);
+                                (msg, 0, None)
+                            }
+                            Err(RecvTimeoutError::Timeout) => continue,
+                            Err(RecvTimeoutError::Disconnected) => {
+                                if in_flight.load(Ordering::Relaxed) == 0 {
+                                    break;
+                                }
+
+                                thread::sleep(POLL);
+                                continue;
+                            }
+                        },
+                    };
+                    let (msg, attempts, failed_on) = next;
+
+                    let exit = match rotation.next(failed_on.as_deref()) {
+                        Some(Ok(exit)) => exit,
+                        Some(Err(wait)) => {
+                            retries.push((msg, attempts, failed_on));
+                            thread::sleep(wait.min(POLL * 10));
+                            continue;
+                        }
+                        None => {
+                            retries.push((msg, attempts, failed_on));
+                            break;
+                        }
+                    };
+
+                    let _entered = step.enter();
+                    let result = self.attempt(&exit, &msg, policy);
+                    rotation.record(&exit, result.is_ok());
+                    match result {
+                        Ok(contents) => {
+                            succeeded.fetch_add(1, Ordering::Relaxed);
+                            on_saved(&msg, &contents);
+                            in_flight.fetch_sub(1, Ordering::Relaxed);
+                        }
+                        Err(_error) if attempts + 1 < self.max_attempts => {
+                            retries.push((msg, attempts + 1, Some(exit.address.clone())));
+                        }
+                        Err(error) => {
+                            self.give_up(msg, attempts + 1, &error);
+                            failed.fetch_add(1, Ordering::Relaxed);
+                            in_flight.fetch_sub(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        /* Left over when every exit was retired */
+        let receiver = receiver.into_inner().unwrap();
+        let leftovers = std::iter::from_fn(|| retries.pop())
+            .map(|(msg, attempts, _failed_on)| (msg, attempts))
+            .chain(receiver.try_iter().map(|msg| {
+                requested.fetch_add(1, Ordering::Relaxed);
+                (msg, 0)
+            }));
+        for (msg, attempts) in leftovers {
+            self.give_up(msg, attempts, &anyhow!("Every exit was retired"));
+            failed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Tally {
+            requested: requested.into_inner(),
+            succeeded: succeeded.into_inner(),
+            failed: failed.into_inner(),
+            skipped: 0,
+        }
+    }
+
+    /* Fetching threads, shared by however many exits there are */
+    fn workers(&self) -> usize {
+        self.workers.unwrap_or_else(current_num_threads).max(1)
+    }
+
+    /* Allowed by the policy and not retired */
+    fn usable_exits(&self, policy: &Policy) -> Vec<Arc<Exit>> {
+        let retired = self.retired.lock().unwrap();
+
+        self.exits
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|exit| policy.allows(&exit.labels))
+            .filter(|exit| !retired.contains(&exit.address))
+            .cloned()
+            .collect()
+    }
+
+    /* Takes the exit out of rotation for the rest of the run */
+    pub fn retire(&self, exit: &Exit, reason: &str) {
+        if self.retired.lock().unwrap().insert(exit.address.clone()) {
+            warn!(exit = %exit.address, reason, "Retiring exit");
+        }
+    }
+
+    fn give_up(&self, (url, _path): File, attempts: usize, error: &anyhow::Error) {
+        warn!(url, attempts, %error, "Giving up on file");
+
+        let dead_letter = DeadLetter {
+            url,
+            attempts,
+            error: error.to_string(),
+        };
+        self.dead_letters.lock().unwrap().push(dead_letter);
+    }
+
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.dead_letters.lock().unwrap().clone()
+    }
+
+    /* Waits for queued disk writes and returns how many failed */
+    pub fn flush(&self) -> usize {
+        self.writer.flush()
+    }
+
+    /* Allowed exits that are not cooling down, healthiest first */
+    fn ranked_exits(&self, policy: &Policy) -> Vec<Arc<Exit>> {
+        let health = self.health();
+        let score = |exit: &Exit| {
+            health
+                .get(&exit.address)
+                .copied()
+                .unwrap_or_default()
+                .weight(exit.latency)
+        };
+
+        let mut exits = self
+            .usable_exits(policy)
+            .into_iter()
+            .filter(|exit| self.cooling_down(&exit.address).is_none())
+            .collect::<Vec<_>>();
+        exits.sort_by(|a, b| score(b).total_cmp(&score(a)));
+
+        exits
+    }
+
+    /* Tries each exit in turn, healthiest first, until one of them saves the file */
+    pub fn save_file_any(&self, msg: &File, policy: &Policy) -> Result<String> {
+        let mut last_error = None;
+        for exit in self.ranked_exits(policy) {
+            match self.attempt(&exit, msg, policy) {
+                Ok(_contents) if self.writer.flush() > 0 => bail!("Failed to write {}", msg.1),
+                Ok(contents) => return Ok(contents),
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("No exit available to fetch {}", msg.0)))
+    }
+
+    /* Whether url exists, i.e. answers anything but 404, asking each exit in turn until one gets an answer */
+    pub fn probe(&self, url: &str, policy: &Policy) -> Result<bool> {
+        let headers = self.headers.render_with(url, &policy.headers)?;
+
+        let mut last_error = None;
+        for exit in self.ranked_exits(policy) {
+            let response = exit.client.get(url).headers(headers.clone()).send();
+            match response.map(|response| response.status()) {
+                Ok(StatusCode::NOT_FOUND) => return Ok(false),
+                Ok(status) if status.is_success() => return Ok(true),
+                Ok(status) => last_error = Some(anyhow!("{url} answered {status}")),
+                Err(error) => last_error = Some(error.into()),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("No exit available to probe {url}")))
+    }
+
+    /* Size url announces in a HEAD response, asking each exit in turn until one gets an answer; read from the header, as a HEAD body is always empty */
+    pub fn content_length(&self, url: &str, policy: &Policy) -> Result<Option<u64>> {
+        let headers = self.headers.render_with(url, &policy.headers)?;
+
+        let mut last_error = None;
+        for exit in self.ranked_exits(policy) {
+            let response = exit.client.head(url).headers(headers.clone()).send();
+            match response.and_then(|response| response.error_for_status()) {
+                Ok(response) => {
+                    let length = response.headers().get(CONTENT_LENGTH);
+                    let length = length.and_then(|length| length.to_str().ok()?.parse().ok());
+                    return Ok(length);
+                }
+                Err(error) => last_error = Some(error.into()),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("No exit available to ask {url} for its size")))
+    }
+
+    /* One request through one exit, with its outcome counted against the exit */
+    fn attempt(&self, exit: &Exit, msg: &File, policy: &Policy) -> Result<String> {
+        let span = debug_span!("request", url = %msg.0, exit = %exit.address);
+        let _entered = span.enter();
+
+        let start = Instant::now();
+        let result = self.save_file(&exit.client, msg, policy);
+        let duration = start.elapsed();
+        let duration_ms = duration.as_millis() as u64;
+
+        let timed_out = match &result {
+            Ok(contents) => {
+                debug!(duration_ms, bytes = contents.len(), "Saved file");
+                false
+            }
+            Err(error) => {
+                warn!(duration_ms, %error, "Failed to save file");
+
+                /* 403s, 429s and challenges */
+                if error.is::<Banned>() {
+                    self.cool_down(&exit.address);
+                }
+
+                error
+                    .downcast_ref::<reqwest::Error>()
+                    .is_some_and(reqwest::Error::is_timeout)
+            }
+        };
+
+        let mut health = self.health.lock().unwrap();
+        let health = health.entry(exit.address.clone()).or_default();
+        health.record(result.is_ok(), timed_out, duration);
+
+        result
+    }
+
+    /* Bans apply to the exit address, so every alias sharing it backs off together */
+    fn cool_down(&self, address: &str) {
+        let until = Instant::now() + self.cooldown;
+        self.cooldowns
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), until);
+    }
+
+    fn cooling_down(&self, address: &str) -> Option<Duration> {
+        let cooldowns = self.cooldowns.lock().unwrap();
+        let until = cooldowns.get(address)?;
+
+        until.checked_duration_since(Instant::now())
+    }
+
+    pub fn save_file(
+        &self,
+        client: &Client,
+        (url, path): &File,
+        policy: &Policy,
+    ) -> Result<String> {
+        let body = self.get_body(client, url, path, policy)?;
+        self.fetched.lock().unwrap().insert(path.clone());
+
+        let Some(body) = body else {
+            self.unchanged.lock().unwrap().insert(path.clone());
+            return Ok(cache::read_to_string(path)?);
+        };
+
+        /* Only the HTML cache is compressed, torrents and media are kept byte for byte as served */
+        let compression = match path.ends_with(".HTML") {
+            true => self.compression,
+            false => Compression::None,
+        };
+        let contents = String::from_utf8_lossy(&body).into_owned();
+        let (target, bytes) = cache::encode(path, body, compression)?;
+        cache::discard_other(path, compression);
+        self.writer.write(target, bytes);
+
+        Ok(contents)
+    }
+
+    /* Returns None when the server reports the cached copy at path is still current */
+    fn get_body(
+        &self,
+        client: &Client,
+        url: &str,
+        path: &str,
+        policy: &Policy,
+    ) -> Result<Option<Vec<u8>>> {
+        /* A bad cached copy must not be revalidated, or a 304 would keep it */
+        let validator = match self.is_cached(path) {
+            true => self.validators.lock().unwrap().get(path).cloned(),
+            false => None,
+        };
+
+        let headers = self.headers.render_with(url, &policy.headers)?;
+        let iterable = Exponential::from_millis(100).map(jitter).take(10);
+        let operation = |_| {
+            let request = client.get(url).headers(headers.clone());
+            let request = match &validator {
+                Some(validator) => validator.apply(request),
+                None => request,
+            };
+
+            request.send()
+        };
+        let response = retry::retry_with_index(iterable, operation).map_err(|e| e.error)?;
+        let status = response.status();
+        debug!(status = status.as_u16(), "Received response");
+        *self
+            .responses
+            .lock()
+            .unwrap()
+            .entry(status.as_u16())
+            .or_default() += 1;
+
+        match status {
+            StatusCode::NOT_MODIFIED => return Ok(None),
+            StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS => {
+                bail!(Banned(url.to_string()))
+            }
+            _ => {}
+        }
+
+        self.statuses
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), status.as_u16());
+        /* Error bodies are never cached, or they would count as fetched */
+        if !status.is_success() {
+            bail!("{url} answered {status}");
+        }
+
+        let final_url = response.url();
+        let hsts = response.headers().contains_key(STRICT_TRANSPORT_SECURITY);
+        if final_url.scheme() == "https" && (url.starts_with("http://") || hsts) {
+            if let Some(host) = final_url.host_str() {
+                self.upgraded.lock().unwrap().insert(host.to_string());
+            }
+        }
+
+        /* A login or error page served in place of a torrent must not be saved as one */
+        let torrent = path.ends_with(".TORRENT");
+        let content_type = response.headers().get(CONTENT_TYPE);
+        let content_type = content_type.and_then(|value| value.to_str().ok());
+        if torrent && content_type.is_some_and(|content_type| content_type.contains("html")) {
+            bail!(
+                "{url} served {} instead of a torrent",
+                content_type.unwrap_or_default()
+            );
+        }
+
+        let validator = Validator::from_headers(response.headers());
+        let body = self.read_body(url, response)?;
+
+        if is_challenge(&String::from_utf8_lossy(&body)) {
+            bail!(Banned(url.to_string()));
+        }
+        /* Every torrent is a bencoded dictionary */
+        if torrent && !body.starts_with(b"d") {
+            bail!("{url} served something other than a torrent");
+        }
+
+        if let Some(validator) = validator {
+            self.validators
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), validator);
+        }
+
+        Ok(Some(body))
+    }
+
+    /* Reads in chunks so the throttle can pace every worker while bodies are still arriving, and so an endless body is cut off */
+    fn read_body(&self, url: &str, mut response: Response) -> Result<Vec<u8>> {
+        let max = self.max_file_size;
+        if let Some(length) = response.content_length().filter(|length| *length > max) {
+            bail!("{url} is {length} bytes, over the limit of {max}");
+        }
+
+        let mut body = Vec::new();
+        let mut chunk = [0; 64 * 1024];
+        loop {
+            let read = response.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+
+            body.extend_from_slice(&chunk[..read]);
+            self.throttle.take(read);
+            if body.len() as u64 > max {
+                bail!("{url} sent more than the limit of {max} bytes");
+            }
+        }
+
+        Ok(body)
+    }
+}
+
+/* Smooth weighted round-robin: adds every weight to its address's running score, takes the total off the highest and returns its index */
+fn smooth_pick(current: &mut HashMap<String, f64>, weights: &[(&str, f64)]) -> Option<usize> {
+    let mut total = 0.0;
+    let mut best = None;
+    for (index, (address, weight)) in weights.iter().enumerate() {
+        let current = current.entry(address.to_string()).or_default();
+        *current += weight;
+        total += weight;
+
+        if best.is_none_or(|(_index, best)| *current > best) {
+            best = Some((index, *current));
+        }
+    }
+
+    let (index, _current) = best?;
+    *current.get_mut(weights[index].0).unwrap() -= total;
+
+    Some(index)
+}
+
+/* Picks an exit for each request by health, skipping retired ones and those cooling down */
+struct Rotation<'a> {
+    downloader: &'a Downloader,
+    policy: &'a Policy,
+    /* Running scores of the smooth weighted round-robin, by exit address */
+    current: Mutex<HashMap<String, f64>>,
+    /* Consecutive failures by exit address */
+    failures: Mutex<HashMap<String, usize>>,
+}
+
+impl<'a> Rotation<'a> {
+    fn new(downloader: &'a Downloader, policy: &'a Policy) -> Self {
+        Self {
+            downloader,
+            policy,
+            current: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /* The next ready exit, avoiding the one that last failed the file while another is ready; otherwise how long until one is, or None once all are retired */
+    fn next(&self, avoid: Option<&str>) -> Option<Result<Arc<Exit>, Duration>> {
+        /* Looked up on every pick, so exits added mid-run join in */
+        let live = self.downloader.usable_exits(self.policy);
+        if live.is_empty() {
+            return None;
+        }
+
+        let mut wait = Duration::MAX;
+        let mut ready = Vec::new();
+        let mut fallback = None;
+        for exit in live {
+            match self.downloader.cooling_down(&exit.address) {
+                Some(remaining) => wait = wait.min(remaining),
+                None if avoid == Some(exit.address.as_str()) => fallback = Some(exit),
+                None => ready.push(exit),
+            }
+        }
+        if ready.is_empty() {
+            return Some(fallback.ok_or(wait));
+        }
+
+        let health = self.downloader.health.lock().unwrap();
+        let weights = ready
+            .iter()
+            .map(|exit| {
+                let health = health.get(&exit.address).copied().unwrap_or_default();
+                (exit.address.as_str(), health.weight(exit.latency))
+            })
+            .collect::<Vec<_>>();
+        let index = smooth_pick(&mut self.current.lock().unwrap(), &weights)?;
+
+        Some(Ok(Arc::clone(&ready[index])))
+    }
+
+    /* Retires an exit after too many failures in a row, or asks Tor for a new identity instead */
+    fn record(&self, exit: &Exit, saved: bool) {
+        let failures = {
+            let mut failures = self.failures.lock().unwrap();
+            let count = failures.entry(exit.address.clone()).or_default();
+            *count = if saved { 0 } else { *count + 1 };
+            *count
+        };
+
+        match &self.downloader.tor {
+            Some(tor) if failures >= tor::MAX_FAILURES => {
+                self.failures.lock().unwrap().remove(&exit.address);
+                if let Err(error) = tor.new_identity() {
+                    warn!(%error, "Failed to request a new Tor identity");
+                }
+            }
+            None if failures >= self.downloader.max_consecutive_failures => {
+                let reason = format!("{failures} consecutive failures");
+                self.downloader.retire(exit, &reason)
+            }
+            _ => {}
+        }
+    }
+}
+
+/* The lowest status line no running worker is using */
+fn take_slot(slots: &Mutex<Vec<bool>>) -> usize {
+    let mut slots = slots.lock().unwrap();
+    let slot = match slots.iter().position(|used| !used) {
+        Some(slot) => slot,
+        None => {
+            slots.push(false);
+            slots.len() - 1
+        }
+    };
+    slots[slot] = true;
+
+    slot
+}
+
+/* Cloudflare interstitials and captcha walls come back as normal responses */
+pub fn is_challenge(text: &str) -> bool {
+    /* Only an interstitial carries these */
+    const INTERSTITIAL: [&str; 4] = [
+        "cf-browser-verification",
+        "cf_chl_",
+        "<title>Just a moment...</title>",
+        "Attention Required! | Cloudflare",
+    ];
+    /* Ordinary pages carry these too, in a comment form or a script tag, so they only count on a page too small to hold an entry */
+    const WALL: [&str; 4] = [
+        "Please enable cookies",
+        "g-recaptcha",
+        "h-captcha",
+        "challenge-platform",
+    ];
+    const MAX_WALL: usize = 32 << 10;
+
+    INTERSTITIAL.iter().any(|marker| text.contains(marker))
+        || (text.len() <= MAX_WALL && WALL.iter().any(|marker| text.contains(marker)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_exits_smoothly_by_weight() {
+        let weights = [("a", 5.0), ("b", 1.0), ("c", 1.0)];
+        let mut current = HashMap::new();
+        let picks = (0..7)
+            .map(|_pick| weights[smooth_pick(&mut current, &weights).unwrap()].0)
+            .collect::<Vec<_>>();
+
+        /* Every exit gets its share of a round, without the heavy one taking its turns in a row */
+        assert_eq!(picks, ["a", "a", "b", "a", "c", "a", "a"]);
+        assert!(current.values().all(|score| score.abs() < 1e-9));
+    }
+
+    #[test]
+    fn picks_nothing_without_exits() {
+        assert_eq!(smooth_pick(&mut HashMap::new(), &[]), None);
+    }
+
+    #[test]
+    fn tells_challenge_pages_from_ordinary_ones() {
+        let entry = "<p>Description</p>".repeat(4 << 10);
+
+        assert!(is_challenge("<title>Just a moment...</title>"));
+        assert!(is_challenge(
+            "<form id=\"challenge-form\" action=\"/?__cf_chl_tk=x\">"
+        ));
+        assert!(is_challenge("<p>Please enable cookies.</p>"));
+        assert!(is_challenge("<div class=\"g-recaptcha\"></div>"));
+        assert!(!is_challenge(&format!(
+            "{entry}<div class=\"g-recaptcha\"></div>"
+        )));
+        assert!(!is_challenge(&entry));
+    }
+}
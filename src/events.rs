@@ -0,0 +1,154 @@
+use std::{
+    fmt::Debug,
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use chrono::Utc;
+use clap::ValueEnum;
+use kdam::Bar;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::Attributes,
+    Event, Id, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    /* Newline delimited JSON events on stdout, for wrappers that track the run */
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/* Whether stdout carries events, in which case it must carry nothing else */
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/* Human output of the pipeline, which becomes a message event in JSON mode */
+macro_rules! say {
+    ($($arg:tt)*) => {
+        $crate::events::message(format!($($arg)*))
+    };
+}
+pub(crate) use say;
+
+pub fn message(text: String) {
+    match json() {
+        true => emit(json!({ "event": "message", "text": text })),
+        false => println!("{text}"),
+    }
+}
+
+/* Progress bars stay on stderr, but are hidden in JSON mode all the same */
+pub fn bar(total: usize) -> Bar {
+    let mut bar = Bar::new(total);
+    bar.disable = json();
+
+    bar
+}
+
+/* Tracing events worth an output event, and the name each goes out under */
+const EVENTS: [(&str, &str); 5] = [
+    ("Starting step", "step_started"),
+    ("Saved file", "file_downloaded"),
+    ("Giving up on file", "file_failed"),
+    ("Finished step", "step_finished"),
+    ("Finished run", "run_finished"),
+];
+
+/* Fields of a span or event, as JSON */
+#[derive(Debug, Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
+
+/* Turns the pipeline's own tracing events into output events, so nothing is threaded through for them */
+pub struct EventLayer;
+
+/* Switches stdout over to events for the rest of the process */
+pub fn start() -> EventLayer {
+    JSON.store(true, Ordering::Relaxed);
+
+    EventLayer
+}
+
+impl<S> Layer<S> for EventLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let message = fields.0.remove("message");
+        let message = message.as_ref().and_then(Value::as_str);
+        let Some((_message, name)) = EVENTS.iter().find(|(known, _name)| Some(*known) == message)
+        else {
+            return;
+        };
+
+        /* The run id, step, url and exit come from the spans the event happened in */
+        let mut object = Map::new();
+        object.insert("event".to_string(), (*name).into());
+        object.insert("at".to_string(), Utc::now().to_rfc3339().into());
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<Fields>() {
+                    object.extend(span_fields.0.clone());
+                }
+            }
+        }
+        object.extend(fields.0);
+
+        emit(Value::Object(object));
+    }
+}
+
+/* One line per event, written under the stdout lock so lines from different threads never interleave */
+fn emit(event: Value) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{event}");
+    let _ = stdout.flush();
+}
@@ -0,0 +1,165 @@
+use std::{
+    cmp::Reverse,
+    fs::{self, File},
+    io::{BufWriter, Write},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{adapter::Adapter, cache, config::Config};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Csv,
+    Jsonl,
+    Rss,
+}
+
+/* One line per torrent link, or per entry when it has none */
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    url: String,
+    title: &'a str,
+    date: Option<DateTime<Utc>>,
+    torrent: Option<&'a str>,
+    path: Option<String>,
+    infohash: Option<&'a str>,
+}
+
+pub fn export(
+    adapter: &Adapter,
+    config: &Config,
+    html_path: &str,
+    torrents_path: &str,
+    format: Format,
+    output: &str,
+) -> Result<()> {
+    let mut rows = Vec::new();
+    for entry in &config.entries {
+        let url = adapter.entry_url(entry);
+        let title = config
+            .metadata
+            .get(entry)
+            .map_or("", |metadata| metadata.title.as_str());
+
+        /* The site shows no dates, so the entry is dated by when it was first saved */
+        let date = cache::metadata(&adapter.entry_path(html_path, entry))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(DateTime::<Utc>::from);
+
+        let links = config.links.get(entry).filter(|links| !links.is_empty());
+        let Some(links) = links else {
+            rows.push(Row {
+                url,
+                title,
+                date,
+                torrent: None,
+                path: None,
+                infohash: None,
+            });
+            continue;
+        };
+
+        for torrent in links {
+            rows.push(Row {
+                url: url.clone(),
+                title,
+                date,
+                torrent: Some(torrent),
+                path: adapter.torrent_path(torrents_path, torrent),
+                infohash: config.infohashes.get(torrent).map(String::as_str),
+            });
+        }
+    }
+
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_path(output)?;
+            for row in &rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        Format::Jsonl => {
+            let mut writer = BufWriter::new(File::create(output)?);
+            for row in &rows {
+                serde_json::to_writer(&mut writer, row)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+        Format::Rss => write_rss(adapter, &rows, output)?,
+    }
+
+    println!("Exported {} rows to {output}", rows.len());
+
+    Ok(())
+}
+
+/* Items carry the torrent as an enclosure, which is what *arr-style indexer feeds expect */
+fn write_rss(adapter: &Adapter, rows: &[Row], output: &str) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(writer, r#"<rss version="2.0">"#)?;
+    writeln!(writer, "<channel>")?;
+    writeln!(writer, "<title>{}</title>", escape(adapter.name))?;
+    writeln!(writer, "<link>{}</link>", escape(&adapter.base_url))?;
+    writeln!(
+        writer,
+        "<description>Torrents archived from {}</description>",
+        escape(&adapter.base_url)
+    )?;
+
+    let mut items = rows
+        .iter()
+        .filter(|row| row.torrent.is_some())
+        .collect::<Vec<_>>();
+    items.sort_by_key(|row| Reverse(row.date));
+
+    for row in items {
+        let torrent = row.torrent.unwrap_or_default();
+        let guid = row.infohash.unwrap_or(torrent);
+        let length = row
+            .path
+            .as_ref()
+            .and_then(|path| fs::metadata(path).ok())
+            .map_or(0, |metadata| metadata.len());
+
+        writeln!(writer, "<item>")?;
+        writeln!(writer, "<title>{}</title>", escape(row.title))?;
+        writeln!(writer, "<link>{}</link>", escape(&row.url))?;
+        writeln!(
+            writer,
+            r#"<guid isPermaLink="false">{}</guid>"#,
+            escape(guid)
+        )?;
+        if let Some(date) = row.date {
+            writeln!(writer, "<pubDate>{}</pubDate>", date.to_rfc2822())?;
+        }
+        writeln!(
+            writer,
+            r#"<enclosure url="{}" length="{length}" type="application/x-bittorrent"/>"#,
+            escape(torrent)
+        )?;
+        writeln!(writer, "</item>")?;
+    }
+
+    writeln!(writer, "</channel>")?;
+    writeln!(writer, "</rss>")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,106 @@
+use anyhow::{bail, Result};
+use clap::{Arg, CommandFactory};
+
+use crate::Args;
+
+/* The flags given before the subcommand, for the child runs of sync and watch, less the args named in drop and their values; walked by the definitions clap parsed them with, so a value spelled like the subcommand is never taken for it */
+pub fn leading(arguments: &[String], drop: &[&str]) -> Result<Vec<String>> {
+    let mut command = Args::command();
+    command.build();
+
+    let mut leading = Vec::new();
+    let mut arguments = arguments.iter().peekable();
+    while let Some(argument) = arguments.next() {
+        let (flag, mut inline) = match argument.split_once('=') {
+            Some((flag, _value)) => (flag, true),
+            None => (argument.as_str(), false),
+        };
+        let arg = match (flag.strip_prefix("--"), flag.strip_prefix('-')) {
+            (Some(long), _) => command.get_arguments().find(|arg| is_long(arg, long)),
+            (None, Some(short)) => {
+                /* -b/data carries its value, -vv only repeats the flag */
+                inline |= short.len() > 1;
+                let short = short.chars().next();
+                command
+                    .get_arguments()
+                    .find(|arg| arg.get_short().is_some_and(|c| Some(c) == short))
+            }
+            (None, None) if command.find_subcommand(argument).is_some() => return Ok(leading),
+            (None, None) => bail!("Expected a subcommand, found {argument}"),
+        };
+        let Some(arg) = arg else {
+            bail!("Unknown argument {argument}");
+        };
+
+        let mut taken = vec![argument.clone()];
+        if arg.get_action().takes_values() && !inline {
+            let max = arg.get_num_args().map_or(1, |range| range.max_values());
+            while taken.len() <= max {
+                match arguments.next_if(|next| !next.starts_with('-')) {
+                    Some(value) => taken.push(value.clone()),
+                    None => break,
+                }
+            }
+        }
+
+        if !drop.contains(&arg.get_id().as_str()) {
+            leading.extend(taken);
+        }
+    }
+
+    bail!("Found no subcommand in the arguments")
+}
+
+fn is_long(arg: &Arg, long: &str) -> bool {
+    arg.get_long_and_visible_aliases()
+        .is_some_and(|names| names.contains(&long))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn keeps_the_flags_before_the_subcommand() {
+        let given = arguments("-b /data --profile old -vv --direct sync --all");
+
+        assert_eq!(
+            leading(&given, &["profile"]).unwrap(),
+            arguments("-b /data -vv --direct")
+        );
+        assert_eq!(
+            leading(&arguments("--profile=old -b/data watch"), &["profile"]).unwrap(),
+            arguments("-b/data")
+        );
+    }
+
+    #[test]
+    fn takes_values_spelled_like_subcommands_as_values() {
+        let given = arguments("--profile sync --base-path watch sync");
+
+        assert_eq!(
+            leading(&given, &[]).unwrap(),
+            arguments("--profile sync --base-path watch")
+        );
+    }
+
+    #[test]
+    fn drops_every_value_of_a_dropped_arg() {
+        let given = arguments("-p a.txt b.txt --direct --proxies-path=c.txt sync");
+
+        assert_eq!(
+            leading(&given, &["proxies_path"]).unwrap(),
+            arguments("--direct")
+        );
+        assert_eq!(leading(&given, &[]).unwrap(), given[..5]);
+    }
+
+    #[test]
+    fn needs_a_subcommand() {
+        assert!(leading(&arguments("-b /data"), &[]).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::xxh3_128;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    Sha256,
+    #[default]
+    Blake3,
+    /* Not cryptographic, only fit for spotting duplicates */
+    Xxh3,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+            Self::Xxh3 => "xxh3",
+        }
+    }
+
+    /* Checksums carry their algorithm as a prefix, e.g. "blake3:af13..." */
+    pub fn digest(&self, bytes: &[u8]) -> String {
+        let hex = match self {
+            Self::Sha256 => format!("{:x}", Sha256::digest(bytes)),
+            Self::Blake3 => blake3::hash(bytes).to_hex().to_string(),
+            Self::Xxh3 => format!("{:032x}", xxh3_128(bytes)),
+        };
+
+        format!("{}:{hex}", self.name())
+    }
+
+    /* Manifests are named for the tool that checks them: b3sum, sha256sum or xxh128sum */
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "b3",
+            Self::Xxh3 => "xxh128",
+        }
+    }
+
+    pub fn of(checksum: &str) -> Option<Self> {
+        let (name, _hex) = checksum.split_once(':')?;
+
+        Self::value_variants()
+            .iter()
+            .find(|algorithm| algorithm.name() == name)
+            .copied()
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    proxy::Health,
+    summary::{Summary, Tally},
+};
+
+/* One step of a past run */
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StepRecord {
+    pub step: usize,
+    pub seconds: f64,
+    #[serde(flatten)]
+    pub tally: Tally,
+    /* Items that succeeded per second */
+    pub throughput: f64,
+}
+
+/* One line of the history file for every finished run */
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RunRecord {
+    pub run_id: String,
+    pub started: Option<DateTime<Utc>>,
+    pub finished: Option<DateTime<Utc>>,
+    pub steps: Vec<StepRecord>,
+    pub total: Tally,
+    /* Exits that served requests, and how those requests went */
+    pub exits: usize,
+    pub successes: usize,
+    pub failures: usize,
+    pub timeouts: usize,
+    /* Mean of the exits' recent latencies, in milliseconds */
+    pub latency: Option<f64>,
+    pub transferred: u64,
+}
+
+impl RunRecord {
+    pub fn new(
+        run_id: &str,
+        started: DateTime<Utc>,
+        summary: &Summary,
+        health: &[(String, Health)],
+        transferred: u64,
+    ) -> Self {
+        /* Streaming records several steps at once, so steps are keyed by number and summed */
+        let mut steps = BTreeMap::<usize, StepRecord>::new();
+        for (step, duration) in summary.timings() {
+            let record = steps.entry(*step).or_default();
+            record.step = *step;
+            record.seconds += duration.as_secs_f64();
+        }
+        for (step, _name, tally) in summary.steps() {
+            let record = steps.entry(*step).or_default();
+            record.step = *step;
+            record.tally += *tally;
+        }
+        for record in steps.values_mut() {
+            if record.seconds > 0.0 {
+                record.throughput = record.tally.succeeded as f64 / record.seconds;
+            }
+        }
+
+        let latencies = health
+            .iter()
+            .filter_map(|(_address, health)| health.recent_latency)
+            .map(|latency| latency.as_secs_f64() * 1000.0)
+            .collect::<Vec<_>>();
+        let latency =
+            (!latencies.is_empty()).then(|| latencies.iter().sum::<f64>() / latencies.len() as f64);
+
+        Self {
+            run_id: run_id.to_string(),
+            started: Some(started),
+            finished: Some(Utc::now()),
+            steps: steps.into_values().collect(),
+            total: summary.total(),
+            exits: health.len(),
+            successes: health.iter().map(|(_address, h)| h.successes).sum(),
+            failures: health.iter().map(|(_address, h)| h.failures).sum(),
+            timeouts: health.iter().map(|(_address, h)| h.timeouts).sum(),
+            latency,
+            transferred,
+        }
+    }
+
+    pub fn seconds(&self) -> f64 {
+        match (self.started, self.finished) {
+            (Some(started), Some(finished)) => {
+                (finished - started).num_milliseconds() as f64 / 1000.0
+            }
+            _ => 0.0,
+        }
+    }
+
+    /* Share of requests that saved their file, in percent */
+    fn success_rate(&self) -> Option<f64> {
+        let requests = self.successes + self.failures + self.timeouts;
+
+        (requests > 0).then(|| self.successes as f64 * 100.0 / requests as f64)
+    }
+}
+
+/* Kept next to the state as TORRENTS.HISTORY.JSONL, one run per line */
+pub fn path(base_path: &str) -> Result<PathBuf> {
+    Ok(Config::get_path(base_path)?.with_extension("HISTORY.JSONL"))
+}
+
+/* Appends, so the file only grows by a line however long the history gets */
+pub fn record(base_path: &str, run: &RunRecord) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path(base_path)?)?;
+    writeln!(file, "{}", serde_json::to_string(run)?)?;
+
+    Ok(())
+}
+
+/* Oldest first; lines that do not parse are skipped, so one bad line never hides the rest */
+pub fn load(base_path: &str) -> Result<Vec<RunRecord>> {
+    let path = path(base_path)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let runs = fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+
+    Ok(runs)
+}
+
+/* The last runs, with each step's seconds, or with step the items and throughput of that step alone */
+pub fn print(base_path: &str, limit: usize, step: Option<usize>, json: bool) -> Result<()> {
+    let runs = load(base_path)?;
+    let runs = &runs[runs.len().saturating_sub(limit)..];
+
+    if json {
+        for run in runs {
+            println!("{}", serde_json::to_string(run)?);
+        }
+        return Ok(());
+    }
+
+    let started = |run: &RunRecord| {
+        run.started
+            .map(|started| started.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default()
+    };
+
+    if let Some(step) = step {
+        println!(
+            "{:<16}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}",
+            "STARTED", "SECONDS", "REQUESTED", "SUCCEEDED", "FAILED", "PER SEC"
+        );
+        for run in runs {
+            let Some(record) = run.steps.iter().find(|record| record.step == step) else {
+                continue;
+            };
+            println!(
+                "{:<16}  {:>9.1}  {:>9}  {:>9}  {:>9}  {:>9.2}",
+                started(run),
+                record.seconds,
+                record.tally.requested,
+                record.tally.succeeded,
+                record.tally.failed,
+                record.throughput
+            );
+        }
+        return Ok(());
+    }
+
+    println!(
+        "{:<16}  {:>9}  {:>9}  {:>9}  {:>5}  {:>7}  {:>9}  STEP SECONDS",
+        "STARTED", "SECONDS", "SUCCEEDED", "FAILED", "EXITS", "OK %", "LATENCY"
+    );
+    for run in runs {
+        let success_rate = run
+            .success_rate()
+            .map_or("-".to_string(), |rate| format!("{rate:.1}"));
+        let latency = run
+            .latency
+            .map_or("-".to_string(), |latency| format!("{latency:.0}ms"));
+        let steps = run
+            .steps
+            .iter()
+            .map(|record| format!("{}:{:.0}", record.step, record.seconds))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{:<16}  {:>9.0}  {:>9}  {:>9}  {:>5}  {:>7}  {:>9}  {steps}",
+            started(run),
+            run.seconds(),
+            run.total.succeeded,
+            run.total.failed,
+            run.exits,
+            success_rate,
+            latency
+        );
+    }
+    println!("Showing the last {} runs", runs.len());
+
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use std::{fs, path::Path, process::Command};
+
+use anyhow::{bail, Context, Result};
+use kdam::rayon::{prelude::*, ThreadPoolBuilder};
+use tracing::{info, warn};
+
+use crate::{metadata::Metadata, settings::Hooks};
+
+/* A torrent step 7 saved, with what is known of the entry it came from */
+pub struct Saved<'a> {
+    pub url: &'a str,
+    pub path: &'a str,
+    pub entry: Option<&'a str>,
+    pub metadata: Option<&'a Metadata>,
+}
+
+impl Saved<'_> {
+    /* Upper-cased with a TORRENTS_ prefix, as environment variables of the commands */
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let metadata = self.metadata.cloned().unwrap_or_default();
+
+        vec![
+            ("url", self.url.to_string()),
+            ("path", self.path.to_string()),
+            ("entry", self.entry.unwrap_or_default().to_string()),
+            ("title", metadata.title),
+            ("category", format!("{:?}", metadata.kind).to_lowercase()),
+            ("tags", metadata.tags.join(",")),
+            ("language", metadata.language.unwrap_or_default()),
+        ]
+    }
+}
+
+/* Runs the built-in actions and then each command on every saved torrent; failures are logged and never fail the run */
+pub fn run(hooks: &Hooks, saved: &[Saved]) -> Result<()> {
+    let actions = !hooks.commands.is_empty() || hooks.copy_to.is_some() || hooks.mode.is_some();
+    if saved.is_empty() || !actions {
+        return Ok(());
+    }
+
+    let mode = hooks.mode.as_deref().map(parse_mode).transpose()?;
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(hooks.concurrency.max(1))
+        .build()?;
+    let failed = pool.install(|| {
+        saved
+            .par_iter()
+            .map(|torrent| {
+                let mut failed = 0;
+
+                if let Some(mode) = mode {
+                    if let Err(error) = set_mode(torrent.path, mode) {
+                        warn!(path = torrent.path, %error, "Failed to set permissions");
+                        failed += 1;
+                    }
+                }
+
+                if let Some(directory) = &hooks.copy_to {
+                    if let Err(error) = copy(torrent.path, directory) {
+                        warn!(path = torrent.path, directory, %error, "Failed to copy torrent");
+                        failed += 1;
+                    }
+                }
+
+                for command in &hooks.commands {
+                    if let Err(error) = execute(command, torrent) {
+                        warn!(path = torrent.path, command, %error, "Failed to run hook");
+                        failed += 1;
+                    }
+                }
+
+                failed
+            })
+            .sum::<usize>()
+    });
+
+    info!(torrents = saved.len(), failed, "Ran hooks");
+
+    Ok(())
+}
+
+/* Permissions as octal digits, e.g. "644" */
+fn parse_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .with_context(|| format!("Invalid hook mode {mode}, expected octal digits such as 644"))
+}
+
+#[cfg(unix)]
+fn set_mode(path: &str, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+    Ok(())
+}
+
+/* Only the read-only bit means anything elsewhere */
+#[cfg(not(unix))]
+fn set_mode(path: &str, mode: u32) -> Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)?;
+
+    Ok(())
+}
+
+fn copy(path: &str, directory: &str) -> Result<()> {
+    let name = Path::new(path)
+        .file_name()
+        .with_context(|| format!("{path} has no file name"))?;
+
+    fs::create_dir_all(directory)?;
+    fs::copy(path, Path::new(directory).join(name))?;
+
+    Ok(())
+}
+
+/* The path is $1 as well as TORRENTS_PATH, so a command can be a plain script taking a file */
+fn execute(command: &str, torrent: &Saved) -> Result<()> {
+    let envs = torrent
+        .fields()
+        .into_iter()
+        .map(|(key, value)| (format!("TORRENTS_{}", key.to_uppercase()), value));
+    /* Output is captured, so hooks running side by side never write over stdout */
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("sh")
+        .arg(torrent.path)
+        .envs(envs)
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Command exited with {}: {}", output.status, stderr.trim());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_octal_modes() {
+        assert_eq!(parse_mode("644").unwrap(), 0o644);
+        assert_eq!(parse_mode("0o2755").unwrap(), 0o2755);
+        assert!(parse_mode("999").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{adapter::Adapter, config::Config};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Filter {
+    /* Not on disk yet, and not given up on */
+    Pending,
+    /* On disk, or handed to the client in direct mode */
+    Downloaded,
+    /* Given up on by the last run */
+    Failed,
+}
+
+impl Filter {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Downloaded => "downloaded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/* One torrent, as printed by --json */
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    url: &'a str,
+    status: &'static str,
+    entry: Option<&'a str>,
+    title: Option<&'a str>,
+    path: Option<String>,
+    error: Option<&'a str>,
+}
+
+/* Torrents in the order the state keeps them, a page at a time; --json prints one object per line for jq and friends */
+pub fn list(
+    adapter: &Adapter,
+    config: &Config,
+    torrents_path: &str,
+    filter: Option<Filter>,
+    limit: Option<usize>,
+    offset: usize,
+    json: bool,
+) -> Result<()> {
+    let entries = config
+        .links
+        .iter()
+        .flat_map(|(entry, links)| links.iter().map(move |url| (url, entry)))
+        .collect::<HashMap<_, _>>();
+    let errors = config
+        .dead_letters
+        .iter()
+        .map(|letter| (&letter.url, letter.error.as_str()))
+        .collect::<HashMap<_, _>>();
+
+    let rows = config
+        .torrents
+        .iter()
+        .map(|url| {
+            let entry = entries.get(url).map(|entry| entry.as_str());
+            let path = adapter.torrent_path(torrents_path, url);
+            let downloaded = config.sent.contains(url)
+                || path.as_ref().is_some_and(|path| Path::new(path).exists());
+            let error = errors.get(url).copied().filter(|_error| !downloaded);
+            let status = match (downloaded, error) {
+                (true, _) => Filter::Downloaded,
+                (false, Some(_error)) => Filter::Failed,
+                (false, None) => Filter::Pending,
+            };
+
+            Row {
+                url,
+                status: status.name(),
+                entry,
+                title: entry
+                    .and_then(|entry| config.metadata.get(entry))
+                    .map(|metadata| metadata.title.as_str()),
+                path,
+                error,
+            }
+        })
+        .filter(|row| filter.is_none_or(|filter| row.status == filter.name()))
+        .collect::<Vec<_>>();
+
+    let total = rows.len();
+    let page = rows
+        .iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect::<Vec<_>>();
+
+    if json {
+        for row in &page {
+            println!("{}", serde_json::to_string(row)?);
+        }
+        return Ok(());
+    }
+
+    println!("{:<10}  {:<50}  {:<50}  TITLE", "STATUS", "ENTRY", "URL");
+    for row in &page {
+        println!(
+            "{:<10}  {:<50}  {:<50}  {}",
+            row.status,
+            row.entry.unwrap_or("-"),
+            row.url,
+            row.title.unwrap_or_default()
+        );
+    }
+
+    /* On stderr, so piping the table on never carries the footer along */
+    eprintln!(
+        "Showing {} of {total} torrents from offset {offset}",
+        page.len()
+    );
+
+    Ok(())
+}
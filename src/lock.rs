@@ -0,0 +1,92 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+    process, thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+
+use crate::events::say;
+
+const LOCK_FILE: &str = "TORRENTS.LOCK";
+const POLL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Owner {
+    pid: u32,
+    run_id: String,
+    started: DateTime<Utc>,
+}
+
+/* Held for the whole run, so overlapping runs never share TORRENTS.JSON; the OS lets go of it when the run exits, however it exits */
+#[derive(Debug)]
+pub struct Lock {
+    /* Closing it releases the lock; the file itself stays, as removing it would let a waiting run lock the old file while a new run creates another */
+    _file: File,
+}
+
+impl Lock {
+    /* Waits for the run holding the lock, or fails */
+    pub fn acquire(base_path: &str, run_id: &str, wait: bool) -> Result<Self> {
+        let path = Path::new(base_path).join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        let mut waiting = false;
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => break,
+                Err(error) if error.kind() == fs2::lock_contended_error().kind() => {}
+                Err(error) => {
+                    return Err(error).with_context(|| format!("Failed to lock {}", path.display()))
+                }
+            }
+
+            let current = fs::read_to_string(&path)
+                .ok()
+                .and_then(|text| serde_json::from_str::<Owner>(&text).ok());
+            let holder = match &current {
+                Some(current) => format!(
+                    "run {} (pid {}, started {})",
+                    current.run_id, current.pid, current.started
+                ),
+                None => "a run that is starting".to_string(),
+            };
+            if !wait {
+                bail!(
+                    "Another {holder} holds {}; pass --wait-for-lock to wait for it",
+                    path.display()
+                );
+            }
+            if !waiting {
+                say!("Waiting for {holder} to finish...");
+                waiting = true;
+            }
+
+            thread::sleep(POLL);
+        }
+
+        /* Only for the message of whoever waits; the lock is what keeps runs apart */
+        let owner = Owner {
+            pid: process::id(),
+            run_id: run_id.to_string(),
+            started: Utc::now(),
+        };
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(serde_json::to_string_pretty(&owner)?.as_bytes())?;
+        file.sync_all()?;
+
+        Ok(Self { _file: file })
+    }
+}
@@ -0,0 +1,49 @@
+use std::{fs::File, io, sync::Mutex};
+
+use anyhow::Result;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+use crate::{events::EventLayer, tui::BoardLayer};
+
+/* The dashboard replaces the stderr log, which would only scribble over it */
+pub fn init(
+    verbose: u8,
+    log_file: Option<&str>,
+    board: Option<BoardLayer>,
+    events: Option<EventLayer>,
+) -> Result<()> {
+    let level = match verbose {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+
+    let stderr = board.is_none().then(|| {
+        fmt::layer()
+            .with_writer(io::stderr)
+            .with_target(false)
+            .with_filter(level)
+    });
+
+    /* The log file is for post-mortems, so it records request level detail regardless of -v */
+    let file = match log_file {
+        Some(path) => Some(
+            fmt::layer()
+                .json()
+                .with_writer(Mutex::new(File::create(path)?))
+                .with_filter(LevelFilter::DEBUG.max(level)),
+        ),
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .with(board)
+        .with(events)
+        .try_init()?;
+
+    Ok(())
+}
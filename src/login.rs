@@ -0,0 +1,73 @@
+use std::env;
+
+use anyhow::{bail, Context, Result};
+use reqwest::{
+    blocking::Response,
+    cookie::{CookieStore, Jar},
+    Url,
+};
+use tracing::warn;
+
+use crate::{adapter::Headers, events::say, proxy::Exit, settings::Login};
+
+/* Posts the login form through each exit in turn until one gets through; the session lands in the shared jar */
+pub fn login(
+    login: &Login,
+    base_url: &str,
+    exits: &[Exit],
+    jar: &Jar,
+    headers: &Headers,
+) -> Result<()> {
+    let Some(url) = &login.url else {
+        return Ok(());
+    };
+    let url = url.replace("{base_url}", base_url);
+
+    let mut form = login.fields.clone();
+    for (field, var) in &login.fields_env {
+        let value = env::var(var)
+            .with_context(|| format!("Failed to read {var} for login field {field}"))?;
+        form.insert(field.clone(), value);
+    }
+
+    let headers = headers.render(&url)?;
+    let mut logged_in = false;
+    for exit in exits {
+        let response = exit
+            .client
+            .post(&url)
+            .headers(headers.clone())
+            .form(&form)
+            .send()
+            .and_then(Response::error_for_status);
+
+        match response {
+            Ok(_response) => {
+                logged_in = true;
+                break;
+            }
+            Err(error) => warn!(exit = %exit.address, %error, "Failed to log in"),
+        }
+    }
+    if !logged_in {
+        bail!("Failed to log in at {url} through any exit");
+    }
+
+    if let Some(cookie) = &login.cookie {
+        let parsed = Url::parse(&url).with_context(|| format!("Invalid login URL {url}"))?;
+        let cookies = jar.cookies(&parsed);
+        let cookies = cookies.as_ref().and_then(|value| value.to_str().ok());
+        let set = cookies.is_some_and(|cookies| {
+            cookies
+                .split("; ")
+                .any(|pair| pair.split('=').next() == Some(cookie.as_str()))
+        });
+        if !set {
+            bail!("Logged in at {url}, but no {cookie} cookie was set; check the login fields");
+        }
+    }
+
+    say!("Logged in at {url}");
+
+    Ok(())
+}
@@ -1,281 +1,2204 @@
-use std::{ffi::OsStr, fs, path::Path};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::Result;
-use clap::Parser;
+use adapter::Adapter;
+use anyhow::{bail, Context, Result};
+use blocklist::Blocklist;
+use browser::Renderer;
+use chrono::Utc;
+use clap::{ArgAction, Parser, Subcommand};
+use client::TorrentClient;
 use config::Config;
-use crossbeam_queue::ArrayQueue;
-use kdam::{rayon::prelude::*, Bar, BarExt, TqdmParallelIterator};
-use lazy_static::lazy_static;
-use regex::Regex;
-use reqwest::{blocking::Client, Proxy};
-use retry::delay::{jitter, Exponential};
-use scraper::{Html, Selector};
+use download::Downloader;
+use events::{say, Format};
+use hash::Algorithm;
+use history::RunRecord;
+use kdam::{
+    rayon::{prelude::*, ThreadPoolBuilder},
+    BarExt, TqdmParallelIterator,
+};
+use lock::Lock;
+use media::Media;
+use metadata::Metadata;
+use metrics::Metrics;
+use notify::{Event, Notifier};
+use order::Order;
+use proxy::{Anonymity, Listing};
+use recheck::Rechecker;
+use releases::DuplicatePolicy;
+use reqwest::{blocking::Client, cookie::Jar, Proxy};
+use sanitize::Sanitizer;
+use scraper::Html;
+use settings::{Mode, Scheme, Settings};
+use summary::{LastRun, Summary, Tally};
+use throttle::Throttle;
+use tls::TlsVersion;
+use tor::Tor;
+use tracing::{info, info_span, span::EnteredSpan, warn, Span};
+use trackers::Rewriter;
+use ui::Ui;
+use uuid::Uuid;
+use writer::Writer;
+use xref::Sources;
 
+mod adapter;
+mod adopt;
+mod archive;
+mod bencode;
+mod blocklist;
+mod browser;
+mod cache;
+mod clean;
+mod client;
 mod config;
-
-/* https://techblog.willshouse.com/2012/01/03/most-common-user-agents */
-const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/118.0.0.0 Safari/537.36";
-const BASE_URL: &str = "http://www.ptorrents.com";
-const ADDR_URL: &str = "https://api.seeip.org";
+mod diff;
+mod dns;
+mod download;
+mod events;
+mod export;
+mod forward;
+mod hash;
+mod history;
+mod hooks;
+mod list;
+mod lock;
+mod logging;
+mod login;
+mod media;
+mod metadata;
+mod metrics;
+mod notify;
+mod order;
+mod pack;
+mod pagination;
+mod placement;
+mod proxy;
+mod queue;
+mod quota;
+mod recheck;
+mod releases;
+mod retention;
+mod sanitize;
+mod search;
+mod seeders;
+mod select;
+mod settings;
+mod state;
+mod stats;
+mod stream;
+mod summary;
+mod sync;
+mod throttle;
+mod tls;
+mod tor;
+mod trackers;
+mod tui;
+mod ui;
+mod verify;
+mod watch;
+mod writer;
+mod xref;
 
 #[derive(Debug, Parser)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short, long, default_value = ".")]
     base_path: String,
 
-    #[arg(short, long, default_value = "proxies.txt")]
-    proxies_path: String,
+    #[arg(long)]
+    settings: Option<String>,
+
+    /* Named [profiles.<name>] table of the settings file to run with */
+    #[arg(long)]
+    profile: Option<String>,
+
+    #[arg(long)]
+    base_url: Option<String>,
+
+    #[arg(long)]
+    mirror: Vec<String>,
+
+    #[arg(long)]
+    hook: Vec<String>,
+
+    #[arg(long)]
+    blocklist: Option<String>,
+
+    #[arg(short, long, num_args = 1..)]
+    proxies_path: Vec<String>,
+
+    #[arg(short, long)]
+    user_agent: Option<String>,
+
+    #[arg(long)]
+    direct: bool,
+
+    #[arg(long, conflicts_with = "mixed")]
+    no_proxy: bool,
+
+    #[arg(long)]
+    mixed: bool,
+
+    #[arg(long)]
+    stream: bool,
+
+    /* Works only from the local cache and state, and fails on anything that would need the network */
+    #[arg(long, conflicts_with_all = ["stream", "interactive"])]
+    offline: bool,
+
+    /* Picks which pending torrents step 7 fetches */
+    #[arg(long, conflicts_with_all = ["stream", "tui"])]
+    interactive: bool,
+
+    #[arg(long)]
+    archive_media: bool,
+
+    #[arg(long)]
+    tor: bool,
+
+    #[arg(long)]
+    tor_proxy: Option<String>,
+
+    #[arg(long)]
+    tor_control: Option<String>,
+
+    #[arg(long)]
+    tor_password: Option<String>,
+
+    #[arg(long)]
+    tor_workers: Option<usize>,
+
+    #[arg(long)]
+    tor_isolate: bool,
+
+    /* Accepts invalid certificates, e.g. from a proxy that intercepts TLS */
+    #[arg(long)]
+    insecure: bool,
+
+    #[arg(long)]
+    ca_bundle: Option<String>,
+
+    #[arg(long, value_enum)]
+    min_tls_version: Option<TlsVersion>,
+
+    /* DoH endpoint or DNS server for the hosts of the site and its mirrors */
+    #[arg(long)]
+    dns: Option<String>,
+
+    /* Fixed address for a host, as host=address */
+    #[arg(long, value_parser = host_address)]
+    resolve: Vec<(String, String)>,
+
+    #[arg(long)]
+    cooldown: Option<u64>,
+
+    #[arg(long)]
+    max_bandwidth: Option<String>,
+
+    #[arg(long)]
+    workers: Option<usize>,
+
+    #[arg(long)]
+    recheck_interval: Option<u64>,
+
+    #[arg(long, value_enum)]
+    min_anonymity: Option<Anonymity>,
+
+    #[arg(long)]
+    connect_timeout: Option<u64>,
+
+    #[arg(long)]
+    request_timeout: Option<u64>,
+
+    #[arg(long)]
+    metrics_file: Option<String>,
+
+    #[arg(long)]
+    check_concurrency: Option<usize>,
+
+    #[arg(long)]
+    proxy_report: Option<String>,
+
+    #[arg(long, value_enum)]
+    checksum: Option<Algorithm>,
+
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    #[arg(long)]
+    log_file: Option<String>,
+
+    #[arg(long)]
+    tui: bool,
+
+    /* Replaces the progress bars with newline delimited JSON events on stdout */
+    #[arg(long, value_enum, conflicts_with = "tui")]
+    output: Option<Format>,
+
+    /* Waits for an overlapping run to finish instead of exiting */
+    #[arg(long)]
+    wait_for_lock: bool,
+
+    #[arg(long)]
+    max_attempts: Option<usize>,
+
+    #[arg(long)]
+    max_failure_rate: Option<f64>,
 
-    #[arg(short, long, default_value = USER_AGENT)]
-    user_agent: String,
+    #[arg(long)]
+    max_consecutive_failures: Option<usize>,
+
+    #[arg(long, value_delimiter = ',')]
+    steps: Vec<usize>,
+
+    #[arg(long, value_delimiter = ',')]
+    skip_steps: Vec<usize>,
+
+    #[arg(long)]
+    max_pages: Option<usize>,
+
+    #[arg(long)]
+    max_entries: Option<usize>,
+
+    #[arg(long)]
+    max_torrents: Option<usize>,
+
+    #[arg(long)]
+    max_file_size: Option<String>,
+
+    #[arg(long)]
+    max_disk_usage: Option<String>,
+
+    #[arg(long)]
+    refresh_older_than: Option<String>,
+
+    #[arg(long)]
+    keep_entries_newer_than: Option<String>,
+
+    #[arg(long)]
+    keep_html_days: Option<u64>,
+
+    #[arg(long)]
+    min_seeders: Option<u64>,
+
+    #[arg(long)]
+    stop_after_empty_pages: Option<usize>,
+
+    #[arg(long, value_enum)]
+    order: Option<Order>,
+
+    #[arg(long)]
+    budget: Option<usize>,
+
+    #[arg(long, value_enum)]
+    duplicates: Option<DuplicatePolicy>,
+
+    #[arg(long)]
+    path_template: Option<String>,
+}
+
+impl Args {
+    /* Flags given on the command line win over torrents.toml */
+    fn apply(&self, settings: &mut Settings) {
+        fn set<T: Clone>(setting: &mut T, flag: &Option<T>) {
+            if let Some(flag) = flag {
+                *setting = flag.clone();
+            }
+        }
+
+        fn set_some<T: Clone>(setting: &mut Option<T>, flag: &Option<T>) {
+            if flag.is_some() {
+                *setting = flag.clone();
+            }
+        }
+
+        set_some(&mut settings.base_url, &self.base_url);
+        if !self.mirror.is_empty() {
+            settings.mirrors = self.mirror.clone();
+        }
+        set_some(&mut settings.blocklist, &self.blocklist);
+        settings.hooks.commands.extend(self.hook.iter().cloned());
+        set(&mut settings.network.user_agent, &self.user_agent);
+        set(&mut settings.network.connect_timeout, &self.connect_timeout);
+        set(&mut settings.network.request_timeout, &self.request_timeout);
+        set(&mut settings.network.cooldown, &self.cooldown);
+        set_some(&mut settings.network.max_bandwidth, &self.max_bandwidth);
+        set_some(&mut settings.network.workers, &self.workers);
+        set_some(
+            &mut settings.proxies.recheck_interval,
+            &self.recheck_interval,
+        );
+        set_some(&mut settings.proxies.min_anonymity, &self.min_anonymity);
+
+        if !self.proxies_path.is_empty() {
+            settings.proxies.paths = self.proxies_path.clone();
+        }
+        set(
+            &mut settings.proxies.check_concurrency,
+            &self.check_concurrency,
+        );
+        set_some(&mut settings.proxies.report, &self.proxy_report);
+
+        settings.client.direct |= self.direct;
+        if self.no_proxy {
+            settings.network.mode = Mode::Direct;
+        }
+        if self.mixed {
+            settings.network.mode = Mode::Mixed;
+        }
+        settings.pipeline.stream |= self.stream;
+        settings.media.enabled |= self.archive_media;
+
+        settings.tor.enabled |= self.tor;
+        settings.tor.isolate |= self.tor_isolate;
+        set(&mut settings.tor.proxy, &self.tor_proxy);
+        set(&mut settings.tor.control, &self.tor_control);
+        set_some(&mut settings.tor.password, &self.tor_password);
+        set(&mut settings.tor.workers, &self.tor_workers);
+
+        settings.tls.insecure |= self.insecure;
+        set_some(&mut settings.tls.ca_bundle, &self.ca_bundle);
+        set_some(&mut settings.tls.min_version, &self.min_tls_version);
+
+        set_some(&mut settings.dns.resolver, &self.dns);
+        settings.dns.addresses.extend(self.resolve.iter().cloned());
+
+        set(&mut settings.retry.max_attempts, &self.max_attempts);
+        set(&mut settings.retry.max_failure_rate, &self.max_failure_rate);
+        set(
+            &mut settings.retry.max_consecutive_failures,
+            &self.max_consecutive_failures,
+        );
+
+        set_some(&mut settings.limits.max_pages, &self.max_pages);
+        set_some(&mut settings.limits.max_entries, &self.max_entries);
+        set_some(&mut settings.limits.max_torrents, &self.max_torrents);
+        set_some(&mut settings.limits.max_file_size, &self.max_file_size);
+        set_some(&mut settings.disk.max_usage, &self.max_disk_usage);
+        set_some(
+            &mut settings.limits.refresh_older_than,
+            &self.refresh_older_than,
+        );
+        set_some(&mut settings.limits.min_seeders, &self.min_seeders);
+        set_some(
+            &mut settings.retention.keep_entries_newer_than,
+            &self.keep_entries_newer_than,
+        );
+        set_some(&mut settings.retention.keep_html_days, &self.keep_html_days);
+        set_some(
+            &mut settings.limits.stop_after_empty_pages,
+            &self.stop_after_empty_pages,
+        );
+        set_some(&mut settings.limits.order, &self.order);
+        set_some(&mut settings.limits.budget, &self.budget);
+        set(&mut settings.duplicates.policy, &self.duplicates);
+
+        set_some(&mut settings.layout.path_template, &self.path_template);
+
+        set(&mut settings.output.format, &self.output);
+        set(&mut settings.output.checksum, &self.checksum);
+        set_some(&mut settings.output.metrics_file, &self.metrics_file);
+        set_some(&mut settings.output.log_file, &self.log_file);
+    }
+
+    fn enabled(&self, step: usize) -> bool {
+        (self.steps.is_empty() || self.steps.contains(&step)) && !self.skip_steps.contains(&step)
+    }
+
+    /* Steps named with --steps run even when there is nothing new for them to do */
+    fn forced(&self, step: usize) -> bool {
+        self.steps.contains(&step)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    Adopt {
+        dir: String,
+    },
+    Adapter {
+        #[command(subcommand)]
+        command: AdapterCommand,
+    },
+    /* Lists torrents, all of them or only those with the given status */
+    List {
+        #[arg(value_enum)]
+        status: Option<list::Filter>,
+
+        #[arg(long)]
+        limit: Option<usize>,
+
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        /* One JSON object per torrent and line */
+        #[arg(long)]
+        json: bool,
+    },
+    Search {
+        query: String,
+
+        #[arg(long)]
+        regex: bool,
+    },
+    Export {
+        #[arg(long, value_enum)]
+        format: export::Format,
+
+        #[arg(long)]
+        output: String,
+    },
+    Diff {
+        #[arg(long)]
+        against: Option<String>,
+
+        #[arg(long)]
+        json: bool,
+    },
+    ApplyRetention {
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /* Moves or deletes the files of entries older than --keep-entries-newer-than or --keep-html-days */
+    Archive {
+        #[arg(long)]
+        dry_run: bool,
+
+        /* Directory to move the files under instead of deleting them */
+        #[arg(long)]
+        to: Option<String>,
+    },
+    Stats,
+    /* Past runs with their step timings, throughput and exit health */
+    History {
+        /* How many of the last runs to show */
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+
+        /* Shows the items and throughput of this step alone */
+        #[arg(long)]
+        step: Option<usize>,
+
+        #[arg(long)]
+        json: bool,
+    },
+    Coverage,
+    /* Lists release families and the entry each keeps */
+    Duplicates,
+    /* Lists what would be removed unless --delete is given */
+    Clean {
+        /* Listing is the default already; this only says so */
+        #[arg(long, conflicts_with = "delete")]
+        dry_run: bool,
+
+        #[arg(long, conflicts_with = "dry_run")]
+        delete: bool,
+    },
+    /* Runs the pipeline for several profiles, each with its own state, and sums up their runs */
+    Sync {
+        profiles: Vec<String>,
+
+        /* Every profile in sync.targets, or in the settings file when that is empty */
+        #[arg(long, conflicts_with = "profiles")]
+        all: bool,
+
+        /* Targets run at once */
+        #[arg(long)]
+        concurrency: Option<usize>,
+    },
+    /* Runs the pipeline on an interval and serves Prometheus metrics */
+    Watch {
+        #[arg(long, default_value_t = 3600)]
+        interval: u64,
+
+        #[arg(long, default_value = "127.0.0.1:9898")]
+        listen: String,
+    },
+    /* Checks archived torrents against their recorded size, checksum and infohash */
+    Verify {
+        /* Deletes bad torrents so the next run downloads them again */
+        #[arg(long)]
+        requeue: bool,
+
+        /* Writes checksums of the good torrents to this path plus .b3, .sha256 or .xxh128 for their algorithm, for b3sum -c and the like */
+        #[arg(long)]
+        manifest: Option<String>,
+    },
+    /* Applies the trackers settings to every archived torrent */
+    RewriteTrackers,
+    /* Rewrites the HTML cache in the format set by disk.compression */
+    MigrateCache,
+    /* Drops error pages, challenges and truncated bodies from the HTML cache, then runs to fetch them again */
+    Revalidate {
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /* Fetches and scrapes the entries skipped for too few seeders again, then runs */
+    RecheckDead,
+    /* Moves the crawl state between machines */
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    Pack {
+        output: String,
+
+        #[arg(long, value_enum)]
+        kind: Option<metadata::Kind>,
+
+        #[arg(long)]
+        query: Option<String>,
+    },
+}
+
+impl Command {
+    /* Read-only commands may run alongside a run; watch and sync only start runs, which take the lock themselves */
+    fn writes(&self) -> bool {
+        !matches!(
+            self,
+            Self::Adapter { .. }
+                | Self::List { .. }
+                | Self::Search { .. }
+                | Self::Export { .. }
+                | Self::Diff { .. }
+                | Self::Stats
+                | Self::History { .. }
+                | Self::Coverage
+                | Self::Duplicates
+                | Self::Sync { .. }
+                | Self::Watch { .. }
+                | Self::Pack { .. }
+                | Self::State {
+                    command: StateCommand::Export { .. }
+                }
+        )
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum StateCommand {
+    /* Packs the state, and with --cache a manifest of the HTML cache; zstd when output ends in .zst, gzip for .gz */
+    Export {
+        #[arg(long)]
+        output: String,
+
+        #[arg(long)]
+        cache: bool,
+    },
+    /* Checks an exported archive against its manifest and installs its state */
+    Import {
+        input: String,
+
+        /* Replaces existing state, which is kept as a backup */
+        #[arg(long)]
+        force: bool,
+    },
+    /* Upgrades the state file on disk to the current version, putting it back if that fails */
+    Migrate,
+}
+
+#[derive(Debug, Subcommand)]
+enum AdapterCommand {
+    Test {
+        name: String,
+
+        #[arg(long)]
+        fixtures: String,
+    },
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let base_path = &args.base_path;
-    let mut config = Config::load(base_path).unwrap_or_default();
+
+    let settings_path = match &args.settings {
+        Some(settings_path) => settings_path.clone(),
+        None => cache::join(&args.base_path, &["torrents.toml"]),
+    };
+    let mut settings = Settings::load(&settings_path, args.profile.as_deref())
+        .with_context(|| format!("Failed to load settings from {settings_path}"))?;
+    args.apply(&mut settings);
+
+    /* Each profile keeps its state and files under its own base path */
+    let base_path = &match &settings.base_path {
+        Some(path) => {
+            let path = Path::new(&args.base_path).join(path);
+            fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            path.display().to_string()
+        }
+        None => args.base_path.clone(),
+    };
+    let schedule = &settings.schedule;
+
+    let text = settings.output.format == Format::Text;
+    if args.interactive && !text {
+        bail!("--interactive needs text output, as JSON events leave no room for its menu");
+    }
+    let (dashboard, board) = match args.tui && text && args.command.is_none() {
+        true => {
+            let (dashboard, board) = tui::start()?;
+            (Some(dashboard), Some(board))
+        }
+        false => (None, None),
+    };
+    /* Only the pipeline emits events; subcommands print what they were asked for */
+    let events = match !text && args.command.is_none() {
+        true => Some(events::start()),
+        false => None,
+    };
+    let log_file = settings.output.log_file.as_deref();
+    logging::init(args.verbose, log_file, board, events)?;
+
+    /* Stamped on log lines, saved state and metrics, to trace records back to the run */
+    let run_id = Uuid::new_v4().to_string();
+    let run = info_span!("run", run_id);
+    let _run = run.enter();
+
+    let writes = args.command.as_ref().is_none_or(Command::writes);
+    let _lock = match writes {
+        true => Some(Lock::acquire(base_path, &run_id, args.wait_for_lock)?),
+        false => None,
+    };
+    /* Before the state is loaded, which would upgrade all of it in memory first */
+    if let Some(Command::State {
+        command: StateCommand::Migrate,
+    }) = &args.command
+    {
+        return state::migrate(base_path);
+    }
+    let mut config = Config::load(base_path)?;
+    if writes {
+        Config::rotate(base_path)?;
+    }
+    config.run_id = Some(run_id.clone());
+    let html_path = cache::join(base_path, &[&settings.layout.html]);
+    let torrents_path = cache::join(base_path, &[&settings.layout.torrents]);
+
+    let mut adapter = Adapter::ptorrents();
+    adapter.sanitizer = Sanitizer::new(&settings.layout);
+    adapter.layout = settings.layout.clone();
+    if let Some(scrape) = settings.scrape.get(adapter.name) {
+        adapter.apply(scrape)?;
+    }
+    if let Some(base_url) = &settings.base_url {
+        adapter.base_url = base_url.trim_end_matches('/').to_string();
+    }
+    match settings.network.scheme {
+        Scheme::Auto => {
+            if let Some(scheme) = &config.scheme {
+                adapter.set_scheme(scheme);
+            }
+        }
+        Scheme::Http => adapter.set_scheme("http"),
+        Scheme::Https => adapter.set_scheme("https"),
+    }
+    let mirrors = settings
+        .mirrors
+        .iter()
+        .map(|mirror| mirror.trim_end_matches('/').to_string());
+    adapter.mirrors = std::iter::once(adapter.base_url.clone())
+        .chain(mirrors)
+        .collect();
+    if let Some(mirror) = config
+        .mirror
+        .as_ref()
+        .filter(|m| adapter.mirrors.contains(m))
+    {
+        adapter.base_url = mirror.clone();
+    }
+    adapter.placements = config.placements.clone();
+
+    match &args.command {
+        Some(Command::Adopt { dir }) => {
+            return adopt::adopt(&adapter, dir, base_path, &torrents_path, &mut config);
+        }
+        Some(Command::Adapter {
+            command: AdapterCommand::Test { name, fixtures },
+        }) => {
+            let mut adapter = Adapter::find(name).context(format!("Unknown adapter {name}"))?;
+            if let Some(scrape) = settings.scrape.get(adapter.name) {
+                adapter.apply(scrape)?;
+            }
+            return adapter::test(&adapter, fixtures);
+        }
+        Some(Command::List {
+            status,
+            limit,
+            offset,
+            json,
+        }) => {
+            return list::list(
+                &adapter,
+                &config,
+                &torrents_path,
+                *status,
+                *limit,
+                *offset,
+                *json,
+            );
+        }
+        Some(Command::Search { query, regex }) => {
+            return search::search(&adapter, &config, &torrents_path, query, *regex);
+        }
+        Some(Command::Export { format, output }) => {
+            return export::export(
+                &adapter,
+                &config,
+                &html_path,
+                &torrents_path,
+                *format,
+                output,
+            );
+        }
+        Some(Command::Diff { against, json }) => {
+            let previous_path = match against {
+                Some(against) => PathBuf::from(against),
+                None => Config::snapshot_path(base_path)?,
+            };
+            let previous = Config::read(&previous_path)
+                .with_context(|| format!("No previous state at {}", previous_path.display()))?;
+            return diff::diff(&previous, &config, *json);
+        }
+        Some(Command::ApplyRetention { dry_run }) => {
+            return retention::apply(
+                &adapter,
+                &mut config,
+                &settings.retention.rules,
+                base_path,
+                &html_path,
+                &torrents_path,
+                *dry_run,
+            );
+        }
+        Some(Command::Archive { dry_run, to }) => {
+            if to.is_some() {
+                settings.retention.archive_to = to.clone();
+            }
+            return archive::archive(
+                &adapter,
+                &mut config,
+                &settings.retention,
+                base_path,
+                &html_path,
+                &torrents_path,
+                *dry_run,
+            );
+        }
+        Some(Command::Stats) => {
+            return stats::stats(&adapter, &config, &html_path, &torrents_path);
+        }
+        Some(Command::History { limit, step, json }) => {
+            return history::print(base_path, *limit, *step, *json);
+        }
+        Some(Command::Duplicates) => {
+            releases::print(&config, &settings.duplicates);
+            return Ok(());
+        }
+        Some(Command::Coverage) => {
+            stats::print_coverage(&adapter, &config, &html_path, &torrents_path);
+            return Ok(());
+        }
+        Some(Command::Clean { dry_run, delete }) => {
+            return clean::clean(
+                &adapter,
+                &mut config,
+                base_path,
+                &html_path,
+                &torrents_path,
+                *delete && !*dry_run,
+            );
+        }
+        Some(Command::Sync {
+            profiles,
+            all,
+            concurrency,
+        }) => {
+            if let Some(concurrency) = concurrency {
+                settings.sync.concurrency = *concurrency;
+            }
+            return sync::sync(
+                &settings,
+                &settings_path,
+                &args.base_path,
+                base_path,
+                profiles,
+                *all,
+                |settings: &mut Settings| args.apply(settings),
+            );
+        }
+        Some(Command::Watch { interval, listen }) => {
+            let metrics_file = settings.output.metrics_file.as_deref();
+            let ui = Ui::new(adapter, base_path, &torrents_path);
+            return watch::watch(*interval, listen, metrics_file, base_path, ui);
+        }
+        Some(Command::Verify { requeue, manifest }) => {
+            return verify::verify(
+                &adapter,
+                &mut config,
+                base_path,
+                &torrents_path,
+                *requeue,
+                manifest.as_deref(),
+            );
+        }
+        Some(Command::RewriteTrackers) => {
+            let client = tls::Policy::load(&settings.tls)?.direct_client(&settings.network)?;
+            let Some(rewriter) = Rewriter::new(&settings.trackers, &client)? else {
+                bail!("Nothing to do, set trackers.strip, trackers.add or trackers.list");
+            };
+            let torrents = config
+                .torrents
+                .iter()
+                .filter_map(|url| Some((url.clone(), adapter.torrent_path(&torrents_path, url)?)))
+                .collect();
+            let algorithm = settings.output.checksum;
+            if rewriter.rewrite_all(&mut config, algorithm, torrents)? > 0 {
+                config.save(base_path)?;
+            }
+
+            return Ok(());
+        }
+        Some(Command::MigrateCache) => {
+            return cache::migrate(&html_path, settings.disk.compression);
+        }
+        Some(Command::Revalidate { dry_run }) => {
+            let dropped = cache::revalidate(&mut config, &settings.layout, &html_path, *dry_run)?;
+            if *dry_run || dropped == 0 {
+                return Ok(());
+            }
+
+            config.save(base_path)?;
+        }
+        Some(Command::State {
+            command: StateCommand::Export { output, cache },
+        }) => {
+            return state::export(base_path, &html_path, output, *cache);
+        }
+        Some(Command::State {
+            command: StateCommand::Import { input, force },
+        }) => {
+            return state::import(base_path, &html_path, input, *force);
+        }
+        Some(Command::State {
+            command: StateCommand::Migrate,
+        }) => unreachable!("state migrate returns before the state is loaded"),
+        Some(Command::RecheckDead) if config.dead.is_empty() => {
+            println!("No entries were skipped for too few seeders");
+            return Ok(());
+        }
+        Some(Command::RecheckDead) => {}
+        Some(Command::Pack {
+            output,
+            kind,
+            query,
+        }) => {
+            return pack::pack(
+                &adapter,
+                &config,
+                &html_path,
+                &torrents_path,
+                *kind,
+                query.as_deref(),
+                output,
+            );
+        }
+        None => {}
+    }
+
+    config.snapshot(base_path)?;
+    let blocklist = match &settings.blocklist {
+        Some(path) => Blocklist::load(path)?,
+        None => Blocklist::default(),
+    };
+    let notifier = Notifier::new(&settings.notify, &run_id)?;
+    let started = Utc::now();
+
+    let tor = settings.tor.enabled.then(|| Tor {
+        proxy: settings.tor.proxy.clone(),
+        control: settings.tor.control.clone(),
+        password: settings.tor.password.clone(),
+    });
+
+    /* Clients keep their own cookies, unless a login session has to be shared by all of them */
+    let jar = settings
+        .login
+        .url
+        .is_some()
+        .then(|| Arc::new(Jar::default()));
+    /* Owns what it needs, so the background re-checker can build clients too */
+    let build_client = {
+        let jar = jar.clone();
+        let tls = tls::Policy::load(&settings.tls)?;
+        /* Offline runs touch no network, resolvers included */
+        let resolved = match args.offline {
+            true => Vec::new(),
+            false => dns::resolve(&settings.dns, &adapter.mirrors, &tls)?,
+        };
+        let user_agent = settings.network.user_agent.clone();
+        let connect_timeout = Duration::from_secs(settings.network.connect_timeout);
+        let request_timeout = Duration::from_secs(settings.network.request_timeout);
+
+        move |proxy: Option<Proxy>| {
+            let builder = Client::builder()
+                .user_agent(&user_agent)
+                .connect_timeout(connect_timeout)
+                .timeout(request_timeout);
+            let builder = match &jar {
+                Some(jar) => builder.cookie_provider(Arc::clone(jar)),
+                None => builder.cookie_store(true),
+            };
+            let builder = match proxy {
+                Some(proxy) => builder.proxy(proxy),
+                None => builder.no_proxy(),
+            };
+
+            let builder = resolved.iter().fold(builder, |builder, (host, address)| {
+                builder.resolve(host, *address)
+            });
+
+            tls.apply(builder).build()
+        }
+    };
+    /* For lists and the address echo, which go out without a proxy */
+    let list_client = build_client(None)?;
+
+    let mut summary = Summary::default();
 
     /* Step 1 */
-    println!("Step 1: Checking Proxies...");
-    let clients = fs::read_to_string(args.proxies_path)?
-        .split('\n')
-        .par_bridge()
-        .map(String::from)
-        .map(|proxy_scheme| {
-            let proxy = Proxy::all(&proxy_scheme);
-
-            proxy.map(|proxy| (proxy, proxy_scheme))
-        })
-        .filter_map(Result::ok)
-        .map(|(proxy, proxy_scheme)| {
-            let client = Client::builder()
-                .proxy(proxy)
-                .user_agent(USER_AGENT)
-                .build();
-
-            client.map(|client| (client, proxy_scheme))
-        })
-        .filter_map(Result::ok)
-        .filter_map(check_proxy)
-        .collect::<Vec<_>>();
+    let mut _span = step(&run, &mut summary, 1);
+    let mode = settings.network.mode;
+    let (mut exits, max_proxies) = if args.offline {
+        say!("Step 1: Checking proxies... (Skipped, offline)");
+        (Vec::new(), 0)
+    } else if args.enabled(1) && mode != Mode::Direct {
+        let listings = match &tor {
+            Some(tor) => (0..settings.tor.workers)
+                .map(|worker| Listing {
+                    proxy: tor.proxy_scheme(settings.tor.isolate.then_some(worker)),
+                    labels: vec!["tor".to_string()],
+                })
+                .collect::<Vec<_>>(),
+            None => match proxy::load_lists(&settings.proxies.paths, &list_client) {
+                Ok(listings) => listings,
+                Err(error) if mode == Mode::Mixed => {
+                    warn!(%error, "Failed to load proxies, continuing with the direct exit");
+                    Vec::new()
+                }
+                Err(error) => return Err(error.context("Failed to load proxies, see --no-proxy")),
+            },
+        };
+
+        let local = proxy::local_address(&list_client)
+            .context("Failed to get the local address, which proxies are checked against")?;
+
+        let max_checks = listings.len();
+        let mut bar = events::bar(max_checks);
+        bar.write(format!("Step 1: Checking {max_checks} proxies..."))?;
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(settings.proxies.check_concurrency)
+            .build()?;
+        let mut checks = pool.install(|| {
+            listings
+                .into_par_iter()
+                .tqdm_with_bar(bar)
+                .map(|listing| {
+                    let client = Proxy::all(&listing.proxy).and_then(|p| build_client(Some(p)));
+
+                    proxy::check(listing, client, &local)
+                })
+                .collect::<Vec<_>>()
+        });
+
+        for check in &checks {
+            if let Some(anonymity) = check.anonymity {
+                config.anonymity.insert(check.proxy.clone(), anonymity);
+            }
+        }
+        if let Some(min) = settings.proxies.min_anonymity {
+            proxy::require_anonymity(&mut checks, min);
+        }
+
+        proxy::print_summary(&checks);
+        if let Some(proxy_report) = &settings.proxies.report {
+            proxy::write_report(&checks, proxy_report)?;
+        }
+
+        let max_proxies = checks.iter().filter(|check| check.outcome.is_ok()).count();
+        let exits = proxy::group_by_exit(checks);
+        for exit in exits.iter().filter(|exit| exit.aliases.len() > 1) {
+            info!(
+                exit = exit.address,
+                aliases = exit.aliases.len(),
+                "Exit shared by several proxies"
+            );
+        }
+        say!("Found {} exits across {max_proxies} proxies", exits.len());
+        if exits.is_empty() && mode == Mode::Proxy {
+            bail!("No proxy works, so nothing can be fetched; see --mixed and --no-proxy");
+        }
+
+        (exits, max_proxies)
+    } else if mode == Mode::Direct {
+        say!("Step 1: Checking proxies... (Skipped, direct)");
+        (Vec::new(), 0)
+    } else {
+        say!("Step 1: Checking proxies... (Skipped)");
+        (Vec::new(), 0)
+    };
+
+    if mode != Mode::Proxy && !args.offline {
+        exits.push(proxy::direct(build_client(None)?));
+    }
+
+    let cooldown = Duration::from_secs(settings.network.cooldown);
+    let validators = std::mem::take(&mut config.validators);
+    let statuses = std::mem::take(&mut config.statuses);
+    let mut headers = adapter.headers();
+    if let Some(overrides) = settings.headers.get(adapter.name) {
+        headers.merge(overrides);
+    }
+    if let Some(jar) = jar.as_deref().filter(|_| !args.offline) {
+        login::login(&settings.login, &adapter.base_url, &exits, jar, &headers)?;
+    }
+    let writer = Writer::new(settings.disk.writers, settings.disk.queue);
+    let max_bandwidth = settings.network.max_bandwidth.as_deref();
+    let throttle = Throttle::new(max_bandwidth.map(throttle::parse_bytes).transpose()?);
+    let max_file_size = settings.limits.max_file_size.as_deref();
+    let max_file_size = max_file_size.map(throttle::parse_bytes).transpose()?;
+    let downloader = Arc::new(Downloader::new(
+        exits,
+        tor,
+        cooldown,
+        &settings.retry,
+        settings.network.workers,
+        validators,
+        statuses,
+        writer,
+        settings.disk.compression,
+        throttle,
+        max_file_size,
+        headers,
+    ));
+    let _rechecker = match mode != Mode::Direct && downloader.tor.is_none() && !args.offline {
+        true => Rechecker::start(Arc::clone(&downloader), &settings.proxies, build_client),
+        false => None,
+    };
 
     /* Step 2 */
-    println!("Step 2: Getting max page number...");
-    let max_pages = {
-        /* Saving */
-        let file = (BASE_URL.to_string(), format!("{base_path}/HTML/INDEX.HTML"));
-        let contents = save_file(&clients[0], &file)?;
+    _span = step(&run, &mut summary, 2);
+    let max_pages = if args.enabled(2) && args.offline {
+        say!("Step 2: Getting max page number... (Cached, offline)");
+
+        let path = settings.layout.index_path(&html_path);
+        let contents = cache::read_to_string(&path)
+            .with_context(|| format!("Offline, but the index is not cached at {path}"))?;
+        let html = Html::parse_document(&contents);
+
+        adapter.discover_max_pages(&html, |page| {
+            Ok(downloader.is_cached(&settings.layout.page_path(&html_path, page)))
+        })?
+    } else if args.enabled(2) {
+        say!("Step 2: Getting max page number...");
+
+        /* Saving, from the first mirror that answers */
+        let mut contents = Err(anyhow::anyhow!("No base URL to fetch"));
+        for mirror in adapter.mirrors.clone() {
+            adapter.base_url = mirror.clone();
+            let file = (mirror.clone(), settings.layout.index_path(&html_path));
+            contents = downloader.save_file_any(&file, &schedule.index);
+            match &contents {
+                Ok(_contents) => break,
+                Err(error) => warn!(mirror, %error, "Base URL failed, trying the next mirror"),
+            }
+        }
+        let contents = contents?;
+        if Some(&adapter.base_url) != adapter.mirrors.first() {
+            say!("Using mirror {}", adapter.base_url);
+        }
+        config.mirror = Some(adapter.base_url.clone());
+
+        if settings.network.scheme == Scheme::Auto && downloader.is_upgraded(adapter.host()) {
+            if !adapter.base_url.starts_with("https://") {
+                say!("{} now enforces https", adapter.host());
+            }
+            adapter.set_scheme("https");
+            config.scheme = Some("https".to_string());
+        }
 
         /* Scraping */
         let html = Html::parse_document(&contents);
-        let selector = Selector::parse("a.page-numbers").unwrap();
-        let elements = html.select(&selector).collect::<Vec<_>>();
-        let element = elements[elements.len() - 2];
-        let texts = element.text().collect::<Vec<_>>();
-        let text = texts.first().expect("Failed to find text");
+        let max_pages = adapter.discover_max_pages(&html, |page| {
+            downloader.probe(&adapter.page_url(page), &schedule.pages)
+        })?;
+
+        let tally = Tally {
+            requested: 1,
+            succeeded: 1,
+            ..Tally::default()
+        };
+        summary.record(2, "Get max page number", tally);
 
-        text.replace(',', "").parse()?
+        max_pages
+    } else {
+        say!("Step 2: Getting max page number... (Skipped)");
+        config.max_pages
     };
+    let max_pages = settings
+        .limits
+        .max_pages
+        .map_or(max_pages, |limit| max_pages.min(limit));
+
+    /* Streaming, when step 3 would run anyway; the steps then only pick up what it missed */
+    let streamed = settings.pipeline.stream
+        && !args.offline
+        && args.enabled(3)
+        && (max_pages > config.max_pages || args.forced(3));
+    if streamed {
+        _span = step(&run, &mut summary, 3);
+        let ([pages, entries, torrents], saved) = stream::run(
+            &adapter,
+            &downloader,
+            &blocklist,
+            &mut config,
+            schedule,
+            &settings.limits,
+            &html_path,
+            &torrents_path,
+            max_pages,
+            settings.client.direct,
+        );
+        summary.record(3, "Stream pages", pages);
+        summary.record(5, "Stream entries", entries);
+        summary.record(7, "Stream torrents", torrents);
+
+        if let Some(rewriter) = Rewriter::new(&settings.trackers, &list_client)? {
+            rewriter.rewrite_all(&mut config, settings.output.checksum, saved)?;
+        }
+
+        config.validators = downloader.validators();
+        config.statuses = downloader.statuses();
+        config.save(base_path)?;
+    }
 
     /* Step 3 */
-    if max_pages > config.max_pages {
-        let pages = (1..=max_pages)
-            .map(|page| {
-                let url = format!("{BASE_URL}/page/{page}");
-                let path = format!("{base_path}/HTML/PAGES/{page}.HTML");
-                (url, path)
-            })
-            .collect();
+    _span = step(&run, &mut summary, 3);
+    let pages_saved =
+        !streamed && args.enabled(3) && (max_pages > config.max_pages || args.forced(3));
+    /* The deepest page this run fetched, which step 4 scrapes up to */
+    let mut walked = max_pages;
+    if args.offline && args.enabled(3) {
+        say!("Step 3: Saving {max_pages} pages to disk... (Cached, offline)");
+        let missing = (1..=max_pages)
+            .filter(|page| !downloader.is_cached(&settings.layout.page_path(&html_path, *page)))
+            .map(|page| adapter.page_url(page))
+            .collect::<Vec<_>>();
+        ensure_cached("pages", &missing)?;
+
+        let tally = Tally {
+            skipped: max_pages,
+            ..Tally::default()
+        };
+        summary.record(3, "Save pages", tally);
+        config.max_pages = max_pages;
+    } else if pages_saved {
+        let file = |page| {
+            let url = adapter.page_url(page);
+            let path = settings.layout.page_path(&html_path, page);
+            (url, path)
+        };
         let text = format!("Step 3: Saving {max_pages} pages to disk...");
-        save_files(&clients, pages, max_pages, text)?;
+        let tally = match settings.limits.stop_after_empty_pages {
+            Some(stop) if stop > 0 => {
+                /* Pages go a batch at a time, each checked against the known entries before going deeper */
+                let mut tally = Tally::default();
+                let mut empty = 0;
+                let mut first = 1;
+                while first <= max_pages && empty < stop {
+                    let last = (first + stop - 1).min(max_pages);
+                    let pages = (first..=last).map(file).collect();
+                    tally += downloader.save_files(
+                        pages,
+                        last - first + 1,
+                        text.clone(),
+                        &schedule.pages,
+                    )?;
+                    walked = last;
+
+                    for page in first..=last {
+                        let path = settings.layout.page_path(&html_path, page);
+                        /* A page that cannot be read may well hold new entries */
+                        let new = cache::read_to_string(&path).map_or(true, |contents| {
+                            adapter
+                                .entry_links(&Html::parse_document(&contents))
+                                .iter()
+                                .any(|link| config.entries.binary_search(link).is_err())
+                        });
+                        empty = match new {
+                            true => 0,
+                            false => empty + 1,
+                        };
+                    }
+                    first = last + 1;
+                }
+
+                if walked < max_pages {
+                    say!("Stopped at page {walked}, after {empty} pages in a row with no new entries");
+                    tally += Tally {
+                        requested: max_pages - walked,
+                        skipped: max_pages - walked,
+                        ..Tally::default()
+                    };
+                }
+
+                tally
+            }
+            _ => {
+                let pages = (1..=max_pages).map(file).collect();
+                downloader.save_files(pages, max_pages, text, &schedule.pages)?
+            }
+        };
+        summary.record(3, "Save pages", tally);
 
         config.max_pages = max_pages;
+        config.validators = downloader.validators();
+        config.statuses = downloader.statuses();
         config.save(base_path)?;
+    } else {
+        say!("Step 3: Saving {max_pages} pages to disk... (Skipped)");
+
+        let tally = Tally {
+            skipped: max_pages,
+            ..Tally::default()
+        };
+        summary.record(3, "Save pages", tally);
+    }
+
+    /* Step 4 */
+    _span = step(&run, &mut summary, 4);
+    /* Offline runs are for re-scraping, so they always scrape */
+    if args.enabled(4) && (pages_saved || args.forced(4) || args.offline) {
+        /* Pages past where step 3 stopped were not fetched, so there is nothing new to scrape on them */
+        let pages = (1..max_pages)
+            .map(|page| (page, settings.layout.page_path(&html_path, page)))
+            .filter(|(page, path)| {
+                *page <= walked
+                    && !(downloader.is_unchanged(path) && config.pages.contains_key(page))
+            })
+            .collect::<Vec<_>>();
+        let unchanged_pages = max_pages.saturating_sub(1) - pages.len();
 
-        /* Step 4 */
-        let mut bar = Bar::new(max_pages);
+        let requested = pages.len();
+        let mut bar = events::bar(requested);
         bar.write(format!("Step 4: Scraping {max_pages} pages for entries..."))?;
 
-        config.entries = (1..max_pages)
-            .into_par_iter()
-            .tqdm_with_bar(bar)
-            .map(|page| (format!("{base_path}/HTML/PAGES/{page}.HTML"), ".html"))
-            .map(scrape_files)
-            .filter_map(Result::ok)
-            .flatten()
-            .collect();
-
-        config.entries.sort();
-        config.entries.dedup();
+        /* Pages whose contents hash the same as when they were last scraped are not parsed again */
+        let hashes = config
+            .page_hashes
+            .iter()
+            .filter(|(page, _hash)| config.pages.contains_key(page))
+            .map(|(page, hash)| (*page, hash.clone()))
+            .collect::<HashMap<_, _>>();
+        let scrape = |(page, path): (usize, String)| {
+            let contents = cache::read_to_string(&path)?;
+            let hash = Algorithm::Blake3.digest(contents.as_bytes());
+            let links = match hashes.get(&page) == Some(&hash) && !args.offline {
+                true => None,
+                false => Some(adapter.entry_links(&Html::parse_document(&contents))),
+            };
+
+            anyhow::Ok((page, hash, links))
+        };
+
+        /* Results go into the state as they arrive, rather than all at once at the end */
+        let mut failed = 0;
+        let mut new_entries = 0;
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                pages
+                    .into_par_iter()
+                    .tqdm_with_bar(bar)
+                    .for_each_with(sender, |sender, page| {
+                        let _ = sender.send(scrape(page));
+                    })
+            });
+
+            for result in receiver {
+                let Ok((page, hash, links)) = result else {
+                    failed += 1;
+                    continue;
+                };
+                config.page_hashes.insert(page, hash);
+                let Some(links) = links else {
+                    continue;
+                };
+
+                let previous = config.pages.insert(page, links.clone()).unwrap_or_default();
+                if links.len() < previous.len() {
+                    warn!(
+                        page,
+                        previous = previous.len(),
+                        current = links.len(),
+                        "Page shrank unexpectedly"
+                    );
+                }
+
+                for link in links {
+                    if let Err(index) = config.entries.binary_search(&link) {
+                        config.introduce([&link]);
+                        config.entries.insert(index, link);
+                        new_entries += 1;
+                    }
+                }
+            }
+        });
+        say!("Found {new_entries} new entries");
+
+        let tally = Tally {
+            requested: requested + unchanged_pages,
+            succeeded: requested - failed,
+            failed,
+            skipped: unchanged_pages,
+        };
+        summary.record(4, "Scrape pages", tally);
+
+        config.validators = downloader.validators();
+        config.statuses = downloader.statuses();
         config.save(base_path)?;
     } else {
-        println!("Step 3: Saving {max_pages} pages to disk... (Skipped)");
-        println!("Step 4: Scraping {max_pages} pages for entries... (Skipped)");
+        say!("Step 4: Scraping {max_pages} pages for entries... (Skipped)");
+
+        let tally = Tally {
+            skipped: max_pages,
+            ..Tally::default()
+        };
+        summary.record(4, "Scrape pages", tally);
     }
 
     /* Step 5 */
+    _span = step(&run, &mut summary, 5);
+    /* Entries queued from the web UI; queueing an archived entry brings it back */
+    let queued = queue::load(base_path)?;
+    for entry in &queued {
+        config.archived.remove(entry);
+    }
+    let blocked = blocklist.apply(&mut config);
+    if blocked > 0 {
+        say!("Blocked {blocked} entries and torrents");
+    }
     let max_entries = config.entries.len();
-    let entries = config
+    let refresh = settings.limits.refresh_older_than.as_deref();
+    let refresh = refresh.map(retention::parse_age).transpose()?;
+    let recheck_dead = settings.limits.recheck_dead_after.as_deref();
+    let recheck_dead = match args.command {
+        Some(Command::RecheckDead) => Some(Duration::ZERO),
+        _ => recheck_dead.map(retention::parse_age).transpose()?,
+    };
+    let now = SystemTime::now();
+    /* Entries saved before fetch times were recorded are aged by their cached file */
+    let stale = |entry: &str, path: &str| {
+        let refresh = match config.dead.contains_key(entry) {
+            true => recheck_dead.or(refresh),
+            false => refresh,
+        };
+        let Some(refresh) = refresh else {
+            return false;
+        };
+        let fetched = config.fetched.get(entry).and_then(|fetched| fetched.at);
+        let fetched = fetched.map(SystemTime::from).or_else(|| {
+            cache::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        });
+
+        fetched
+            .and_then(|fetched| now.duration_since(fetched).ok())
+            .is_none_or(|age| age > refresh)
+    };
+    let mut entries = config
         .entries
         .iter()
-        .map(|entry| {
-            let url = format!("{BASE_URL}/{entry}");
-            let path = format!("{base_path}/HTML/ENTRIES/{entry}.HTML");
-            (url, path)
-        })
-        .filter(|(_url, path)| fs::metadata(path).is_err())
+        .filter(|entry| !config.tombstones.contains(*entry))
+        .filter(|entry| !config.archived.contains_key(*entry))
+        .filter(|entry| !config.blocked.contains_key(*entry))
+        .map(|entry| (entry, adapter.entry_path(&html_path, entry)))
+        .filter(|(entry, path)| !downloader.is_cached(path) || stale(entry, path))
+        .collect::<Vec<_>>();
+    if let Some(order) = settings.limits.order {
+        order::sort(&mut entries, &config, order, &run_id, |(entry, _path)| {
+            Some(*entry)
+        });
+    }
+    /* Queued entries go first, so a limit never leaves them out */
+    entries.sort_by_key(|(entry, _path)| !queued.contains(*entry));
+    let budget = settings.limits.budget.unwrap_or(usize::MAX);
+    let limit = settings.limits.max_entries.unwrap_or(usize::MAX);
+    let entries = entries
+        .into_iter()
+        .take(limit.min(budget))
+        .map(|(entry, path)| (entry.clone(), (adapter.entry_url(entry), path)))
         .collect::<Vec<_>>();
 
     let new_entries = entries.len();
-    if new_entries > 0 {
+    let entries_saved = args.enabled(5) && new_entries > 0;
+    if entries_saved && args.offline {
+        say!("Step 5: Saving {max_entries} entries to disk... (Cached, offline)");
+        let missing = entries
+            .iter()
+            .filter(|(_entry, (_url, path))| !downloader.is_cached(path))
+            .map(|(_entry, (url, _path))| url.clone())
+            .collect::<Vec<_>>();
+        ensure_cached("entries", &missing)?;
+
+        let tally = Tally {
+            skipped: max_entries,
+            ..Tally::default()
+        };
+        summary.record(5, "Save entries", tally);
+    } else if entries_saved {
+        let (names, entries) = entries.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+        let paths = entries
+            .iter()
+            .map(|(_url, path)| path.clone())
+            .collect::<Vec<_>>();
         let text = format!("Step 5: Saving {max_entries} entries to disk... ({new_entries})");
-        save_files(&clients, entries, new_entries, text)?;
+        let mut tally = downloader.save_files(entries, new_entries, text, &schedule.entries)?;
+        tally.skipped = max_entries - new_entries;
+        summary.record(5, "Save entries", tally);
+
+        let fetched_at = Utc::now();
+        for (entry, path) in names.into_iter().zip(paths) {
+            if downloader.is_fetched(&path) {
+                config.fetched.entry(entry).or_default().at = Some(fetched_at);
+            }
+        }
+    } else {
+        say!("Step 5: Saving {max_entries} entries to disk... (Skipped)");
+
+        let tally = Tally {
+            skipped: max_entries,
+            ..Tally::default()
+        };
+        summary.record(5, "Save entries", tally);
+    }
+
+    /* Step 6 */
+    _span = step(&run, &mut summary, 6);
+    if args.enabled(6) && (entries_saved || args.forced(6) || args.offline) {
+        let entries = config
+            .entries
+            .iter()
+            .filter(|entry| !config.tombstones.contains(*entry))
+            .map(|entry| (entry, adapter.entry_path(&html_path, entry)))
+            .filter(|(_entry, path)| downloader.is_cached(path))
+            .collect::<Vec<_>>();
+        let missing_entries = max_entries - entries.len();
 
-        /* Step 6 */
-        let mut bar = Bar::new(max_entries);
+        let requested = entries.len();
+        let mut bar = events::bar(requested);
         let text = format!("Step 6: Scraping {max_entries} entries for torrents...");
         bar.write(text)?;
 
-        config.torrents = config
-            .entries
-            .par_iter()
-            .tqdm_with_bar(bar)
-            .map(|entry| (format!("{base_path}/HTML/ENTRIES/{entry}.HTML"), ".torrent"))
-            .map(scrape_files)
-            .filter_map(Result::ok)
-            .flatten()
-            .collect();
-
-        config.torrents.sort();
-        config.torrents.dedup();
+        /* A page that has not changed since it was last scraped keeps what it gave then */
+        let hashes = entries
+            .iter()
+            .filter(|(entry, _path)| config.metadata.contains_key(*entry))
+            .filter(|(entry, _path)| config.links.contains_key(*entry))
+            .filter_map(|(entry, _path)| {
+                let hash = config.fetched.get(*entry)?.hash.clone()?;
+
+                Some(((*entry).clone(), hash))
+            })
+            .collect::<HashMap<_, _>>();
+        let entries = entries
+            .into_iter()
+            .map(|(entry, path)| (entry.clone(), path))
+            .collect::<Vec<_>>();
+        let scrape = |(entry, path): (String, String)| {
+            let contents = cache::read_to_string(&path)?;
+            let hash = Algorithm::Blake3.digest(contents.as_bytes());
+            let scraped = match hashes.get(&entry) == Some(&hash) && !args.offline {
+                true => None,
+                false => {
+                    let html = Html::parse_document(&contents);
+                    Some((adapter.torrent_links(&html), Metadata::scrape(&html)))
+                }
+            };
+
+            anyhow::Ok((entry, hash, scraped))
+        };
+
+        /* Results go into the state as they arrive, rather than all at once at the end */
+        let mut failed = 0;
+        let mut torrents = BTreeSet::new();
+        let mut unlinked = Vec::new();
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                entries
+                    .into_par_iter()
+                    .tqdm_with_bar(bar)
+                    .for_each_with(sender, |sender, entry| {
+                        let _ = sender.send(scrape(entry));
+                    })
+            });
+
+            for result in receiver {
+                let Ok((entry, hash, scraped)) = result else {
+                    failed += 1;
+                    continue;
+                };
+                config.fetched.entry(entry.clone()).or_default().hash = Some(hash);
+                if let Some((links, metadata)) = scraped {
+                    config.links.insert(entry.clone(), links);
+                    config.metadata.insert(entry.clone(), metadata);
+                }
+
+                match config.links.get(&entry).filter(|links| !links.is_empty()) {
+                    Some(links) => torrents.extend(links.iter().cloned()),
+                    None => unlinked.push(entry),
+                }
+            }
+        });
+
+        if settings.browser.enabled && args.offline && !unlinked.is_empty() {
+            let mut missing = Vec::new();
+            for entry in unlinked {
+                let path = settings.layout.rendered_path(&html_path, &entry);
+                match cache::read_to_string(&path) {
+                    Ok(contents) => {
+                        let links = adapter.torrent_links(&Html::parse_document(&contents));
+                        torrents.extend(links.iter().cloned());
+                        config.links.insert(entry, links);
+                    }
+                    Err(_error) => missing.push(adapter.entry_url(&entry)),
+                }
+            }
+            ensure_cached("rendered entries", &missing)?;
+        } else if settings.browser.enabled && !unlinked.is_empty() {
+            let renderer = Renderer::new(
+                &settings.browser,
+                &settings.network.user_agent,
+                settings.disk.compression,
+            )?;
+            say!(
+                "Rendering {} entries without torrent links...",
+                unlinked.len()
+            );
+
+            for entry in unlinked {
+                let url = adapter.entry_url(&entry);
+                let path = settings.layout.rendered_path(&html_path, &entry);
+                match renderer.fetch(&url, &path) {
+                    Ok(contents) => {
+                        let links = adapter.torrent_links(&Html::parse_document(&contents));
+                        torrents.extend(links.iter().cloned());
+                        config.links.insert(entry, links);
+                    }
+                    Err(error) => warn!(url, %error, "Failed to render entry"),
+                }
+            }
+        }
+
+        let tally = Tally {
+            requested: max_entries,
+            succeeded: requested - failed,
+            failed,
+            skipped: missing_entries,
+        };
+        summary.record(6, "Scrape entries", tally);
+
+        config.introduce(&torrents);
+        config.torrents = torrents.into_iter().collect();
+        config.validators = downloader.validators();
+        config.statuses = downloader.statuses();
         config.save(base_path)?;
     } else {
-        println!("Step 5: Saving {max_entries} entries to disk... (Skipped)");
-        println!("Step 6: Scraping {max_entries} entries for torrents... (Skipped)");
+        say!("Step 6: Scraping {max_entries} entries for torrents... (Skipped)");
+
+        let tally = Tally {
+            skipped: max_entries,
+            ..Tally::default()
+        };
+        summary.record(6, "Scrape entries", tally);
+    }
+
+    if let Some(template) = &settings.layout.path_template {
+        placement::place(&adapter, &mut config, &torrents_path, template);
+        adapter.placements = config.placements.clone();
+    }
+
+    /* Descriptions and images, for entries missing any of them */
+    if settings.media.enabled && args.enabled(6) && !args.offline {
+        let mut descriptions = 0;
+        let mut images = Vec::new();
+        let mut recorded = BTreeMap::new();
+        for entry in config
+            .entries
+            .iter()
+            .filter(|entry| !config.tombstones.contains(*entry))
+        {
+            let files = config.media.get(entry);
+            if files.is_some_and(|files| files.iter().all(|file| Path::new(file).exists())) {
+                continue;
+            }
+
+            let dir = config
+                .links
+                .get(entry)
+                .and_then(|links| links.first())
+                .and_then(|url| adapter.torrent_path(&torrents_path, url))
+                .and_then(|path| Some(Path::new(&path).parent()?.to_path_buf()));
+            let Some(dir) = dir else {
+                continue;
+            };
+            let stem = adapter.sanitizer.component(entry.trim_end_matches(".html"));
+            let nfo = dir.join(format!("{stem}.NFO"));
+            let Ok(contents) = cache::read_to_string(&adapter.entry_path(&html_path, entry)) else {
+                continue;
+            };
+
+            let media = Media::scrape(&Html::parse_document(&contents), &adapter.base_url);
+            let title = config
+                .metadata
+                .get(entry)
+                .map_or(entry.as_str(), |m| &m.title);
+            if !nfo.exists() {
+                fs::create_dir_all(&dir)?;
+                fs::write(&nfo, media.nfo(title, &adapter.entry_url(entry)))?;
+                descriptions += 1;
+            }
+
+            let mut files = vec![nfo.to_string_lossy().into_owned()];
+            for (index, url) in media.images.into_iter().enumerate() {
+                let name = format!("{stem}.{}.{}", index + 1, media::extension(&url));
+                let path = dir.join(name).to_string_lossy().into_owned();
+                if !Path::new(&path).exists() {
+                    images.push((url, path.clone()));
+                }
+                files.push(path);
+            }
+            recorded.insert(entry.clone(), files);
+        }
+        if !recorded.is_empty() {
+            config.media.extend(recorded);
+            config.save(base_path)?;
+        }
+
+        if descriptions > 0 {
+            say!("Archived {descriptions} entry descriptions");
+        }
+        if !images.is_empty() {
+            let total = images.len();
+            let text = format!("Saving {total} entry images...");
+            let tally = downloader.save_files(images, total, text, &schedule.entries)?;
+            summary.record(6, "Save media", tally);
+        }
     }
 
     /* Step 7 */
-    let regex = Regex::new(r"^https://d\.ptorrents\.com/(.+)/\[ptorrents.com\]\.(.+)\.torrent$")?;
+    _span = step(&run, &mut summary, 7);
+    /* Titles are known by now, so entries blocked by title lose their torrents too */
+    let blocked = blocklist.apply(&mut config);
+    if blocked > 0 {
+        say!("Blocked {blocked} entries and torrents");
+    }
+    let mut sources = Sources::new();
+    xref::index(&config, &mut sources);
+    for archive in &settings.xref.archives {
+        match Config::load(archive) {
+            Ok(other) => xref::index(&other, &mut sources),
+            Err(error) => warn!(archive, %error, "Failed to load archive for cross-reference"),
+        }
+    }
+    config.sources = sources;
+
+    let dead = seeders::apply(&mut config, settings.limits.min_seeders);
+    if dead > 0 {
+        say!("Skipping the torrents of {dead} entries with too few seeders");
+    }
+    let families = releases::apply(&mut config, &settings.duplicates);
+    if families > 0 {
+        say!(
+            "Found {families} release families with duplicates, skipping {} entries",
+            config.superseded.len()
+        );
+    }
+    let dead = seeders::dead_torrents(&config);
+    let superseded = releases::superseded_torrents(&config);
+    let archived = archive::archived_torrents(&config);
+    let wanted = queued
+        .iter()
+        .filter_map(|entry| config.links.get(entry))
+        .flatten()
+        .collect::<BTreeSet<_>>();
+
+    let direct = settings.client.direct;
     let max_torrents = config.torrents.len();
-    let torrents = config
+    let mut torrents = config
         .torrents
-        .into_iter()
+        .iter()
+        .filter(|haystack| !config.blocked.contains_key(*haystack))
+        /* Queued torrents were asked for by name, so only the blocklist holds them back */
+        .filter(|haystack| {
+            wanted.contains(haystack)
+                || !(dead.contains(*haystack) || superseded.contains(*haystack))
+        })
+        .filter(|haystack| !archived.contains(*haystack))
         .filter_map(|haystack| {
-            let Some(captures) = regex.captures(&haystack) else {
-                return None;
-            };
+            let path = adapter.torrent_path(&torrents_path, haystack)?;
 
-            let Some(path) = captures.get(1).map(|m| m.as_str()) else {
-                return None;
-            };
+            Some((haystack.clone(), path))
+        })
+        .filter(|(url, path)| match direct {
+            true => !config.sent.contains(url),
+            false => fs::metadata(path).is_err(),
+        })
+        .collect::<Vec<_>>();
+    let owners = order::owners(&config);
+    if let Some(order) = settings.limits.order {
+        order::sort(&mut torrents, &config, order, &run_id, |(url, _path)| {
+            owners.get(url).copied()
+        });
+    }
+    torrents.sort_by_key(|(url, _path)| !wanted.contains(url));
+    /* The budget counts entries, so the torrents of an entry are taken together */
+    let budget = order::budget(&torrents, budget, |(url, _path)| owners.get(url).copied());
+    let limit = settings.limits.max_torrents.unwrap_or(usize::MAX);
+    torrents.truncate(limit.min(budget));
 
-            let Some(name) = captures.get(2).map(|m| m.as_str()) else {
-                return None;
-            };
+    let torrents = match args.interactive && args.enabled(7) && !torrents.is_empty() {
+        true => {
+            let titles = config
+                .links
+                .iter()
+                .flat_map(|(entry, links)| links.iter().map(move |url| (url, entry)))
+                .filter_map(|(url, entry)| Some((url, &config.metadata.get(entry)?.title)))
+                .collect::<HashMap<_, _>>();
+            let items = torrents
+                .into_iter()
+                .map(|(url, path)| {
+                    let name = adapter.torrent_name(&url).unwrap_or(&url);
+                    let label = match titles.get(&url) {
+                        Some(title) => format!("{title}  ({name})"),
+                        None => name.to_string(),
+                    };
+                    (label, (url, path))
+                })
+                .collect();
 
-            let path = format!("{base_path}/TORRENT/{path}/{name}.TORRENT");
+            select::pick(items)?
+        }
+        false => torrents,
+    };
 
-            Some((haystack, path))
-        })
-        .filter(|(_url, path)| fs::metadata(path).is_err())
-        .collect::<Vec<_>>();
+    /* Only torrents saved to disk take up space there */
+    let torrents = match args.enabled(7) && !args.offline && !direct && !torrents.is_empty() {
+        true => {
+            let max_usage = settings.disk.max_usage.as_deref();
+            let max_usage = max_usage.map(throttle::parse_bytes).transpose()?;
+            quota::fit(
+                &config,
+                &downloader,
+                &schedule.torrents,
+                base_path,
+                max_usage,
+                torrents,
+            )?
+        }
+        false => torrents,
+    };
 
     let new_torrents = torrents.len();
-    if new_torrents > 0 {
+    let mut arrived = Vec::new();
+    if args.enabled(7) && new_torrents > 0 && args.offline {
+        let missing = torrents
+            .into_iter()
+            .map(|(url, _path)| url)
+            .collect::<Vec<_>>();
+        ensure_cached("torrents", &missing)?;
+    } else if args.enabled(7) && new_torrents > 0 && direct {
+        let text =
+            format!("Step 7: Sending {max_torrents} torrents to the client... ({new_torrents})");
+        let client = TorrentClient::new(&settings.client)?;
+        let urls = torrents.into_iter().map(|(url, _path)| url).collect();
+        let (mut tally, sent) = client.send(urls, text)?;
+        tally.skipped = max_torrents - new_torrents;
+        summary.record(7, "Send torrents", tally);
+
+        arrived.extend(sent.iter().map(|url| (url.clone(), None)));
+        config.sent.extend(sent);
+        config.save(base_path)?;
+    } else if args.enabled(7) && new_torrents > 0 {
         let text = format!("Step 7: Saving {max_torrents} torrents to disk... ({new_torrents})");
-        save_files(&clients, torrents, new_torrents, text)?;
+        let files = torrents.clone();
+        let mut tally = downloader.save_files(files, new_torrents, text, &schedule.torrents)?;
+        tally.skipped = max_torrents - new_torrents;
+
+        /* Same content from another entry or archive, when this link did not work */
+        let missing = torrents
+            .iter()
+            .filter(|(_url, path)| fs::metadata(path).is_err())
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut recovered = 0;
+        for (url, path) in missing {
+            for alternative in xref::alternatives(&config.sources, &url) {
+                let local = adapter.torrent_path(&torrents_path, &alternative);
+                let saved = match local.filter(|local| Path::new(local).exists()) {
+                    Some(local) => fs::copy(local, &path).is_ok(),
+                    None => {
+                        let file = (alternative.clone(), path.clone());
+                        downloader.save_file_any(&file, &schedule.torrents).is_ok()
+                    }
+                };
+
+                if saved {
+                    info!(url, alternative, "Saved torrent from alternative source");
+                    recovered += 1;
+                    break;
+                }
+            }
+        }
+        if recovered > 0 {
+            say!("Recovered {recovered} torrents from alternative sources");
+        }
+        tally.succeeded += recovered;
+        tally.failed = tally.failed.saturating_sub(recovered);
+        summary.record(7, "Save torrents", tally);
+
+        let saved = torrents
+            .into_iter()
+            .filter(|(_url, path)| Path::new(path).exists())
+            .map(|(url, path)| (url, Some(path)));
+        arrived.extend(saved);
+
+        if let Some(rewriter) = Rewriter::new(&settings.trackers, &list_client)? {
+            let torrents = arrived
+                .iter()
+                .filter_map(|(url, path)| Some((url.clone(), path.clone()?)))
+                .collect();
+            rewriter.rewrite_all(&mut config, settings.output.checksum, torrents)?;
+        }
+
+        let entries = config
+            .links
+            .iter()
+            .flat_map(|(entry, links)| links.iter().map(move |url| (url, entry)))
+            .collect::<HashMap<_, _>>();
+        let saved = arrived
+            .iter()
+            .filter_map(|(url, path)| {
+                let entry = entries.get(url).copied();
+                Some(hooks::Saved {
+                    url,
+                    path: path.as_deref()?,
+                    entry: entry.map(String::as_str),
+                    metadata: entry.and_then(|entry| config.metadata.get(entry)),
+                })
+            })
+            .collect::<Vec<_>>();
+        hooks::run(&settings.hooks, &saved)?;
+
+        config.validators = downloader.validators();
+        config.statuses = downloader.statuses();
+        config.save(base_path)?;
     } else {
-        println!("Step 7: Saving {max_torrents} torrents to disk... (Skipped)");
+        say!("Step 7: Saving {max_torrents} torrents to disk... (Skipped)");
+
+        let tally = Tally {
+            skipped: max_torrents,
+            ..Tally::default()
+        };
+        summary.record(7, "Save torrents", tally);
     }
 
-    Ok(())
-}
+    /* Queued entries leave the queue once all their torrents are in */
+    if !queued.is_empty() {
+        let done = queued
+            .iter()
+            .filter(|entry| {
+                let links = config.links.get(*entry).filter(|links| !links.is_empty());
+                links.is_some_and(|links| {
+                    links.iter().all(|url| {
+                        config.sent.contains(url)
+                            || adapter
+                                .torrent_path(&torrents_path, url)
+                                .is_some_and(|path| Path::new(&path).exists())
+                    })
+                })
+            })
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        queue::settle(base_path, &done)?;
+    }
+
+    if notifier.per_torrent() && !arrived.is_empty() {
+        let titles = config
+            .links
+            .iter()
+            .flat_map(|(entry, links)| links.iter().map(move |url| (url, entry)))
+            .filter_map(|(url, entry)| Some((url, config.metadata.get(entry)?.title.as_str())))
+            .collect::<HashMap<_, _>>();
 
-fn check_proxy((client, proxy): (Client, String)) -> Option<Client> {
-    lazy_static! {
-        static ref LOCAL_TEXT: String = reqwest::blocking::get(ADDR_URL).unwrap().text().unwrap();
+        for (url, path) in &arrived {
+            notifier.notify(&Event::NewTorrent {
+                url,
+                title: titles.get(url).copied().unwrap_or_default(),
+                path: path.as_deref(),
+            });
+        }
     }
 
-    let Ok(remote_response) = client.get(ADDR_URL).send() else {
-        eprintln!("Failed to get response {proxy}");
-        return None;
-    };
+    let algorithm = settings.output.checksum;
+    let checksums = config
+        .torrents
+        .par_iter()
+        .filter_map(|torrent| adapter.torrent_path(&torrents_path, torrent))
+        .filter(|path| {
+            let checksum = config.checksums.get(path);
+            checksum.and_then(|checksum| Algorithm::of(checksum)) != Some(algorithm)
+        })
+        .filter_map(|path| {
+            let bytes = fs::read(&path).ok()?;
+            let size = bytes.len() as u64;
+            Some((path, algorithm.digest(&bytes), size))
+        })
+        .collect::<Vec<_>>();
 
-    let Ok(remote_text) = remote_response.text() else {
-        eprintln!("Failed to get response {proxy}");
-        return None;
-    };
+    let infohashes = config
+        .torrents
+        .par_iter()
+        .filter(|url| !config.infohashes.contains_key(*url))
+        .filter_map(|url| {
+            let path = adapter.torrent_path(&torrents_path, url)?;
+            let bytes = fs::read(path).ok()?;
+            let info_hash = bencode::info_hash(&bytes).ok()?;
+            Some((url.clone(), info_hash))
+        })
+        .collect::<Vec<_>>();
 
-    if remote_text == LOCAL_TEXT.as_str() {
-        eprintln!("Failed to connect {proxy}");
-        return None;
+    if !checksums.is_empty() || !infohashes.is_empty() {
+        if !checksums.is_empty() {
+            say!(
+                "Hashed {} torrents with {}",
+                checksums.len(),
+                algorithm.name()
+            );
+        }
+        for (path, checksum, size) in checksums {
+            config.sizes.insert(path.clone(), size);
+            config.checksums.insert(path, checksum);
+        }
+        config.infohashes.extend(infohashes);
+        config.save(base_path)?;
     }
 
-    Some(client)
-}
+    let transferred = downloader.throttle.bytes();
+    if transferred > 0 {
+        say!(
+            "Transferred {} at {}/s",
+            throttle::format_bytes(transferred as f64),
+            throttle::format_bytes(downloader.throttle.speed())
+        );
+    }
 
-type File = (String, String);
-fn save_files(clients: &Vec<Client>, files: Vec<File>, total: usize, text: String) -> Result<()> {
-    let queue = ArrayQueue::new(total);
-    let _ = files.into_par_iter().try_for_each(|msg| queue.push(msg));
+    let mut health = downloader.health().into_iter().collect::<Vec<_>>();
+    health.sort_by(|(_, a), (_, b)| a.score().total_cmp(&b.score()));
+    for (address, health) in health.iter().filter(|(_, health)| health.timeouts > 0) {
+        warn!(
+            exit = address,
+            timeouts = health.timeouts,
+            score = health.score(),
+            "Exit timed out"
+        );
+    }
 
-    let mut bar = Bar::new(total);
-    bar.desc = clients.len().to_string();
-    bar.write(text)?;
+    if let Some(metrics_file) = &settings.output.metrics_file {
+        let mut metrics = Metrics::default();
 
-    clients
-        .into_par_iter()
-        .for_each_with(bar, move |bar, client| {
-            while let Some(msg) = queue.pop() {
-                let _ = bar.update_to(total - queue.len());
+        metrics.family(
+            "torrents_proxies",
+            "gauge",
+            "Proxies that passed validation",
+        );
+        metrics.sample("torrents_proxies", &[], max_proxies);
+        metrics.family("torrents_exits", "gauge", "Distinct exit addresses in use");
+        metrics.sample("torrents_exits", &[], downloader.exits().len());
+        metrics.family("torrents_pages", "gauge", "Listing pages known");
+        metrics.sample("torrents_pages", &[], config.max_pages);
+        metrics.family("torrents_entries", "gauge", "Entries known");
+        metrics.sample("torrents_entries", &[], config.entries.len());
+        metrics.family("torrents_torrents", "gauge", "Torrent links known");
+        metrics.sample("torrents_torrents", &[], config.torrents.len());
 
-                if let Err(error) = save_file(client, &msg) {
-                    eprintln!("{error}");
+        metrics.family(
+            "torrents_requests_total",
+            "counter",
+            "Requests by exit and result",
+        );
+        for (address, health) in &health {
+            let results = [
+                ("success", health.successes),
+                ("failure", health.failures),
+                ("timeout", health.timeouts),
+            ];
+            for (result, value) in results {
+                let labels = [("exit", address.as_str()), ("result", result)];
+                metrics.sample("torrents_requests_total", &labels, value);
+            }
+        }
 
-                    queue.push(msg).unwrap();
-                }
+        metrics.family(
+            "torrents_responses_total",
+            "counter",
+            "Responses by HTTP status",
+        );
+        for (status, value) in downloader.responses() {
+            let status = status.to_string();
+            metrics.sample("torrents_responses_total", &[("status", &status)], value);
+        }
+
+        metrics.family(
+            "torrents_transferred_bytes_total",
+            "counter",
+            "Response bytes received",
+        );
+        metrics.sample("torrents_transferred_bytes_total", &[], transferred);
+
+        metrics.family(
+            "torrents_step_items_total",
+            "counter",
+            "Items by step and result",
+        );
+        for (step, _name, tally) in summary.steps() {
+            let step = step.to_string();
+            let results = [
+                ("succeeded", tally.succeeded),
+                ("failed", tally.failed),
+                ("skipped", tally.skipped),
+            ];
+            for (result, value) in results {
+                let labels = [("step", step.as_str()), ("result", result)];
+                metrics.sample("torrents_step_items_total", &labels, value);
             }
-        });
+        }
 
-    Ok(())
-}
+        metrics.family("torrents_exit_health_score", "gauge", "Exit health score");
+        for (address, health) in &health {
+            let labels = [("exit", address.as_str())];
+            metrics.sample("torrents_exit_health_score", &labels, health.score());
+        }
 
-fn save_file(client: &Client, (url, path): &File) -> Result<String> {
-    let contents = get_text(client, url)?;
+        metrics.family("torrents_run_info", "gauge", "Run that wrote these metrics");
+        metrics.sample("torrents_run_info", &[("run_id", run_id.as_str())], 1);
 
-    if let Some(file_name) = Path::new(&path).file_name().and_then(OsStr::to_str) {
-        let directory_path = path.replace(file_name, "");
-        fs::create_dir_all(directory_path)?;
-    };
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        metrics.family(
+            "torrents_last_run_timestamp_seconds",
+            "gauge",
+            "End of the last run",
+        );
+        metrics.sample("torrents_last_run_timestamp_seconds", &[], timestamp);
+
+        metrics.write(metrics_file)?;
+    }
+
+    drop(dashboard);
+    if !events::json() {
+        summary.print();
+    }
+    let (coverage, _kinds, _years) = stats::coverage(&adapter, &config, &html_path, &torrents_path);
+    if coverage.entries > 0 {
+        say!(
+            "Coverage: {} of {} entries fully downloaded ({:.1}%)",
+            coverage.downloaded,
+            coverage.entries,
+            coverage.downloaded as f64 * 100.0 / coverage.entries as f64
+        );
+    }
+
+    let dead_letters = downloader.dead_letters();
+    if !dead_letters.is_empty() {
+        say!(
+            "Gave up on {} files, listed under dead_letters",
+            dead_letters.len()
+        );
+    }
+    config.dead_letters = dead_letters;
+
+    summary.finish();
+    let history = RunRecord::new(&run_id, started, &summary, &health, transferred);
+    if let Err(error) = history::record(base_path, &history) {
+        warn!(%error, "Failed to record the run in the history");
+    }
 
-    fs::write(path, &contents)?;
+    let total = summary.total();
+    info!(
+        requested = total.requested,
+        succeeded = total.succeeded,
+        failed = total.failed,
+        skipped = total.skipped,
+        "Finished run"
+    );
+    config.last_run = Some(LastRun {
+        run_id: run_id.clone(),
+        started: Some(started),
+        finished: Some(Utc::now()),
+        total,
+    });
+    config.save(base_path)?;
+
+    notifier.notify(&Event::RunCompleted {
+        total,
+        summary: summary.render(),
+    });
+
+    let max_failure_rate = settings.retry.max_failure_rate;
+    if total.failed as f64 > max_failure_rate * total.requested as f64 {
+        bail!(
+            "{} of {} requested items failed, above the {:.0}% threshold",
+            total.failed,
+            total.requested,
+            max_failure_rate * 100.0
+        );
+    }
 
-    Ok(contents)
+    Ok(())
 }
 
-fn scrape_files((path, pat): (String, &str)) -> Result<Vec<String>> {
-    lazy_static! {
-        static ref SELECTOR: Selector = Selector::parse("a[href]").unwrap();
+fn host_address(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((host, address)) => Ok((host.to_string(), address.to_string())),
+        None => Err(format!("expected host=address, got {text}")),
     }
+}
 
-    let contents = fs::read_to_string(path)?;
-    let html = Html::parse_document(&contents);
-    let links = html
-        .select(&SELECTOR)
-        .filter_map(|e| e.value().attr("href"))
-        .map(String::from)
-        .filter(|s| s.ends_with(pat))
-        .map(|s| s.replace(BASE_URL, ""))
-        .collect();
+/* Offline runs stop at the first step that would have to fetch something */
+fn ensure_cached(kind: &str, missing: &[String]) -> Result<()> {
+    if let Some(first) = missing.first() {
+        bail!(
+            "Offline, but {} {kind} are not cached, starting with {first}",
+            missing.len()
+        );
+    }
 
-    Ok(links)
+    Ok(())
 }
 
-fn get_text(client: &Client, url: &str) -> Result<String> {
-    let iterable = Exponential::from_millis(100).map(jitter).take(10);
-    let operation = |_| client.get(url).send();
-    let response = retry::retry_with_index(iterable, operation)?;
-    let text = response.text()?;
+fn step(run: &Span, summary: &mut Summary, step: usize) -> EnteredSpan {
+    let span = info_span!(parent: run, "step", step).entered();
+    info!(step, "Starting step");
+    summary.start(step);
 
-    Ok(text)
+    span
 }
@@ -0,0 +1,86 @@
+use lazy_static::lazy_static;
+use scraper::{Html, Selector};
+
+/* Description and images of an entry page, archived beside its torrents */
+#[derive(Debug, Default)]
+pub struct Media {
+    pub description: String,
+    pub images: Vec<String>,
+}
+
+impl Media {
+    pub fn scrape(html: &Html, base_url: &str) -> Self {
+        lazy_static! {
+            static ref PARAGRAPHS: Selector =
+                Selector::parse(".entry-content p, article p, .post p").unwrap();
+            static ref META: Selector =
+                Selector::parse("meta[name=\"description\"], meta[property=\"og:description\"]")
+                    .unwrap();
+            static ref IMAGES: Selector =
+                Selector::parse(".entry-content img[src], article img[src], .post img[src]")
+                    .unwrap();
+            static ref OG_IMAGE: Selector = Selector::parse("meta[property=\"og:image\"]").unwrap();
+        }
+
+        let mut paragraphs = html
+            .select(&PARAGRAPHS)
+            .map(|e| e.text().collect::<String>().trim().to_string())
+            .filter(|paragraph| !paragraph.is_empty())
+            .collect::<Vec<_>>();
+        paragraphs.dedup();
+        let description = match paragraphs.is_empty() {
+            true => html
+                .select(&META)
+                .find_map(|e| e.value().attr("content"))
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            false => paragraphs.join("\n\n"),
+        };
+
+        let sources = html
+            .select(&IMAGES)
+            .filter_map(|e| e.value().attr("src"))
+            .chain(
+                html.select(&OG_IMAGE)
+                    .filter_map(|e| e.value().attr("content")),
+            );
+        let mut images = Vec::new();
+        for url in sources.filter_map(|src| absolute(base_url, src)) {
+            if !images.contains(&url) {
+                images.push(url);
+            }
+        }
+
+        Self {
+            description,
+            images,
+        }
+    }
+
+    /* Markdown, which reads fine as a plain .NFO too */
+    pub fn nfo(&self, title: &str, url: &str) -> String {
+        format!("# {title}\n\n{}\n\n<{url}>\n", self.description)
+    }
+}
+
+fn absolute(base_url: &str, src: &str) -> Option<String> {
+    let src = src.trim();
+
+    match src {
+        _ if src.starts_with("https://") || src.starts_with("http://") => Some(src.to_string()),
+        _ if src.starts_with("//") => Some(format!("https:{src}")),
+        _ if src.starts_with('/') => Some(format!("{base_url}{src}")),
+        _ => None,
+    }
+}
+
+/* The image's own extension when it has a usual one */
+pub fn extension(url: &str) -> &'static str {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+
+    ["jpg", "jpeg", "png", "gif", "webp", "avif"]
+        .into_iter()
+        .find(|extension| path.ends_with(&format!(".{extension}")))
+        .unwrap_or("jpg")
+}
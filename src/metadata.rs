@@ -0,0 +1,157 @@
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    ValueEnum,
+    Deserialize,
+    Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Movie,
+    Tv,
+    Software,
+    Music,
+    Book,
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Metadata {
+    pub title: String,
+    pub tags: Vec<String>,
+    pub language: Option<String>,
+    pub kind: Kind,
+    /* Peer counts the entry page showed when it was scraped, if it shows them */
+    pub seeders: Option<u64>,
+    pub leechers: Option<u64>,
+    /* The date the entry page gives for the release, which --order falls back to for entries gone from the listing */
+    pub published: Option<NaiveDate>,
+}
+
+impl Metadata {
+    pub fn scrape(html: &Html) -> Self {
+        lazy_static! {
+            static ref TITLE: Selector = Selector::parse("h1, title").unwrap();
+            static ref TAGS: Selector = Selector::parse("a[rel~=\"tag\"]").unwrap();
+            static ref SEEDERS: Regex =
+                Regex::new(r"(?i)\bseed(?:er)?s?\b\s*:?\s*(\d[\d,]*)").unwrap();
+            static ref LEECHERS: Regex =
+                Regex::new(r"(?i)\bleech(?:er)?s?\b\s*:?\s*(\d[\d,]*)").unwrap();
+            static ref PUBLISHED: Selector = Selector::parse(
+                "meta[property=\"article:published_time\"], meta[itemprop=\"datePublished\"], time[datetime]"
+            )
+            .unwrap();
+        }
+
+        let title = html
+            .select(&TITLE)
+            .next()
+            .map(|e| e.text().collect::<String>().trim().to_string())
+            .unwrap_or_default();
+
+        let mut tags = html
+            .select(&TAGS)
+            .map(|e| e.text().collect::<String>().trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect::<Vec<_>>();
+        tags.sort();
+        tags.dedup();
+
+        /* Only the day is kept, from "2024-03-01" or a full timestamp */
+        let published = html
+            .select(&PUBLISHED)
+            .filter_map(|e| e.value().attr("content").or(e.value().attr("datetime")))
+            .find_map(|date| NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok());
+
+        let haystack = format!("{title} {}", tags.join(" "));
+        let text = html.root_element().text().collect::<String>();
+
+        Self {
+            language: language(&haystack),
+            kind: kind(&haystack),
+            seeders: count(&SEEDERS, &text),
+            leechers: count(&LEECHERS, &text),
+            published,
+            title,
+            tags,
+        }
+    }
+}
+
+/* Reads "Seeders: 1,204" and the like */
+fn count(regex: &Regex, text: &str) -> Option<u64> {
+    let captures = regex.captures(text)?;
+
+    captures.get(1)?.as_str().replace(',', "").parse().ok()
+}
+
+fn kind(haystack: &str) -> Kind {
+    lazy_static! {
+        static ref KINDS: [(Kind, Regex); 5] = [
+            (Kind::Tv, Regex::new(r"(?i)\bS\d{1,2}E\d{1,3}\b|\bseason\b|\btv\b|\bseries\b").unwrap()),
+            (Kind::Movie, Regex::new(r"(?i)\bmovies?\b|\bfilm\b|\b(480|720|1080|2160)p\b|\bblu-?ray\b|\b(web|dvd|hd|br)rip\b|\bx26[45]\b").unwrap()),
+            (Kind::Music, Regex::new(r"(?i)\bmusic\b|\bmp3\b|\bflac\b|\balbum\b|\bdiscography\b|\bost\b").unwrap()),
+            (Kind::Book, Regex::new(r"(?i)\be-?books?\b|\bbooks?\b|\bepub\b|\bpdf\b|\bmobi\b|\baudiobook\b").unwrap()),
+            (Kind::Software, Regex::new(r"(?i)\bsoftware\b|\bwindows\b|\bmacos\b|\blinux\b|\bportable\b|\bx64\b|\bx86\b|\bapps?\b|\bgames?\b").unwrap()),
+        ];
+    }
+
+    KINDS
+        .iter()
+        .find(|(_kind, regex)| regex.is_match(haystack))
+        .map(|(kind, _regex)| *kind)
+        .unwrap_or_default()
+}
+
+fn language(haystack: &str) -> Option<String> {
+    lazy_static! {
+        static ref LANGUAGES: [(&'static str, Regex); 10] = [
+            (
+                "multi",
+                Regex::new(r"(?i)\bmulti\b|\bmultilingual\b").unwrap()
+            ),
+            ("english", Regex::new(r"(?i)\beng(lish)?\b").unwrap()),
+            (
+                "french",
+                Regex::new(r"(?i)\bfr(ench)?\b|\bvostfr\b|\btruefrench\b").unwrap()
+            ),
+            (
+                "german",
+                Regex::new(r"(?i)\bger(man)?\b|\bdeutsch\b").unwrap()
+            ),
+            (
+                "spanish",
+                Regex::new(r"(?i)\bspa(nish)?\b|\bespa[nñ]ol\b|\bcastellano\b").unwrap()
+            ),
+            ("italian", Regex::new(r"(?i)\bita(lian)?\b").unwrap()),
+            ("russian", Regex::new(r"(?i)\brus(sian)?\b").unwrap()),
+            (
+                "portuguese",
+                Regex::new(r"(?i)\bportuguese\b|\bpt-?br\b").unwrap()
+            ),
+            ("japanese", Regex::new(r"(?i)\bjap(anese)?\b").unwrap()),
+            ("hindi", Regex::new(r"(?i)\bhindi\b").unwrap()),
+        ];
+    }
+
+    LANGUAGES
+        .iter()
+        .find(|(_language, regex)| regex.is_match(haystack))
+        .map(|(language, _regex)| language.to_string())
+}
@@ -0,0 +1,50 @@
+use std::{fmt::Display, fs, path::Path};
+
+use anyhow::Result;
+
+/* Prometheus text exposition, e.g. for node_exporter's textfile collector; each family is declared under the exact name its samples use */
+#[derive(Debug, Default)]
+pub struct Metrics(String);
+
+impl Metrics {
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        self.0 += &format!("# TYPE {name} {kind}\n# HELP {name} {help}\n");
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| format!("{key}=\"{}\"", escape(value)))
+            .collect::<Vec<_>>();
+
+        match labels.is_empty() {
+            true => self.0 += &format!("{name} {value}\n"),
+            false => self.0 += &format!("{name}{{{}}} {value}\n", labels.join(",")),
+        }
+    }
+
+    /* Appends another exposition, e.g. a textfile written by a run */
+    pub fn extend(&mut self, text: &str) {
+        self.0 += text.trim_end_matches("# EOF\n");
+    }
+
+    pub fn render(&self) -> String {
+        format!("{}# EOF\n", self.0)
+    }
+
+    /* The collector may read at any moment, so never expose a half written file */
+    pub fn write(&self, path: &str) -> Result<()> {
+        let temp_path = format!("{path}.tmp");
+        fs::write(&temp_path, self.render())?;
+        fs::rename(&temp_path, Path::new(path))?;
+
+        Ok(())
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,161 @@
+use std::{process::Command, time::Duration};
+
+use anyhow::{bail, Result};
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::{settings::NotifySettings, summary::Tally};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /* Carries "content" and "text" as well, which is what Discord and Slack read */
+    #[default]
+    Json,
+    /* ntfy publishes the plain body, with the title in a header */
+    Ntfy,
+}
+
+pub enum Event<'a> {
+    RunCompleted {
+        total: Tally,
+        summary: String,
+    },
+    NewTorrent {
+        url: &'a str,
+        title: &'a str,
+        path: Option<&'a str>,
+    },
+}
+
+impl Event<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::RunCompleted { .. } => "run_completed",
+            Self::NewTorrent { .. } => "new_torrent",
+        }
+    }
+
+    fn title(&self) -> String {
+        match self {
+            Self::RunCompleted { total, .. } => format!(
+                "Run finished: {} succeeded, {} failed",
+                total.succeeded, total.failed
+            ),
+            Self::NewTorrent { title, .. } => format!("New torrent: {title}"),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Self::RunCompleted { summary, .. } => format!("{}\n```\n{summary}```", self.title()),
+            Self::NewTorrent { url, .. } => format!("{}\n{url}", self.title()),
+        }
+    }
+
+    /* Event fields, as JSON keys and, upper-cased with a TORRENTS_ prefix, as environment variables */
+    fn fields(&self, run_id: &str) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("event", self.name().to_string()),
+            ("run_id", run_id.to_string()),
+        ];
+
+        match self {
+            Self::RunCompleted { total, .. } => fields.extend([
+                ("requested", total.requested.to_string()),
+                ("succeeded", total.succeeded.to_string()),
+                ("failed", total.failed.to_string()),
+                ("skipped", total.skipped.to_string()),
+            ]),
+            Self::NewTorrent { url, title, path } => fields.extend([
+                ("url", url.to_string()),
+                ("title", title.to_string()),
+                ("path", path.unwrap_or_default().to_string()),
+            ]),
+        }
+
+        fields
+    }
+}
+
+/* Failures are logged and never fail the run, which has already done its work */
+pub struct Notifier<'a> {
+    settings: &'a NotifySettings,
+    run_id: &'a str,
+    http: Client,
+}
+
+impl<'a> Notifier<'a> {
+    pub fn new(settings: &'a NotifySettings, run_id: &'a str) -> Result<Self> {
+        let http = Client::builder().timeout(Duration::from_secs(30)).build()?;
+
+        Ok(Self {
+            settings,
+            run_id,
+            http,
+        })
+    }
+
+    pub fn per_torrent(&self) -> bool {
+        self.settings.per_torrent
+    }
+
+    pub fn notify(&self, event: &Event) {
+        if let Some(webhook) = &self.settings.webhook {
+            if let Err(error) = self.post(webhook, event) {
+                warn!(webhook, event = event.name(), %error, "Failed to call webhook");
+            }
+        }
+
+        if let Some(command) = &self.settings.command {
+            if let Err(error) = self.run(command, event) {
+                warn!(command, event = event.name(), %error, "Failed to run notify command");
+            }
+        }
+    }
+
+    fn post(&self, webhook: &str, event: &Event) -> Result<()> {
+        let request = match self.settings.format {
+            WebhookFormat::Json => {
+                let mut payload = json!({
+                    "content": event.text(),
+                    "text": event.text(),
+                });
+                for (key, value) in event.fields(self.run_id) {
+                    payload[key] = Value::from(value);
+                }
+
+                self.http.post(webhook).json(&payload)
+            }
+            WebhookFormat::Ntfy => self
+                .http
+                .post(webhook)
+                .header("Title", event.title())
+                .body(event.text()),
+        };
+
+        request.send()?.error_for_status()?;
+
+        Ok(())
+    }
+
+    fn run(&self, command: &str, event: &Event) -> Result<()> {
+        let envs = event
+            .fields(self.run_id)
+            .into_iter()
+            .map(|(key, value)| (format!("TORRENTS_{}", key.to_uppercase()), value));
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(envs)
+            .status()?;
+
+        if !status.success() {
+            bail!("Command exited with {status}");
+        }
+
+        Ok(())
+    }
+}
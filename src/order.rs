@@ -0,0 +1,167 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use xxhash_rust::xxh3::{xxh3_64, xxh3_64_with_seed};
+
+use crate::{config::Config, releases};
+
+/* The order steps 5 and 7 work through entries in, so a limit or --budget picks which ones a run gets to */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Order {
+    NewestFirst,
+    /* Backfills the catalog from its far end */
+    OldestFirst,
+    /* Shuffled afresh every run */
+    Random,
+}
+
+/* Sorts items by the entry each belongs to; items of no entry go last, in the order they came */
+pub fn sort<T>(
+    items: &mut [T],
+    config: &Config,
+    order: Order,
+    run_id: &str,
+    entry: impl Fn(&T) -> Option<&String>,
+) {
+    let listed = releases::listed(config);
+    /* Page 1 lists the newest entries first, and entries gone from the listing fall back to the date on their page */
+    let age = |entry: &String| {
+        let position = listed.get(entry).copied().unwrap_or((usize::MAX, 0));
+        let published = config.metadata.get(entry).and_then(|m| m.published);
+
+        (position, Reverse(published))
+    };
+    let seed = xxh3_64(run_id.as_bytes());
+
+    match order {
+        Order::NewestFirst => items.sort_by_key(|item| {
+            let age = entry(item).map(&age);
+            (age.is_none(), age)
+        }),
+        Order::OldestFirst => items.sort_by_key(|item| {
+            let age = entry(item).map(&age);
+            (age.is_none(), age.map(Reverse))
+        }),
+        Order::Random => items.sort_by_key(|item| {
+            let hash = entry(item).map(|entry| xxh3_64_with_seed(entry.as_bytes(), seed));
+            (hash.is_none(), hash)
+        }),
+    }
+}
+
+/* How many of items fit a budget of whole entries, with items of no entry counting as one each */
+pub fn budget<T>(items: &[T], budget: usize, entry: impl Fn(&T) -> Option<&String>) -> usize {
+    let mut entries = HashSet::new();
+    let mut spent = 0;
+    items
+        .iter()
+        .take_while(|item| {
+            spent += usize::from(entry(item).is_none_or(|entry| entries.insert(entry)));
+            spent <= budget
+        })
+        .count()
+}
+
+/* The entry each torrent was scraped from, the first one when several link to it */
+pub fn owners(config: &Config) -> HashMap<&String, &String> {
+    let mut owners = HashMap::new();
+    for (entry, links) in &config.links {
+        for url in links {
+            owners.entry(url).or_insert(entry);
+        }
+    }
+
+    owners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /* Page 1 lists b then a, page 2 lists c; d is on no page */
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.pages.insert(1, entries(&["b", "a"]));
+        config.pages.insert(2, entries(&["c"]));
+
+        config
+    }
+
+    fn sorted(order: Order, run_id: &str) -> Vec<String> {
+        let config = config();
+        let mut items = entries(&["a", "b", "c", "d"]);
+        sort(&mut items, &config, order, run_id, |item| Some(item));
+
+        items
+    }
+
+    #[test]
+    fn sorts_by_listing_position() {
+        assert_eq!(sorted(Order::NewestFirst, "run"), ["b", "a", "c", "d"]);
+        assert_eq!(sorted(Order::OldestFirst, "run"), ["d", "c", "a", "b"]);
+    }
+
+    #[test]
+    fn shuffles_the_same_way_within_a_run() {
+        let shuffled = sorted(Order::Random, "run");
+
+        assert_eq!(shuffled, sorted(Order::Random, "run"));
+        let mut all = shuffled.clone();
+        all.sort();
+        assert_eq!(all, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn puts_items_of_no_entry_last() {
+        let config = config();
+        let owners = entries(&["a", "b"]);
+        let mut items = vec![(None, 1), (Some(&owners[0]), 2), (Some(&owners[1]), 3)];
+        sort(&mut items, &config, Order::NewestFirst, "run", |item| {
+            item.0
+        });
+
+        assert_eq!(
+            items.iter().map(|item| item.1).collect::<Vec<_>>(),
+            [3, 2, 1]
+        );
+    }
+
+    #[test]
+    fn budgets_whole_entries() {
+        let owners = entries(&["a", "b"]);
+        let items = [
+            Some(&owners[0]),
+            Some(&owners[0]),
+            Some(&owners[1]),
+            None,
+            None,
+        ];
+        let fit = |n| budget(&items, n, |item| *item);
+
+        assert_eq!(fit(0), 0);
+        assert_eq!(fit(1), 2);
+        assert_eq!(fit(2), 3);
+        assert_eq!(fit(3), 4);
+        assert_eq!(fit(usize::MAX), 5);
+    }
+
+    #[test]
+    fn finds_the_owner_of_each_torrent() {
+        let mut config = Config::default();
+        config.links.insert("a".to_string(), entries(&["t1", "t2"]));
+        config.links.insert("b".to_string(), entries(&["t2"]));
+        let owners = owners(&config);
+
+        assert_eq!(owners.get(&"t1".to_string()).map(|e| e.as_str()), Some("a"));
+        assert_eq!(owners.get(&"t2".to_string()).map(|e| e.as_str()), Some("a"));
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::Write,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use tar::{Builder, Header};
+
+use crate::{
+    adapter::Adapter,
+    bencode::{self, Value},
+    cache,
+    config::Config,
+    metadata::{Kind, Metadata},
+};
+
+/* One archived torrent, described the same way in index.json and index.bencode */
+#[derive(Debug, Serialize)]
+struct Item {
+    file: String,
+    title: String,
+    infohash: String,
+    category: Kind,
+    date: Option<DateTime<Utc>>,
+    url: String,
+}
+
+impl Item {
+    fn to_bencode(&self) -> Value {
+        let mut dict = BTreeMap::new();
+        let mut insert = |key: &str, value: &str| {
+            let value = Value::Bytes(value.as_bytes().to_vec());
+            dict.insert(key.as_bytes().to_vec(), value);
+        };
+
+        let category = format!("{:?}", self.category).to_lowercase();
+        insert("file", &self.file);
+        insert("title", &self.title);
+        insert("infohash", &self.infohash);
+        insert("category", &category);
+        insert("url", &self.url);
+        if let Some(date) = self.date {
+            insert("date", &date.to_rfc3339());
+        }
+
+        Value::Dict(dict)
+    }
+}
+
+/* A tar of the selected .torrent files plus an index, handed off as is; gzipped when output ends in .gz */
+pub fn pack(
+    adapter: &Adapter,
+    config: &Config,
+    html_path: &str,
+    torrents_path: &str,
+    kind: Option<Kind>,
+    query: Option<&str>,
+    output: &str,
+) -> Result<()> {
+    let query = query.map(str::to_lowercase);
+    let default = Metadata::default();
+
+    let mut items = Vec::new();
+    let mut seen = HashSet::new();
+    for entry in &config.entries {
+        let metadata = config.metadata.get(entry).unwrap_or(&default);
+        if kind.is_some_and(|kind| kind != metadata.kind) {
+            continue;
+        }
+        if let Some(query) = &query {
+            if !metadata.title.to_lowercase().contains(query) {
+                continue;
+            }
+        }
+
+        let date = cache::metadata(&adapter.entry_path(html_path, entry))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(DateTime::<Utc>::from);
+
+        for url in config.links.get(entry).into_iter().flatten() {
+            let Some(path) = adapter.torrent_path(torrents_path, url) else {
+                continue;
+            };
+            let Ok(bytes) = fs::read(&path) else {
+                continue;
+            };
+            let infohash = match config.infohashes.get(url) {
+                Some(infohash) => infohash.clone(),
+                None => match bencode::info_hash(&bytes) {
+                    Ok(infohash) => infohash,
+                    Err(_) => continue,
+                },
+            };
+
+            /* The same content linked from several entries goes in once */
+            if !seen.insert(infohash.clone()) {
+                continue;
+            }
+
+            let name = adapter.torrent_name(url).unwrap_or(&infohash);
+            let item = Item {
+                file: format!("torrents/{infohash}/{name}.torrent"),
+                title: metadata.title.clone(),
+                infohash,
+                category: metadata.kind,
+                date,
+                url: url.clone(),
+            };
+            items.push((item, bytes));
+        }
+    }
+
+    let file = File::create(output)?;
+    match output.ends_with(".gz") {
+        true => {
+            let encoder = GzEncoder::new(file, flate2::Compression::default());
+            write(encoder, &items)?.finish()?.sync_all()?;
+        }
+        false => write(file, &items)?.sync_all()?,
+    }
+
+    println!("Packed {} torrents into {output}", items.len());
+
+    Ok(())
+}
+
+fn write<W: Write>(writer: W, items: &[(Item, Vec<u8>)]) -> Result<W> {
+    let mut builder = Builder::new(writer);
+
+    let index = items.iter().map(|(item, _bytes)| item).collect::<Vec<_>>();
+    let json = serde_json::to_vec_pretty(&index)?;
+    let list = Value::List(index.iter().map(|item| item.to_bencode()).collect());
+    let bencode = bencode::encode(&list);
+
+    let files = [
+        ("index.json", json.as_slice()),
+        ("index.bencode", bencode.as_slice()),
+    ];
+    let torrents = items
+        .iter()
+        .map(|(item, bytes)| (item.file.as_str(), bytes.as_slice()));
+    for (name, bytes) in files.into_iter().chain(torrents) {
+        let mut header = Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, bytes)?;
+    }
+
+    Ok(builder.into_inner()?)
+}
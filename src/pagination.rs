@@ -0,0 +1,96 @@
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::{Html, Selector};
+use tracing::warn;
+
+/* Probing stops here, far beyond any real listing, in case the site answers every page */
+const MAX_PAGES: usize = 1 << 20;
+
+/* The highest page number in the listing markup, from numbered links or /page/N style hrefs */
+pub fn from_markup(html: &Html, page_numbers: &Selector) -> Option<usize> {
+    lazy_static! {
+        static ref SELECTORS: [Selector; 3] = [
+            Selector::parse(".pagination a, .nav-links a, .wp-pagenavi a").unwrap(),
+            Selector::parse("a[href]").unwrap(),
+            Selector::parse("a.last, a[title~=\"Last\"]").unwrap(),
+        ];
+        static ref HREF: Regex = Regex::new(r"[/?&]page[/=](\d+)").unwrap();
+    }
+
+    let texts = std::iter::once(page_numbers)
+        .chain(&SELECTORS[..1])
+        .flat_map(|selector| html.select(selector))
+        .filter_map(|element| {
+            let text = element.text().collect::<String>().replace(',', "");
+            text.trim().parse::<usize>().ok()
+        });
+    let hrefs = SELECTORS[1..]
+        .iter()
+        .flat_map(|selector| html.select(selector))
+        .filter_map(|element| element.value().attr("href"))
+        .filter_map(|href| HREF.captures(href)?.get(1)?.as_str().parse::<usize>().ok());
+
+    texts.chain(hrefs).max()
+}
+
+fn has_next(html: &Html) -> bool {
+    lazy_static! {
+        static ref NEXT: Selector =
+            Selector::parse("link[rel~=\"next\"], a[rel~=\"next\"], a.next").unwrap();
+    }
+
+    html.select(&NEXT).next().is_some()
+}
+
+/* Falls back to probing page URLs when the markup gives no page count */
+pub fn discover(
+    html: &Html,
+    page_numbers: &Selector,
+    exists: impl FnMut(usize) -> Result<bool>,
+) -> Result<usize> {
+    if let Some(max_pages) = from_markup(html, page_numbers) {
+        return Ok(max_pages);
+    }
+
+    let start = match has_next(html) {
+        true => 2,
+        false => 1,
+    };
+    warn!(
+        start,
+        "No page numbers in the index, probing for the last page"
+    );
+
+    search(start, exists).context(
+        "Failed to find the max page number: the index has no page numbers or page links, \
+         and probing page URLs did not find the last page",
+    )
+}
+
+/* Doubles until a page is missing, then bisects between the last page found and the first missing one */
+fn search(start: usize, mut exists: impl FnMut(usize) -> Result<bool>) -> Result<usize> {
+    if !exists(start)? {
+        bail!("Page {start} does not exist");
+    }
+
+    let mut low = start;
+    let mut high = start * 2;
+    while exists(high)? {
+        low = high;
+        high *= 2;
+        if high > MAX_PAGES {
+            bail!("Every page up to {low} exists");
+        }
+    }
+
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        match exists(middle)? {
+            true => low = middle,
+            false => high = middle,
+        }
+    }
+
+    Ok(low)
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashSet, path::Path};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{adapter::Adapter, config::Config, metadata::Metadata};
+
+/* Paths from a template such as "{category}/{year}/{title}" for torrents not on disk yet; recorded once, so rescrapes never move files */
+pub fn place(adapter: &Adapter, config: &mut Config, torrents_path: &str, template: &str) {
+    let mut used = config.placements.values().cloned().collect::<HashSet<_>>();
+    let default = Metadata::default();
+
+    let mut placements = Vec::new();
+    for (entry, links) in &config.links {
+        let metadata = config.metadata.get(entry).unwrap_or(&default);
+        for url in links {
+            if config.placements.contains_key(url) {
+                continue;
+            }
+            match adapter.torrent_path(torrents_path, url) {
+                Some(path) if Path::new(&path).exists() => continue,
+                Some(_path) => {}
+                None => continue,
+            }
+
+            let name = adapter.torrent_name(url).unwrap_or(entry);
+            let base = match render(adapter, template, entry, name, metadata) {
+                base if base.is_empty() => adapter.sanitizer.component(name),
+                base => base,
+            };
+
+            /* Several torrents of one entry share its title, so later ones are numbered */
+            let mut relative = base.clone();
+            let mut index = 1;
+            while !used.insert(relative.clone()) {
+                index += 1;
+                relative = format!("{base} ({index})");
+            }
+            placements.push((url.clone(), relative));
+        }
+    }
+
+    config.placements.extend(placements);
+}
+
+fn render(
+    adapter: &Adapter,
+    template: &str,
+    entry: &str,
+    name: &str,
+    metadata: &Metadata,
+) -> String {
+    lazy_static! {
+        static ref YEAR: Regex = Regex::new(r"\b(19[0-9]{2}|20[0-9]{2})\b").unwrap();
+    }
+
+    let category = format!("{:?}", metadata.kind).to_lowercase();
+    let year = YEAR
+        .find(&metadata.title)
+        .map_or("unknown", |year| year.as_str());
+    let title = match metadata.title.trim() {
+        "" => name,
+        title => title,
+    };
+    let language = metadata.language.as_deref().unwrap_or("unknown");
+    let entry = entry.trim_end_matches(".html");
+
+    /* Each value is one path component, so a slash in a title cannot add directories */
+    template
+        .split('/')
+        .map(|component| {
+            let component = component
+                .replace("{category}", &category)
+                .replace("{year}", year)
+                .replace("{title}", title)
+                .replace("{language}", language)
+                .replace("{entry}", entry)
+                .replace("{name}", name);
+
+            adapter.sanitizer.component(&component)
+        })
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
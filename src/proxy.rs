@@ -0,0 +1,418 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::events::say;
+
+const ADDR_URL: &str = "https://api.seeip.org";
+/* Plain http, as a proxy cannot add headers to a tunnelled https request */
+const ECHO_URL: &str = "http://httpbin.org/headers";
+/* Headers a proxy adds to say the request went through it */
+const PROXY_HEADERS: [&str; 7] = [
+    "via",
+    "forwarded",
+    "x-forwarded-for",
+    "x-real-ip",
+    "x-proxy-id",
+    "client-ip",
+    "proxy-connection",
+];
+
+/* Weight of the newest request in the moving averages */
+const RECENT: f64 = 0.2;
+/* Even an exit failing everything lately keeps a trickle of requests, to notice when it recovers */
+const MIN_SHARE: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Anonymity {
+    /* Passes the local address on */
+    Transparent,
+    /* Hides the local address, but says it is a proxy */
+    Anonymous,
+    /* Looks like a direct connection */
+    Elite,
+}
+
+impl Anonymity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Transparent => "transparent",
+            Self::Anonymous => "anonymous",
+            Self::Elite => "elite",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Health {
+    pub successes: usize,
+    pub failures: usize,
+    pub timeouts: usize,
+    /* Moving averages over the last several requests, so an exit that turns bad or slow loses traffic quickly */
+    pub recent_failures: f64,
+    pub recent_latency: Option<Duration>,
+}
+
+impl Health {
+    pub fn record(&mut self, saved: bool, timed_out: bool, latency: Duration) {
+        match (saved, timed_out) {
+            (true, _) => self.successes += 1,
+            (false, true) => self.timeouts += 1,
+            (false, false) => self.failures += 1,
+        }
+
+        let failed = if saved { 0.0 } else { 1.0 };
+        self.recent_failures += RECENT * (failed - self.recent_failures);
+        self.recent_latency = Some(match self.recent_latency {
+            Some(recent) => recent.mul_f64(1.0 - RECENT) + latency.mul_f64(RECENT),
+            None => latency,
+        });
+    }
+
+    /* Share of requests the exit gets, by success rate overall and lately, against its latency */
+    pub fn weight(&self, checked: Duration) -> f64 {
+        let latency = self.recent_latency.unwrap_or(checked).as_secs_f64();
+        let recent = (1.0 - self.recent_failures).max(MIN_SHARE);
+
+        self.score() * recent / (1.0 + latency)
+    }
+
+    /* Timeouts tie up a worker far longer than a refused request, so they weigh double */
+    pub fn score(&self) -> f64 {
+        let attempts = self.successes + self.failures + 2 * self.timeouts;
+
+        (self.successes + 1) as f64 / (attempts + 1) as f64
+    }
+}
+
+/* Many proxies share one exit address, so the exit is the unit that gets rate limited and banned */
+#[derive(Debug, Clone)]
+pub struct Exit {
+    pub address: String,
+    pub client: Client,
+    pub latency: Duration,
+    pub aliases: Vec<String>,
+    pub labels: Vec<String>,
+}
+
+/* One line of a proxy list, e.g. "1.2.3.4:8080 # slow, DE" */
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub proxy: String,
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub proxy: String,
+    pub labels: Vec<String>,
+    pub latency: Duration,
+    pub outcome: Result<(Client, String), String>,
+    /* Unknown when the proxy is dead or the echo request failed */
+    pub anonymity: Option<Anonymity>,
+}
+
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    proxy: &'a str,
+    status: &'a str,
+    exit: &'a str,
+    anonymity: &'a str,
+    labels: String,
+    latency_ms: u128,
+    error: &'a str,
+}
+
+/* Each source is a file, a directory of files or a URL fetched through client, any of them optionally gzipped */
+pub fn load_lists(sources: &[String], client: &Client) -> Result<Vec<Listing>> {
+    let mut lists = Vec::new();
+
+    for source in sources {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            let response = client.get(source).send()?.error_for_status()?;
+            let list = response.bytes()?.to_vec();
+            lists.push((source.clone(), list));
+        } else if Path::new(source).is_dir() {
+            let mut paths = fs::read_dir(source)?
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .collect::<Vec<_>>();
+            paths.sort();
+
+            for path in paths {
+                let list = fs::read(&path)?;
+                lists.push((path.display().to_string(), list));
+            }
+        } else {
+            lists.push((source.clone(), fs::read(source)?));
+        }
+    }
+
+    let mut listings = Vec::<Listing>::new();
+    let mut seen = BTreeMap::new();
+    for (source, list) in lists {
+        let text = match list.starts_with(&[0x1f, 0x8b]) {
+            true => {
+                let mut text = String::new();
+                GzDecoder::new(list.as_slice()).read_to_string(&mut text)?;
+                text
+            }
+            false => String::from_utf8_lossy(&list).into_owned(),
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let (proxy, comment) = line.split_once('#').unwrap_or((line, ""));
+            let proxy = proxy.trim();
+            if proxy.is_empty() {
+                continue;
+            }
+
+            if let Err(error) = reqwest::Proxy::all(proxy) {
+                warn!(source, line = number + 1, proxy, %error, "Skipping invalid proxy");
+                continue;
+            }
+
+            let labels = comment
+                .split(',')
+                .map(str::trim)
+                .filter(|label| !label.is_empty())
+                .map(String::from);
+
+            /* The same proxy listed by several sources keeps every label it was given */
+            let index = *seen.entry(proxy.to_string()).or_insert_with(|| {
+                listings.push(Listing {
+                    proxy: proxy.to_string(),
+                    labels: Vec::new(),
+                });
+                listings.len() - 1
+            });
+            let listing = &mut listings[index];
+            listing.labels.extend(labels);
+            listing.labels.sort();
+            listing.labels.dedup();
+        }
+    }
+
+    Ok(listings)
+}
+
+/* What the address echo answers without a proxy, which a working proxy must hide */
+pub fn local_address(client: &Client) -> Result<String> {
+    let response = client.get(ADDR_URL).send()?.error_for_status()?;
+
+    Ok(response.text()?)
+}
+
+/* local is what local_address answered */
+pub fn check(
+    Listing { proxy, labels }: Listing,
+    client: reqwest::Result<Client>,
+    local: &str,
+) -> Check {
+    let start = Instant::now();
+    let outcome = client
+        .map_err(|error| error.to_string())
+        .and_then(|client| {
+            let remote_response = client
+                .get(ADDR_URL)
+                .send()
+                .map_err(|error| format!("Failed to get response: {error}"))?;
+
+            let remote_text = remote_response
+                .text()
+                .map_err(|error| format!("Failed to get response: {error}"))?;
+
+            if remote_text == local {
+                return Err("Failed to connect: local address leaked".to_string());
+            }
+
+            Ok((client, remote_text.trim().to_string()))
+        });
+
+    let latency = start.elapsed();
+    let anonymity = outcome.as_ref().ok().and_then(|(client, _address)| {
+        classify(client, local.trim())
+            .map_err(|error| debug!(proxy, error, "Failed to classify proxy anonymity"))
+            .ok()
+    });
+    match &outcome {
+        Ok((_client, address)) => debug!(
+            proxy,
+            address,
+            latency_ms = latency.as_millis() as u64,
+            "Proxy alive"
+        ),
+        Err(error) => debug!(
+            proxy,
+            error,
+            latency_ms = latency.as_millis() as u64,
+            "Proxy dead"
+        ),
+    }
+
+    Check {
+        proxy,
+        labels,
+        latency,
+        outcome,
+        anonymity,
+    }
+}
+
+/* By what the echo endpoint saw of the request */
+fn classify(client: &Client, local: &str) -> Result<Anonymity, String> {
+    let echo = client
+        .get(ECHO_URL)
+        .send()
+        .and_then(|response| response.text())
+        .map_err(|error| format!("Failed to get echo: {error}"))?
+        .to_ascii_lowercase();
+
+    let anonymity = if !local.is_empty() && echo.contains(local) {
+        Anonymity::Transparent
+    } else if PROXY_HEADERS
+        .iter()
+        .any(|header| echo.contains(&format!("\"{header}\"")))
+    {
+        Anonymity::Anonymous
+    } else {
+        Anonymity::Elite
+    };
+
+    Ok(anonymity)
+}
+
+/* Fails live proxies below min, or whose anonymity is unknown */
+pub fn require_anonymity(checks: &mut [Check], min: Anonymity) {
+    for check in checks.iter_mut().filter(|check| check.outcome.is_ok()) {
+        if check.anonymity.is_none_or(|anonymity| anonymity < min) {
+            let level = check.anonymity.map_or("unknown", Anonymity::name);
+            check.outcome = Err(format!("Anonymity {level} is below {}", min.name()));
+        }
+    }
+}
+
+pub fn print_summary(checks: &[Check]) {
+    let mut alive = checks
+        .iter()
+        .filter_map(|check| match &check.outcome {
+            Ok((_client, address)) => Some((check, address)),
+            Err(_error) => None,
+        })
+        .collect::<Vec<_>>();
+    alive.sort_by_key(|(check, _address)| check.latency);
+
+    say!(
+        "{:>10}  {:<40}  {:<40}  {:<11}  LABELS",
+        "LATENCY",
+        "PROXY",
+        "EXIT",
+        "ANONYMITY"
+    );
+    for (check, address) in &alive {
+        let latency = format!("{}ms", check.latency.as_millis());
+        let anonymity = check.anonymity.map_or("unknown", Anonymity::name);
+        let labels = check.labels.join(", ");
+        say!(
+            "{latency:>10}  {:<40}  {address:<40}  {anonymity:<11}  {labels}",
+            check.proxy
+        );
+    }
+
+    let median = alive
+        .get(alive.len() / 2)
+        .map(|(check, _address)| check.latency.as_millis())
+        .unwrap_or_default();
+    say!(
+        "Alive: {}, Dead: {}, Median latency: {median}ms",
+        alive.len(),
+        checks.len() - alive.len()
+    );
+}
+
+pub fn write_report(checks: &[Check], path: &str) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    for check in checks {
+        let (status, exit, error) = match &check.outcome {
+            Ok((_client, address)) => ("alive", address.as_str(), ""),
+            Err(error) => ("dead", "", error.as_str()),
+        };
+
+        writer.serialize(Row {
+            proxy: &check.proxy,
+            status,
+            exit,
+            anonymity: check.anonymity.map_or("", Anonymity::name),
+            labels: check.labels.join(";"),
+            latency_ms: check.latency.as_millis(),
+            error,
+        })?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/* The machine's own connection, as an exit alongside or instead of the proxies */
+pub fn direct(client: Client) -> Exit {
+    Exit {
+        address: "direct".to_string(),
+        client,
+        latency: Duration::ZERO,
+        aliases: Vec::new(),
+        labels: vec!["direct".to_string()],
+    }
+}
+
+pub fn group_by_exit(checks: Vec<Check>) -> Vec<Exit> {
+    let mut exits = BTreeMap::<String, Exit>::new();
+
+    for Check {
+        proxy,
+        labels,
+        latency,
+        outcome,
+        ..
+    } in checks
+    {
+        let Ok((client, address)) = outcome else {
+            continue;
+        };
+
+        let exit = exits.entry(address.clone()).or_insert_with(|| Exit {
+            address,
+            client: client.clone(),
+            latency,
+            aliases: Vec::new(),
+            labels: Vec::new(),
+        });
+
+        /* Keep whichever alias reached the exit fastest */
+        if latency < exit.latency {
+            exit.client = client;
+            exit.latency = latency;
+        }
+
+        exit.aliases.push(proxy);
+        exit.labels.extend(labels);
+        exit.labels.sort();
+        exit.labels.dedup();
+    }
+
+    exits.into_values().collect()
+}
@@ -0,0 +1,74 @@
+use std::{
+    collections::BTreeSet,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use fs2::FileExt;
+
+use crate::config::Config;
+
+/* Entries queued from the web UI, kept next to the state as TORRENTS.QUEUE, one per line */
+pub fn path(base_path: &str) -> Result<PathBuf> {
+    Ok(Config::get_path(base_path)?.with_extension("QUEUE"))
+}
+
+pub fn load(base_path: &str) -> Result<BTreeSet<String>> {
+    let path = path(base_path)?;
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+
+    let mut file = File::open(path)?;
+    file.lock_shared()?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+
+    Ok(parse(&text))
+}
+
+fn parse(text: &str) -> BTreeSet<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/* Appends, so queueing never has to wait for a run holding the state lock, only for a settle in progress */
+pub fn push(base_path: &str, entry: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path(base_path)?)?;
+    file.lock_exclusive()?;
+    writeln!(file, "{entry}")?;
+
+    Ok(())
+}
+
+/* Drops the entries a run finished, under the file's lock so an entry queued meanwhile is never lost; the file stays, as another process may be waiting on its lock */
+pub fn settle(base_path: &str, done: &BTreeSet<String>) -> Result<()> {
+    let path = path(base_path)?;
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    file.lock_exclusive()?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    let pending = parse(&text)
+        .difference(done)
+        .map(|entry| format!("{entry}\n"))
+        .collect::<String>();
+
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(pending.as_bytes())?;
+    file.sync_all()?;
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use anyhow::{bail, Result};
+use walkdir::WalkDir;
+
+use crate::{
+    config::Config,
+    download::{Downloader, File},
+    events::say,
+    settings::Policy,
+    throttle::format_bytes,
+};
+
+/* Torrents asked for their size when none have been saved yet */
+const SAMPLE: usize = 5;
+
+/* Assumed when neither saved torrents nor the sample tell */
+const FALLBACK: u64 = 64 << 10;
+
+/* Expected size of a torrent: the mean of those already saved, or of a sample of the batch */
+fn estimate(config: &Config, downloader: &Downloader, torrents: &[File], policy: &Policy) -> u64 {
+    if !config.sizes.is_empty() {
+        return config.sizes.values().sum::<u64>() / config.sizes.len() as u64;
+    }
+
+    let sizes = torrents
+        .iter()
+        .take(SAMPLE)
+        .filter_map(|(url, _path)| downloader.content_length(url, policy).ok().flatten())
+        .collect::<Vec<_>>();
+    match sizes.is_empty() {
+        true => FALLBACK,
+        false => sizes.iter().sum::<u64>() / sizes.len() as u64,
+    }
+}
+
+/* Bytes taken by every file under path */
+fn usage(path: &str) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/* Fails when the batch cannot fit in the free space under base_path, and cuts it to what fits under the quota */
+pub fn fit(
+    config: &Config,
+    downloader: &Downloader,
+    policy: &Policy,
+    base_path: &str,
+    max_usage: Option<u64>,
+    mut torrents: Vec<File>,
+) -> Result<Vec<File>> {
+    let size = estimate(config, downloader, &torrents, policy).max(1);
+    let required = size * torrents.len() as u64;
+
+    let available = fs2::available_space(base_path)?;
+    if required > available {
+        bail!(
+            "Step 7 needs about {} for {} torrents, but only {} is free under {base_path}",
+            format_bytes(required as f64),
+            torrents.len(),
+            format_bytes(available as f64)
+        );
+    }
+
+    if let Some(max_usage) = max_usage {
+        let used = usage(base_path);
+        let fits = (max_usage.saturating_sub(used) / size) as usize;
+        if fits < torrents.len() {
+            say!(
+                "Disk quota: {} of {} used, so only {fits} of {} torrents are fetched",
+                format_bytes(used as f64),
+                format_bytes(max_usage as f64),
+                torrents.len()
+            );
+            torrents.truncate(fits);
+        }
+    }
+
+    Ok(torrents)
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use kdam::rayon::{prelude::*, ThreadPoolBuilder};
+use reqwest::{blocking::Client, Proxy};
+use tracing::{info, warn};
+
+use crate::{
+    download::Downloader,
+    proxy::{self, Anonymity, Health, Listing},
+    settings::Proxies,
+};
+
+const TICK: Duration = Duration::from_secs(1);
+
+/* Re-checks idle exits in the background and tops the pool up from the refill sources; stops when dropped */
+pub struct Rechecker {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Rechecker {
+    pub fn start<F>(downloader: Arc<Downloader>, proxies: &Proxies, build_client: F) -> Option<Self>
+    where
+        F: Fn(Option<Proxy>) -> reqwest::Result<Client> + Send + Sync + 'static,
+    {
+        let interval = Duration::from_secs(proxies.recheck_interval?);
+        let refill = proxies.refill.clone();
+        let min_exits = proxies.min_exits;
+        let min_anonymity = proxies.min_anonymity;
+        let concurrency = proxies.check_concurrency;
+        let running = Arc::new(AtomicBool::new(true));
+
+        let handle = {
+            let running = Arc::clone(&running);
+            thread::spawn(move || {
+                /* Proxies already tried this run, so a refill only checks new ones */
+                let mut seen = downloader
+                    .exits()
+                    .iter()
+                    .flat_map(|exit| exit.aliases.clone())
+                    .collect::<HashSet<_>>();
+                let mut last = downloader.health();
+                let mut next = Instant::now() + interval;
+                /* Fetched at the first round, and again until it comes back */
+                let mut local = None;
+
+                while running.load(Ordering::Relaxed) {
+                    if Instant::now() < next {
+                        thread::sleep(TICK);
+                        continue;
+                    }
+                    next = Instant::now() + interval;
+
+                    if local.is_none() {
+                        local = build_client(None)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|client| proxy::local_address(&client))
+                            .map_err(|error| warn!(%error, "Failed to get the local address, skipping the re-check"))
+                            .ok();
+                    }
+                    let Some(local) = &local else {
+                        continue;
+                    };
+
+                    let health = downloader.health();
+                    recheck_idle(&downloader, &last, &health, concurrency, local);
+                    last = health;
+
+                    let live = downloader
+                        .exits()
+                        .iter()
+                        .filter(|exit| !downloader.is_retired(exit))
+                        .count();
+                    if live < min_exits && !refill.is_empty() {
+                        replenish(
+                            &downloader,
+                            &refill,
+                            &mut seen,
+                            concurrency,
+                            min_anonymity,
+                            &build_client,
+                            local,
+                        );
+                    }
+                }
+            })
+        };
+
+        Some(Self {
+            running,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Rechecker {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/* Busy exits prove themselves on every request, so only those without one since the last round are checked */
+fn recheck_idle(
+    downloader: &Downloader,
+    last: &HashMap<String, Health>,
+    health: &HashMap<String, Health>,
+    concurrency: usize,
+    local: &str,
+) {
+    let attempts = |health: &HashMap<String, Health>, address: &str| {
+        health
+            .get(address)
+            .map_or(0, |h| h.successes + h.failures + h.timeouts)
+    };
+
+    let idle = downloader
+        .exits()
+        .into_iter()
+        .filter(|exit| !exit.aliases.is_empty() && !downloader.is_retired(exit))
+        .filter(|exit| attempts(last, &exit.address) == attempts(health, &exit.address))
+        .collect::<Vec<_>>();
+    if idle.is_empty() {
+        return;
+    }
+
+    let Ok(pool) = ThreadPoolBuilder::new().num_threads(concurrency).build() else {
+        return;
+    };
+    let retired = pool.install(|| {
+        idle.into_par_iter()
+            .filter_map(|exit| {
+                let listing = Listing {
+                    proxy: exit.aliases[0].clone(),
+                    labels: exit.labels.clone(),
+                };
+                let reason = match proxy::check(listing, Ok(exit.client.clone()), local).outcome {
+                    Ok((_client, address)) if address == exit.address => return None,
+                    Ok((_client, address)) => format!("Failed re-check: now exits from {address}"),
+                    Err(error) => format!("Failed re-check: {error}"),
+                };
+                downloader.retire(&exit, &reason);
+
+                Some(exit)
+            })
+            .count()
+    });
+
+    info!(retired, "Re-checked idle exits");
+}
+
+fn replenish<F>(
+    downloader: &Downloader,
+    refill: &[String],
+    seen: &mut HashSet<String>,
+    concurrency: usize,
+    min_anonymity: Option<Anonymity>,
+    build_client: &F,
+    local: &str,
+) where
+    F: Fn(Option<Proxy>) -> reqwest::Result<Client> + Sync,
+{
+    let listings = build_client(None)
+        .map_err(anyhow::Error::from)
+        .and_then(|client| proxy::load_lists(refill, &client));
+    let listings = match listings {
+        Ok(listings) => listings,
+        Err(error) => {
+            warn!(%error, "Failed to load proxies to refill the pool");
+            return;
+        }
+    };
+    let listings = listings
+        .into_iter()
+        .filter(|listing| seen.insert(listing.proxy.clone()))
+        .collect::<Vec<_>>();
+    if listings.is_empty() {
+        return;
+    }
+
+    let Ok(pool) = ThreadPoolBuilder::new().num_threads(concurrency).build() else {
+        return;
+    };
+    let mut checks = pool.install(|| {
+        listings
+            .into_par_iter()
+            .map(|listing| {
+                let client = Proxy::all(&listing.proxy).and_then(|p| build_client(Some(p)));
+
+                proxy::check(listing, client, local)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    if let Some(min) = min_anonymity {
+        proxy::require_anonymity(&mut checks, min);
+    }
+
+    let checked = checks.len();
+    let added = downloader.add_exits(proxy::group_by_exit(checks));
+    info!(checked, added, "Refilled the exit pool");
+}
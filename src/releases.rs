@@ -0,0 +1,206 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+};
+
+use clap::ValueEnum;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{config::Config, settings::Duplicates};
+
+/* Which entries of a release family step 7 downloads */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    #[default]
+    DownloadAll,
+    /* The entry listed first on the site */
+    NewestOnly,
+    /* Highest resolution, then the better source, then the newest */
+    BestQuality,
+}
+
+impl DuplicatePolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DownloadAll => "download-all",
+            Self::NewestOnly => "newest-only",
+            Self::BestQuality => "best-quality",
+        }
+    }
+}
+
+/* Release tags, separators and case dropped, so variations of one release name come out the same */
+pub fn normalize(title: &str) -> String {
+    lazy_static! {
+        static ref BRACKETS: Regex = Regex::new(r"\[[^\]]*\]|\([^)]*\)|\{[^}]*\}").unwrap();
+        static ref TAGS: Regex = Regex::new(
+            r"(?i)\b(\d{3,4}p|4k|uhd|blu-?ray|bdrip|brrip|remux|web-?dl|web-?rip|hdtv|dvdrip|hdrip|x26[45]|h\.?26[45]|hevc|avc|aac|ac3|dts|hdr|10bit|proper|repack)\b"
+        )
+        .unwrap();
+        static ref SEPARATORS: Regex = Regex::new(r"[\s._\-]+").unwrap();
+    }
+
+    let title = BRACKETS.replace_all(title, " ");
+    let title = TAGS.replace_all(&title, " ").to_lowercase();
+
+    SEPARATORS.replace_all(&title, " ").trim().to_string()
+}
+
+/* Resolution and then source, higher is better */
+fn quality(title: &str) -> (u32, u32) {
+    lazy_static! {
+        static ref RESOLUTION: Regex = Regex::new(r"(?i)\b(\d{3,4})p\b|\b(4k|uhd)\b").unwrap();
+        static ref SOURCES: [(u32, Regex); 5] = [
+            (5, Regex::new(r"(?i)\bremux\b").unwrap()),
+            (4, Regex::new(r"(?i)\bblu-?ray\b|\bb[dr]rip\b").unwrap()),
+            (3, Regex::new(r"(?i)\bweb-?dl\b").unwrap()),
+            (2, Regex::new(r"(?i)\bweb-?rip\b|\bhdrip\b").unwrap()),
+            (1, Regex::new(r"(?i)\bhdtv\b|\bdvdrip\b").unwrap()),
+        ];
+    }
+
+    let resolution =
+        RESOLUTION
+            .captures(title)
+            .map_or(0, |captures| match (captures.get(1), captures.get(2)) {
+                (Some(lines), _) => lines.as_str().parse().unwrap_or_default(),
+                _ => 2160,
+            });
+    let source = SOURCES
+        .iter()
+        .find(|(_rank, regex)| regex.is_match(title))
+        .map_or(0, |(rank, _regex)| *rank);
+
+    (resolution, source)
+}
+
+/* Entries whose titles normalize the same, keyed by the normalized title; entries with no duplicate are left out */
+pub fn families(config: &Config) -> BTreeMap<String, Vec<String>> {
+    let mut families = BTreeMap::<String, Vec<String>>::new();
+    for (entry, metadata) in &config.metadata {
+        if config.tombstones.contains(entry)
+            || config.blocked.contains_key(entry)
+            || config.dead.contains_key(entry)
+        {
+            continue;
+        }
+
+        let family = normalize(&metadata.title);
+        if !family.is_empty() {
+            families.entry(family).or_default().push(entry.clone());
+        }
+    }
+
+    families.retain(|_family, entries| entries.len() > 1);
+
+    families
+}
+
+/* A family's own policy, or the default one */
+fn family_policy(duplicates: &Duplicates, family: &str) -> DuplicatePolicy {
+    duplicates
+        .families
+        .get(family)
+        .copied()
+        .unwrap_or(duplicates.policy)
+}
+
+/* The entry of a family its policy keeps, or None when it keeps them all */
+fn pick<'a>(
+    config: &Config,
+    entries: &'a [String],
+    policy: DuplicatePolicy,
+    listed: &HashMap<&String, (usize, usize)>,
+) -> Option<&'a String> {
+    /* Page 1 lists the newest entries first, and entries on no page count as oldest */
+    let age = |entry: &String| listed.get(entry).copied().unwrap_or((usize::MAX, 0));
+    let rank = |entry: &String| {
+        let title = config.metadata.get(entry).map_or("", |m| m.title.as_str());
+        quality(title)
+    };
+
+    match policy {
+        DuplicatePolicy::DownloadAll => None,
+        DuplicatePolicy::NewestOnly => entries.iter().min_by_key(|entry| age(entry)),
+        DuplicatePolicy::BestQuality => entries
+            .iter()
+            .min_by_key(|entry| (Reverse(rank(entry)), age(entry))),
+    }
+}
+
+/* Where each entry sits in the listing, as page and position */
+pub fn listed(config: &Config) -> HashMap<&String, (usize, usize)> {
+    let mut listed = HashMap::new();
+    for (page, links) in &config.pages {
+        for (index, entry) in links.iter().enumerate() {
+            listed.entry(entry).or_insert((*page, index));
+        }
+    }
+
+    listed
+}
+
+/* Records the entries each family's policy passes over, with the entry kept instead; returns how many families have duplicates */
+pub fn apply(config: &mut Config, duplicates: &Duplicates) -> usize {
+    let families = families(config);
+    let listed = listed(config);
+
+    let mut superseded = BTreeMap::new();
+    for (family, entries) in &families {
+        let policy = family_policy(duplicates, family);
+        let Some(kept) = pick(config, entries, policy, &listed) else {
+            continue;
+        };
+
+        for entry in entries.iter().filter(|entry| *entry != kept) {
+            superseded.insert(entry.clone(), kept.clone());
+        }
+    }
+    config.superseded = superseded;
+
+    families.len()
+}
+
+/* Torrent links only superseded entries link to, which step 7 skips */
+pub fn superseded_torrents(config: &Config) -> HashSet<&String> {
+    let kept = config
+        .links
+        .iter()
+        .filter(|(entry, _links)| !config.superseded.contains_key(*entry))
+        .flat_map(|(_entry, links)| links)
+        .collect::<HashSet<_>>();
+
+    config
+        .superseded
+        .keys()
+        .filter_map(|entry| config.links.get(entry))
+        .flatten()
+        .filter(|url| !kept.contains(url))
+        .collect()
+}
+
+/* Lists each release family and the entry its policy keeps, without touching the network */
+pub fn print(config: &Config, duplicates: &Duplicates) {
+    let families = families(config);
+    let listed = listed(config);
+
+    for (family, entries) in &families {
+        let policy = family_policy(duplicates, family);
+        let kept = pick(config, entries, policy, &listed);
+
+        println!("{family}  ({} entries, {})", entries.len(), policy.name());
+        for entry in entries {
+            let mark = match kept {
+                Some(kept) if kept != entry => " ",
+                _ => "*",
+            };
+            let title = config.metadata.get(entry).map_or("", |m| m.title.as_str());
+            println!("  {mark} {entry:<50}  {title}");
+        }
+    }
+
+    println!("Found {} release families with duplicates", families.len());
+}
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashSet,
+    fs,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{adapter::Adapter, cache, config::Config, metadata::Metadata, settings::Rule};
+
+pub const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/* Accepts "30d", "12h", "2w" or a plain number of seconds */
+pub fn parse_age(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number = number
+        .parse::<u64>()
+        .with_context(|| format!("Invalid age {text}"))?;
+
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => DAY.as_secs(),
+        "w" => 7 * DAY.as_secs(),
+        unit => bail!("Unknown age unit {unit}"),
+    };
+
+    Ok(Duration::from_secs(number * seconds))
+}
+
+/* Deletes the cached page and torrents of expired entries and tombstones them in the state */
+pub fn apply(
+    adapter: &Adapter,
+    config: &mut Config,
+    rules: &[Rule],
+    base_path: &str,
+    html_path: &str,
+    torrents_path: &str,
+    dry_run: bool,
+) -> Result<()> {
+    let now = SystemTime::now();
+    let default = Metadata::default();
+
+    let mut expired = Vec::new();
+    for entry in config
+        .entries
+        .iter()
+        .filter(|e| !config.tombstones.contains(*e))
+    {
+        let metadata = config.metadata.get(entry).unwrap_or(&default);
+        let Some(rule) = rules.iter().find(|rule| rule.matches(metadata)) else {
+            continue;
+        };
+        let Some(max_age_days) = rule.max_age_days else {
+            continue;
+        };
+
+        /* The site shows no dates, so the entry is aged by when it was first saved */
+        let path = adapter.entry_path(html_path, entry);
+        let age = cache::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok());
+        let Some(age) = age else {
+            continue;
+        };
+
+        let days = age.as_secs() / DAY.as_secs();
+        if days > max_age_days {
+            let kind = format!("{:?}", metadata.kind).to_lowercase();
+            println!("{days:>6}d  {kind:<8}  {entry}");
+            expired.push((entry.clone(), path));
+        }
+    }
+
+    if dry_run {
+        println!("Would retire {} entries", expired.len());
+        return Ok(());
+    }
+
+    let mut removed = HashSet::new();
+    let mut files = 0;
+    for (entry, path) in &expired {
+        let links = config.links.get(entry).cloned().unwrap_or_default();
+        let torrents = links
+            .iter()
+            .filter_map(|url| adapter.torrent_path(torrents_path, url));
+        if cache::remove(path) {
+            files += 1;
+        }
+        for path in torrents {
+            if fs::remove_file(&path).is_ok() {
+                files += 1;
+            }
+        }
+
+        removed.extend(links);
+        config.tombstones.insert(entry.clone());
+    }
+
+    config.torrents.retain(|url| !removed.contains(url));
+    config.save(base_path)?;
+
+    println!(
+        "Retired {} entries and deleted {files} files",
+        expired.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_age("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse_age(" 2 d ").unwrap(), DAY * 2);
+        assert_eq!(parse_age("1w").unwrap(), DAY * 7);
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+}
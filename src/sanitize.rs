@@ -0,0 +1,83 @@
+use crate::settings::Layout;
+
+/* Names Windows refuses whatever the extension */
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/* Turns scraped strings into file names that work on Linux, macOS and Windows alike */
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    max_length: usize,
+    replacement: String,
+    hash_suffix: bool,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Self::new(&Layout::default())
+    }
+}
+
+impl Sanitizer {
+    pub fn new(layout: &Layout) -> Self {
+        Self {
+            max_length: layout.max_name_length.max(16),
+            replacement: layout.replacement.clone(),
+            hash_suffix: layout.hash_suffix,
+        }
+    }
+
+    /* One path component; names that are already safe come back unchanged */
+    pub fn component(&self, text: &str) -> String {
+        let mut name = text
+            .chars()
+            .map(|c| match c {
+                '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => self.replacement.clone(),
+                c if c.is_control() => self.replacement.clone(),
+                c => c.to_string(),
+            })
+            .collect::<String>();
+
+        /* Windows drops trailing dots and spaces, so two names could end up as one */
+        name.truncate(name.trim_end_matches(['.', ' ']).len());
+        if name.is_empty() || name == "." || name == ".." {
+            name = self.replacement.clone();
+        }
+
+        let stem = name.split('.').next().unwrap_or_default();
+        if RESERVED
+            .iter()
+            .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        {
+            name.insert_str(stem.len(), &self.replacement);
+        }
+
+        /* A short hash of the original tells apart names that sanitize to the same thing */
+        let changed = name != text || name.len() > self.max_length;
+        let suffix = match self.hash_suffix && changed {
+            true => format!("~{}", &blake3::hash(text.as_bytes()).to_hex()[..8]),
+            false => String::new(),
+        };
+
+        let mut length = self.max_length.saturating_sub(suffix.len());
+        while !name.is_char_boundary(length.min(name.len())) {
+            length -= 1;
+        }
+        name.truncate(length);
+
+        name + &suffix
+    }
+
+    /* Each component of a relative path, keeping its separators */
+    pub fn path(&self, text: &str) -> String {
+        text.split('/')
+            .map(|component| match component {
+                "" => String::new(),
+                component => self.component(component),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
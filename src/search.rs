@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use anyhow::Result;
+use regex::RegexBuilder;
+
+use crate::{adapter::Adapter, config::Config};
+
+/* Matches titles, tags and kinds in the scraped metadata, without touching the network */
+pub fn search(
+    adapter: &Adapter,
+    config: &Config,
+    torrents_path: &str,
+    query: &str,
+    regex: bool,
+) -> Result<()> {
+    let pattern = match regex {
+        true => query.to_string(),
+        false => regex::escape(query),
+    };
+    let pattern = RegexBuilder::new(&pattern).case_insensitive(true).build()?;
+
+    println!("{:<10}  {:<8}  {:<50}  TITLE", "STATUS", "KIND", "ENTRY");
+    let mut matches = 0;
+    for (entry, metadata) in &config.metadata {
+        let kind = format!("{:?}", metadata.kind).to_lowercase();
+        let fields = [&metadata.title, &kind].into_iter().chain(&metadata.tags);
+        if !fields.into_iter().any(|field| pattern.is_match(field)) {
+            continue;
+        }
+
+        let status = status(adapter, config, torrents_path, entry);
+        println!("{status:<10}  {kind:<8}  {entry:<50}  {}", metadata.title);
+        matches += 1;
+    }
+
+    println!("Found {matches} matching entries");
+
+    Ok(())
+}
+
+fn status(adapter: &Adapter, config: &Config, torrents_path: &str, entry: &str) -> &'static str {
+    let links = config
+        .links
+        .get(entry)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let saved = links
+        .iter()
+        .filter_map(|url| adapter.torrent_path(torrents_path, url))
+        .filter(|path| Path::new(path).exists())
+        .count();
+
+    match saved {
+        _ if links.is_empty() => "no links",
+        0 => "pending",
+        saved if saved < links.len() => "partial",
+        _ => "archived",
+    }
+}
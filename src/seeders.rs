@@ -0,0 +1,34 @@
+use std::collections::HashSet;
+
+use crate::config::Config;
+
+/* Marks entries scraped with fewer than min seeders as dead and revives those that came back; returns how many are dead */
+pub fn apply(config: &mut Config, min_seeders: Option<u64>) -> usize {
+    let Some(min_seeders) = min_seeders else {
+        config.dead.clear();
+        return 0;
+    };
+
+    for (entry, metadata) in &config.metadata {
+        match metadata.seeders {
+            Some(seeders) if seeders < min_seeders => {
+                config.dead.insert(entry.clone(), seeders);
+            }
+            _ => {
+                config.dead.remove(entry);
+            }
+        }
+    }
+
+    config.dead.len()
+}
+
+/* Torrent links of dead entries, which step 7 skips */
+pub fn dead_torrents(config: &Config) -> HashSet<&String> {
+    config
+        .dead
+        .keys()
+        .filter_map(|entry| config.links.get(entry))
+        .flatten()
+        .collect()
+}
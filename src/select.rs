@@ -0,0 +1,99 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+};
+
+use anyhow::{bail, Result};
+
+/* How many matches are listed at once; narrowing the filter shows the rest */
+const PAGE: usize = 40;
+
+/* Drawn on stderr, leaving stdout to the run's own output; text narrows the list, numbers and ranges such as 3-7 toggle, "all" and "none" act on every match, an empty line confirms */
+pub fn pick<T>(items: Vec<(String, T)>) -> Result<Vec<T>> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut chosen = BTreeSet::new();
+    let mut filter = String::new();
+
+    loop {
+        let needle = filter.to_lowercase();
+        let matches = items
+            .iter()
+            .enumerate()
+            .filter(|(_index, (label, _item))| label.to_lowercase().contains(&needle))
+            .map(|(index, _item)| index)
+            .collect::<Vec<_>>();
+
+        eprintln!();
+        for (number, index) in matches.iter().take(PAGE).enumerate() {
+            let mark = if chosen.contains(index) { 'x' } else { ' ' };
+            eprintln!("[{mark}] {:>3}  {}", number + 1, items[*index].0);
+        }
+        if matches.len() > PAGE {
+            eprintln!("      ... {} more, type to narrow", matches.len() - PAGE);
+        }
+        eprint!(
+            "{} of {} selected, filter \"{filter}\" > ",
+            chosen.len(),
+            items.len()
+        );
+        io::stderr().flush()?;
+
+        let Some(line) = lines.next() else {
+            bail!("Selection cancelled");
+        };
+        let line = line?;
+        let line = line.trim();
+
+        match line {
+            "" => break,
+            "all" => chosen.extend(matches.iter().copied()),
+            "none" => {
+                for index in &matches {
+                    chosen.remove(index);
+                }
+            }
+            _ => match numbers(line, matches.len().min(PAGE)) {
+                Some(numbers) => {
+                    for number in numbers {
+                        let index = matches[number - 1];
+                        if !chosen.remove(&index) {
+                            chosen.insert(index);
+                        }
+                    }
+                }
+                None => filter = line.to_string(),
+            },
+        }
+    }
+
+    let picked = items
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _item)| chosen.contains(index))
+        .map(|(_index, (_label, item))| item)
+        .collect();
+
+    Ok(picked)
+}
+
+/* "1 4 7-9" as shown numbers, or None when the line is a filter */
+fn numbers(line: &str, shown: usize) -> Option<Vec<usize>> {
+    let mut numbers = Vec::new();
+    for part in line.split([' ', ',']).filter(|part| !part.is_empty()) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            None => {
+                let number = part.parse().ok()?;
+                (number, number)
+            }
+        };
+        if start == 0 || end > shown || start > end {
+            return None;
+        }
+
+        numbers.extend(start..=end);
+    }
+
+    Some(numbers)
+}
@@ -0,0 +1,606 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::{
+    cache,
+    cache::Compression,
+    client::Api,
+    events::Format,
+    hash::Algorithm,
+    metadata::{Kind, Metadata},
+    notify::WebhookFormat,
+    order::Order,
+    proxy::Anonymity,
+    releases::DuplicatePolicy,
+    tls::TlsVersion,
+};
+
+/* https://techblog.willshouse.com/2012/01/03/most-common-user-agents */
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/118.0.0.0 Safari/537.36";
+
+/* Everything a run can be configured with; crawl state stays in TORRENTS.JSON */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /* State and files of this archive, relative to --base-path; mostly set per profile */
+    pub base_path: Option<String>,
+    pub base_url: Option<String>,
+    /* Tried in order when the base URL keeps failing */
+    pub mirrors: Vec<String>,
+    /* File of patterns for entries and torrents never to fetch */
+    pub blocklist: Option<String>,
+    pub network: Network,
+    pub proxies: Proxies,
+    pub tor: TorSettings,
+    pub tls: TlsSettings,
+    pub dns: Dns,
+    pub retry: Retry,
+    pub limits: Limits,
+    pub layout: Layout,
+    pub output: Output,
+    pub disk: Disk,
+    pub client: ClientSettings,
+    pub notify: NotifySettings,
+    pub hooks: Hooks,
+    pub sync: SyncSettings,
+    pub xref: Xref,
+    /* Header template overrides, keyed by adapter name and then header name */
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+    /* Selector and URL pattern overrides, keyed by adapter name, for when the site changes its templates */
+    pub scrape: BTreeMap<String, Scrape>,
+    pub browser: BrowserSettings,
+    pub media: MediaSettings,
+    pub trackers: TrackerSettings,
+    pub retention: Retention,
+    pub duplicates: Duplicates,
+    pub schedule: Schedule,
+    pub pipeline: Pipeline,
+    pub login: Login,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Network {
+    pub user_agent: String,
+    pub connect_timeout: u64,
+    pub request_timeout: u64,
+    pub cooldown: u64,
+    pub scheme: Scheme,
+    pub mode: Mode,
+    /* Cap across all workers, e.g. "5MB/s" */
+    pub max_bandwidth: Option<String>,
+    /* Fetching threads, spread over the exits in turn; one per CPU when unset */
+    pub workers: Option<usize>,
+}
+
+/* Auto follows the site when it redirects to https or sends HSTS, and never downgrades */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    #[default]
+    Auto,
+    Http,
+    Https,
+}
+
+/* Direct sends requests without a proxy, e.g. behind a VPN; mixed adds that direct exit to the proxies */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Proxy,
+    Direct,
+    Mixed,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self {
+            user_agent: USER_AGENT.to_string(),
+            connect_timeout: 10,
+            request_timeout: 60,
+            cooldown: 300,
+            scheme: Scheme::Auto,
+            mode: Mode::Proxy,
+            max_bandwidth: None,
+            workers: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Proxies {
+    pub paths: Vec<String>,
+    pub check_concurrency: usize,
+    pub report: Option<String>,
+    /* Seconds between re-checks of idle exits during a run; off when unset */
+    pub recheck_interval: Option<u64>,
+    /* Sources of fresh proxies, pulled when fewer than min_exits are left at a re-check */
+    pub refill: Vec<String>,
+    pub min_exits: usize,
+    /* Proxies forwarding less than this about themselves are dropped at the check */
+    pub min_anonymity: Option<Anonymity>,
+}
+
+impl Default for Proxies {
+    fn default() -> Self {
+        Self {
+            paths: vec!["proxies.txt".to_string()],
+            check_concurrency: 32,
+            report: None,
+            recheck_interval: None,
+            refill: Vec::new(),
+            min_exits: 10,
+            min_anonymity: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TorSettings {
+    pub enabled: bool,
+    pub proxy: String,
+    pub control: String,
+    pub password: Option<String>,
+    pub workers: usize,
+    pub isolate: bool,
+}
+
+impl Default for TorSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            proxy: "127.0.0.1:9050".to_string(),
+            control: "127.0.0.1:9051".to_string(),
+            password: None,
+            workers: 8,
+            isolate: false,
+        }
+    }
+}
+
+/* Trust for every client, including the proxy checks, for proxies that intercept TLS */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    /* Accepts any certificate, which leaves traffic open to whoever is in between */
+    pub insecure: bool,
+    /* PEM file of certificates trusted on top of the system ones */
+    pub ca_bundle: Option<String>,
+    pub min_version: Option<TlsVersion>,
+}
+
+/* How the hosts of the site and its mirrors are resolved, for networks whose DNS lies about them */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Dns {
+    /* A DoH endpoint such as "https://1.1.1.1/dns-query", or a DNS server such as "9.9.9.9" */
+    pub resolver: Option<String>,
+    /* Hosts looked up through the resolver; every host of base_url and mirrors when empty */
+    pub hosts: Vec<String>,
+    /* Fixed addresses by host, which need no resolver */
+    pub addresses: BTreeMap<String, String>,
+}
+
+/* Profiles `sync --all` runs, each under its own base path */
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncSettings {
+    /* Every profile of the file when empty */
+    pub targets: Vec<String>,
+    /* Targets run at once, each logging to its own base path; one after another when 1 */
+    pub concurrency: usize,
+    /* Checks the proxies of every target's lists once, then runs targets on proxies with the working ones in place of their own lists */
+    pub share_proxies: bool,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            concurrency: 1,
+            share_proxies: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retry {
+    pub max_attempts: usize,
+    pub max_failure_rate: f64,
+    /* An exit failing this many requests in a row is retired for the rest of the run */
+    pub max_consecutive_failures: usize,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            max_failure_rate: 0.05,
+            max_consecutive_failures: 20,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_pages: Option<usize>,
+    pub max_entries: Option<usize>,
+    pub max_torrents: Option<usize>,
+    /* Bodies larger than this, e.g. "64MiB", are abandoned as failed; 64MiB when unset */
+    pub max_file_size: Option<String>,
+    /* Entries fetched longer ago than this, e.g. "30d", are fetched again */
+    pub refresh_older_than: Option<String>,
+    /* Torrents of entries showing fewer seeders than this are skipped in step 7 */
+    pub min_seeders: Option<u64>,
+    /* Dead entries fetched longer ago than this, e.g. "7d", are fetched again to see if they came back */
+    pub recheck_dead_after: Option<String>,
+    /* Steps 3 and 4 stop walking deeper once this many pages in a row list no new entries */
+    pub stop_after_empty_pages: Option<usize>,
+    /* Which entries steps 5 and 7 get to first; the order of the state when unset */
+    pub order: Option<Order>,
+    /* Steps 5 and 7 each take on at most this many entries a run, so a backfill spreads over sessions */
+    pub budget: Option<usize>,
+}
+
+/* Directories under the base path, so an existing archive can keep its own naming */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layout {
+    pub html: String,
+    /* Directories under html for listing pages, entry pages and entries rendered by the browser */
+    pub pages: String,
+    pub entries: String,
+    pub rendered: String,
+    pub torrents: String,
+    /* Places new torrents by metadata, from {category}, {year}, {title}, {language}, {entry} and {name} */
+    pub path_template: Option<String>,
+    /* File names made from scraped strings are cut to this many bytes */
+    pub max_name_length: usize,
+    /* Stands in for characters some filesystem refuses */
+    pub replacement: String,
+    /* Appends a short hash of the original to names that had to change, so they cannot collide */
+    pub hash_suffix: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            html: "HTML".to_string(),
+            pages: "PAGES".to_string(),
+            entries: "ENTRIES".to_string(),
+            rendered: "RENDERED".to_string(),
+            torrents: "TORRENT".to_string(),
+            path_template: None,
+            max_name_length: 200,
+            replacement: "_".to_string(),
+            hash_suffix: false,
+        }
+    }
+}
+
+impl Layout {
+    pub fn index_path(&self, html_path: &str) -> String {
+        cache::join(html_path, &["INDEX.HTML"])
+    }
+
+    pub fn pages_path(&self, html_path: &str) -> String {
+        cache::join(html_path, &[&self.pages])
+    }
+
+    pub fn page_path(&self, html_path: &str, page: usize) -> String {
+        cache::join(html_path, &[&self.pages, &format!("{page}.HTML")])
+    }
+
+    pub fn entries_path(&self, html_path: &str) -> String {
+        cache::join(html_path, &[&self.entries])
+    }
+
+    /* Entries are relative links, so each of their components is a directory */
+    pub fn entry_path(&self, html_path: &str, entry: &str) -> String {
+        cache::join(html_path, &[&self.entries, &format!("{entry}.HTML")])
+    }
+
+    pub fn rendered_path(&self, html_path: &str, entry: &str) -> String {
+        cache::join(html_path, &[&self.rendered, &format!("{entry}.HTML")])
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Output {
+    pub format: Format,
+    pub checksum: Algorithm,
+    pub metrics_file: Option<String>,
+    pub log_file: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Disk {
+    pub writers: usize,
+    pub queue: usize,
+    /* Format new HTML cache files are written in; both are always readable */
+    pub compression: Compression,
+    /* Step 7 fetches no more torrents than fit under this, e.g. "500GB", counting everything under the base path */
+    pub max_usage: Option<String>,
+}
+
+impl Default for Disk {
+    fn default() -> Self {
+        Self {
+            writers: 2,
+            queue: 64,
+            compression: Compression::None,
+            max_usage: None,
+        }
+    }
+}
+
+/* Torrent client that step 7 hands links to instead of saving them, when direct is set */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientSettings {
+    pub direct: bool,
+    pub api: Api,
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/* Where to report run completion and, with per_torrent, every torrent that arrived */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifySettings {
+    pub webhook: Option<String>,
+    pub format: WebhookFormat,
+    pub command: Option<String>,
+    pub per_torrent: bool,
+}
+
+/* What happens to every torrent step 7 saves to disk, such as handing it to a client's watch directory */
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /* Shell commands given the path as $1, and the entry's metadata as TORRENTS_ environment variables */
+    pub commands: Vec<String>,
+    /* A directory each torrent is copied into */
+    pub copy_to: Option<String>,
+    /* Octal permissions set on each torrent, e.g. "644" */
+    pub mode: Option<String>,
+    /* How many torrents are handled at once */
+    pub concurrency: usize,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            commands: Vec::new(),
+            copy_to: None,
+            mode: None,
+            concurrency: 4,
+        }
+    }
+}
+
+/* Base paths of other archives whose state is cross-referenced for alternative sources */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Xref {
+    pub archives: Vec<String>,
+}
+
+/* Rules checked in order by `apply-retention`, the first that matches an entry deciding, and the age limits of `archive` */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+    pub rules: Vec<Rule>,
+    /* `archive` takes the HTML and torrents of entries older than this, e.g. "365d" */
+    pub keep_entries_newer_than: Option<String>,
+    /* `archive` takes the HTML of entries cached longer ago than this, and leaves their torrents */
+    pub keep_html_days: Option<u64>,
+    /* Where `archive` moves files, keeping their paths under the base path; they are deleted when unset */
+    pub archive_to: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rule {
+    pub kind: Option<Kind>,
+    pub tag: Option<String>,
+    /* Entries older than this are dropped; without it matching entries are kept */
+    pub max_age_days: Option<u64>,
+}
+
+impl Rule {
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        let kind = self.kind.is_none_or(|kind| kind == metadata.kind);
+        let tag = self
+            .tag
+            .as_ref()
+            .is_none_or(|tag| metadata.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)));
+
+        kind && tag
+    }
+}
+
+/* Unset fields keep the adapter's built-in values */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scrape {
+    /* CSS selector for the pagination links on the index */
+    pub page_numbers: Option<String>,
+    /* CSS selector for links to entries and torrents */
+    pub links: Option<String>,
+    /* Matches torrent URLs, capturing the directory and then the file name */
+    pub torrent_regex: Option<String>,
+    /* Listing page path under the base URL, with {page} for the number */
+    pub page_path: Option<String>,
+}
+
+/* Entries whose titles normalize to the same release, see releases::normalize */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Duplicates {
+    pub policy: DuplicatePolicy,
+    /* Policies for single families, keyed by normalized title */
+    pub families: BTreeMap<String, DuplicatePolicy>,
+}
+
+/* Descriptions as .NFO and images, saved beside each entry's torrents */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MediaSettings {
+    pub enabled: bool,
+}
+
+/* Announce URLs written into each saved torrent */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackerSettings {
+    /* Drops the site's own announce URLs */
+    pub strip: bool,
+    pub add: Vec<String>,
+    /* A file or URL with one tracker per line, such as ngosang/trackerslist */
+    pub list: Option<String>,
+}
+
+/* Headless Chrome, tried on entries whose static markup has no torrent links */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrowserSettings {
+    pub enabled: bool,
+    /* Chrome executable; found on the PATH when unset */
+    pub path: Option<String>,
+    pub proxy: Option<String>,
+    pub timeout: u64,
+    /* Milliseconds to wait after load for scripts to add links */
+    pub settle: u64,
+}
+
+impl Default for BrowserSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            proxy: None,
+            timeout: 30,
+            settle: 2000,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pipeline {
+    /* Fetch pages, entries and torrents concurrently, leaving steps 3 to 7 to pick up what it missed */
+    pub stream: bool,
+}
+
+/* A form posted once at startup, after which every client shares the session cookie it set */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Login {
+    /* May use {base_url} */
+    pub url: Option<String>,
+    pub fields: BTreeMap<String, String>,
+    /* Fields read from environment variables, to keep passwords out of this file */
+    pub fields_env: BTreeMap<String, String>,
+    /* Cookie that must be set afterwards for the login to count */
+    pub cookie: Option<String>,
+}
+
+/* Which exits may serve each kind of request, by proxy label, and what extra headers it carries */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schedule {
+    pub index: Policy,
+    pub pages: Policy,
+    pub entries: Policy,
+    pub torrents: Policy,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /* Over the adapter's headers and [headers], e.g. a Referer only torrent downloads need */
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Policy {
+    pub fn allows(&self, labels: &[String]) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|l| labels.contains(l));
+        let excluded = self.exclude.iter().any(|l| labels.contains(l));
+
+        included && !excluded
+    }
+}
+
+impl Settings {
+    /* A profile is a [profiles.<name>] table laid over the rest of the file, section by section */
+    pub fn load(path: &str, profile: Option<&str>) -> Result<Self> {
+        if !Path::new(path).exists() {
+            if let Some(profile) = profile {
+                bail!("Unknown profile {profile}, as {path} does not exist");
+            }
+            return Ok(Self::default());
+        }
+
+        let text = fs::read_to_string(path)?;
+        let mut table = toml::from_str::<Table>(&text)?;
+        let profiles = match table.remove("profiles") {
+            Some(Value::Table(profiles)) => profiles,
+            Some(_) => bail!("profiles must be a table of named profiles"),
+            None => Table::new(),
+        };
+
+        if let Some(profile) = profile {
+            let overrides = profiles
+                .get(profile)
+                .and_then(Value::as_table)
+                .with_context(|| format!("Unknown profile {profile}"))?;
+            overlay(&mut table, overrides);
+        }
+
+        let settings = Value::Table(table).try_into()?;
+
+        Ok(settings)
+    }
+
+    /* Names of the [profiles.<name>] tables, sorted */
+    pub fn profiles(path: &str) -> Result<Vec<String>> {
+        if !Path::new(path).exists() {
+            return Ok(Vec::new());
+        }
+
+        let text = fs::read_to_string(path)?;
+        let table = toml::from_str::<Table>(&text)?;
+        let profiles = match table.get("profiles") {
+            Some(Value::Table(profiles)) => profiles.keys().cloned().collect(),
+            Some(_) => bail!("profiles must be a table of named profiles"),
+            None => Vec::new(),
+        };
+
+        Ok(profiles)
+    }
+}
+
+fn overlay(table: &mut Table, overrides: &Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(key), value) {
+            (Some(Value::Table(table)), Value::Table(overrides)) => overlay(table, overrides),
+            _ => {
+                table.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
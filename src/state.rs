@@ -0,0 +1,470 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use kdam::{rayon::prelude::*, Bar, BarExt};
+use serde::{
+    de::{Error as _, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer as _, Serialize, Serializer as _,
+};
+use serde_json::Value;
+use tar::{Archive, Builder, Header};
+use walkdir::WalkDir;
+
+use crate::{
+    cache,
+    config::{self, Config},
+    events,
+    hash::Algorithm,
+};
+
+const MANIFEST: &str = "MANIFEST.JSON";
+const STATE: &str = "TORRENTS.JSON";
+const CACHE: &str = "CACHE.JSON";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/* Size and checksum a file must still have when it is read back */
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct Sum {
+    size: u64,
+    checksum: String,
+}
+
+impl Sum {
+    fn of(bytes: &[u8]) -> Self {
+        Self {
+            size: bytes.len() as u64,
+            checksum: Algorithm::Blake3.digest(bytes),
+        }
+    }
+}
+
+/* Describes the rest of the archive, which is checked against it before anything is installed */
+#[derive(Debug, Deserialize, Serialize)]
+struct Manifest {
+    version: u32,
+    exported: DateTime<Utc>,
+    /* Path keys in the state start with it, and are moved under the importing base path */
+    base_path: String,
+    files: BTreeMap<String, Sum>,
+}
+
+/* A tar of the state, and with cache a manifest of the HTML cache; zstd when output ends in .zst, gzip for .gz */
+pub fn export(base_path: &str, html_path: &str, output: &str, cache: bool) -> Result<()> {
+    let state_path = Config::get_path(base_path)?;
+    let state = fs::read(&state_path)
+        .with_context(|| format!("No state to export at {}", state_path.display()))?;
+
+    let mut files = vec![(STATE, state)];
+    if cache {
+        files.push((
+            CACHE,
+            serde_json::to_vec_pretty(&cache_manifest(html_path))?,
+        ));
+    }
+
+    let manifest = Manifest {
+        version: config::VERSION,
+        exported: Utc::now(),
+        base_path: base_path.to_string(),
+        files: files
+            .iter()
+            .map(|(name, bytes)| (name.to_string(), Sum::of(bytes)))
+            .collect(),
+    };
+    files.insert(0, (MANIFEST, serde_json::to_vec_pretty(&manifest)?));
+
+    let file = File::create(output)?;
+    if output.ends_with(".zst") {
+        let encoder = zstd::Encoder::new(file, 0)?;
+        write(encoder, &files)?.finish()?.sync_all()?;
+    } else if output.ends_with(".gz") {
+        let encoder = GzEncoder::new(file, flate2::Compression::default());
+        write(encoder, &files)?.finish()?.sync_all()?;
+    } else {
+        write(file, &files)?.sync_all()?;
+    }
+
+    println!("Exported the state to {output}");
+
+    Ok(())
+}
+
+/* Installs the state from an archive once every file in it checks out; the state it replaces is kept as a backup */
+pub fn import(base_path: &str, html_path: &str, input: &str, force: bool) -> Result<()> {
+    let files = read(input).with_context(|| format!("Failed to read {input}"))?;
+
+    let manifest = files
+        .get(MANIFEST)
+        .with_context(|| format!("{input} has no {MANIFEST}, so it is not an exported state"))?;
+    let manifest = serde_json::from_slice::<Manifest>(manifest)?;
+    if manifest.version > config::VERSION {
+        bail!(
+            "{input} holds state version {}, newer than the supported {}",
+            manifest.version,
+            config::VERSION
+        );
+    }
+
+    for (name, bytes) in files.iter().filter(|(name, _bytes)| *name != MANIFEST) {
+        match manifest.files.get(name) {
+            Some(sum) if *sum == Sum::of(bytes) => {}
+            Some(_sum) => bail!("{name} in {input} does not match its manifest"),
+            None => bail!("{name} in {input} is not in its manifest"),
+        }
+    }
+    if let Some(name) = manifest
+        .files
+        .keys()
+        .find(|name| !files.contains_key(*name))
+    {
+        bail!("{name} is in the manifest of {input}, but missing from it");
+    }
+
+    let state = files
+        .get(STATE)
+        .with_context(|| format!("{input} has no {STATE}"))?;
+    let mut config = Config::parse(&String::from_utf8_lossy(state))?;
+
+    let state_path = Config::get_path(base_path)?;
+    if state_path.exists() && !force {
+        bail!(
+            "State already exists at {}; pass --force to replace it",
+            state_path.display()
+        );
+    }
+
+    let from = &manifest.base_path;
+    rebase(&mut config.validators, from, base_path);
+    rebase(&mut config.statuses, from, base_path);
+    rebase(&mut config.checksums, from, base_path);
+    rebase(&mut config.sizes, from, base_path);
+
+    if let Some(cache) = files.get(CACHE) {
+        let cache = serde_json::from_slice::<BTreeMap<String, Sum>>(cache)?;
+        check_cache(&mut config, &cache, html_path);
+    }
+
+    config.save(base_path)?;
+    println!(
+        "Imported the state exported {} into {}",
+        manifest.exported,
+        state_path.display()
+    );
+
+    Ok(())
+}
+
+/* Upgrades the state on disk to the current version without ever holding all of it, one top-level field at a time; the old file is kept as TORRENTS.JSON.PRE-MIGRATE, and stays in place if any step fails */
+pub fn migrate(base_path: &str) -> Result<()> {
+    let state_path = Config::get_path(base_path)?;
+    if !state_path.exists() {
+        bail!("No state to migrate at {}", state_path.display());
+    }
+
+    /* Only the version is kept; the rest is skipped as it streams past */
+    let file = BufReader::new(File::open(&state_path)?);
+    let version = serde_json::from_reader::<_, Version>(file)
+        .with_context(|| format!("Failed to read {}", state_path.display()))?
+        .version;
+    if version >= config::VERSION {
+        println!("State is already at version {version}");
+        return Ok(());
+    }
+
+    let backup_path = state_path.with_extension("JSON.PRE-MIGRATE");
+    fs::copy(&state_path, &backup_path)
+        .with_context(|| format!("Failed to back up the state to {}", backup_path.display()))?;
+
+    let temp_path = state_path.with_extension("JSON.MIGRATE");
+    if let Err(error) = upgrade(&state_path, &temp_path, version) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(error.context("Failed to migrate the state, which was left as it was"));
+    }
+    fs::rename(&temp_path, &state_path)?;
+
+    println!(
+        "Migrated the state from version {version} to {}, keeping the old one as {}",
+        config::VERSION,
+        backup_path.display()
+    );
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Version {
+    #[serde(default)]
+    version: u32,
+}
+
+/* Writes the upgraded state to temp_path, and keeps it only once it reads back as a state */
+fn upgrade(state_path: &Path, temp_path: &Path, version: u32) -> Result<()> {
+    let size = fs::metadata(state_path)?.len() as usize;
+    let mut bar = events::bar(size);
+    bar.unit = "B".to_string();
+    bar.unit_scale = true;
+    bar.write(format!(
+        "Migrating the state from version {version} to {}...",
+        config::VERSION
+    ))?;
+
+    /* Buffered outside, so the bar moves a chunk at a time rather than a byte */
+    let input = BufReader::new(Progress {
+        inner: File::open(state_path)?,
+        bar,
+    });
+    let mut output = BufWriter::new(File::create(temp_path)?);
+    let mut deserializer = serde_json::Deserializer::from_reader(input);
+    deserializer.deserialize_map(Upgrade {
+        output: &mut output,
+        from: version,
+    })?;
+    deserializer.end()?;
+    output.into_inner()?.sync_all()?;
+
+    serde_json::from_reader::<_, Config>(BufReader::new(File::open(temp_path)?))
+        .context("Migrated state does not read back")?;
+
+    Ok(())
+}
+
+/* Moves the bar along with the bytes read through it */
+struct Progress<R> {
+    inner: R,
+    bar: Bar,
+}
+
+impl<R: Read> Read for Progress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bar.update(read)?;
+
+        Ok(read)
+    }
+}
+
+/* Copies the state's top-level fields to output one at a time, upgrading each on the way */
+struct Upgrade<W> {
+    output: W,
+    from: u32,
+}
+
+impl<'de, W: Write> Visitor<'de> for Upgrade<W> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a state object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut fields: A) -> Result<(), A::Error> {
+        let mut serializer = serde_json::Serializer::pretty(self.output);
+        let mut map = serializer.serialize_map(None).map_err(A::Error::custom)?;
+        map.serialize_entry("version", &config::VERSION)
+            .map_err(A::Error::custom)?;
+
+        while let Some(field) = fields.next_key::<String>()? {
+            let mut value = fields.next_value::<Value>()?;
+            if field == "version" {
+                continue;
+            }
+            for from in self.from..config::VERSION {
+                config::migrate_field(&field, &mut value, from).map_err(A::Error::custom)?;
+            }
+            map.serialize_entry(&field, &value)
+                .map_err(A::Error::custom)?;
+        }
+
+        map.end().map_err(A::Error::custom)
+    }
+}
+
+/* Every file under the HTML cache, keyed by its path below it with / separators */
+fn cache_manifest(html_path: &str) -> BTreeMap<String, Sum> {
+    let paths = WalkDir::new(html_path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .collect::<Vec<_>>();
+
+    paths
+        .into_par_iter()
+        .filter_map(|path| {
+            let bytes = fs::read(&path).ok()?;
+            let relative = path.strip_prefix(html_path).ok()?;
+            let relative = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            Some((relative, Sum::of(&bytes)))
+        })
+        .collect()
+}
+
+/* Cached files that differ from the exported ones lose their validators, so they are fetched in full */
+fn check_cache(config: &mut Config, cache: &BTreeMap<String, Sum>, html_path: &str) {
+    let mut identical = 0;
+    let mut missing = 0;
+    let mut different = 0;
+    for (relative, sum) in cache {
+        let path = cache::join(html_path, &[relative]);
+        match fs::read(&path) {
+            Ok(bytes) if Sum::of(&bytes) == *sum => identical += 1,
+            Ok(_bytes) => {
+                different += 1;
+                let logical = path.strip_suffix(".gz").unwrap_or(&path);
+                config.validators.remove(logical);
+            }
+            Err(_error) => missing += 1,
+        }
+    }
+
+    println!(
+        "HTML cache: {identical} of {} files identical, {different} different, {missing} missing",
+        cache.len()
+    );
+}
+
+/* Moves the keys of a state map keyed by path from one base path to another */
+fn rebase<V>(paths: &mut BTreeMap<String, V>, from: &str, to: &str) {
+    let from = cache::normalize(from);
+    *paths = std::mem::take(paths)
+        .into_iter()
+        .map(|(path, value)| match Path::new(&path).strip_prefix(&from) {
+            Ok(relative) => {
+                let path = Path::new(to).join(relative);
+                (cache::normalize(&path.to_string_lossy()), value)
+            }
+            Err(_error) => (path, value),
+        })
+        .collect();
+}
+
+fn write<W: Write>(writer: W, files: &[(&str, Vec<u8>)]) -> Result<W> {
+    let mut builder = Builder::new(writer);
+
+    for (name, bytes) in files {
+        let mut header = Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, bytes.as_slice())?;
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+/* Compression is told by the leading bytes rather than the name, which may have changed in transit */
+fn read(input: &str) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut magic = [0; 4];
+    let read = File::open(input)?.read(&mut magic)?;
+
+    let file = File::open(input)?;
+    let reader: Box<dyn Read> = match &magic[..read] {
+        bytes if bytes.starts_with(&ZSTD_MAGIC) => Box::new(zstd::Decoder::new(file)?),
+        bytes if bytes.starts_with(&GZIP_MAGIC) => Box::new(GzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+
+    let mut files = BTreeMap::new();
+    for entry in Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        files.insert(name, bytes);
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebases_paths_under_the_old_base_path() {
+        let old = cache::join("old", &["HTML", "1.HTML"]);
+        let other = cache::join("elsewhere", &["1.HTML"]);
+        let mut paths = BTreeMap::from([(old, 1), (other.clone(), 2)]);
+        rebase(&mut paths, "old", "new");
+
+        assert_eq!(
+            paths.get(&cache::join("new", &["HTML", "1.HTML"])),
+            Some(&1)
+        );
+        assert_eq!(paths.get(&other), Some(&2));
+    }
+
+    #[test]
+    fn round_trips_an_archive() {
+        let files = [("A.JSON", b"{}".to_vec()), ("B.JSON", b"[1]".to_vec())];
+        let tar = write(Vec::new(), &files).unwrap();
+        let path = std::env::temp_dir().join(format!("torrents-{}.tar", std::process::id()));
+        fs::write(&path, tar).unwrap();
+        let read = read(&path.to_string_lossy());
+        fs::remove_file(&path).unwrap();
+
+        let read = read.unwrap();
+        assert_eq!(read.get("A.JSON"), Some(&b"{}".to_vec()));
+        assert_eq!(read.get("B.JSON"), Some(&b"[1]".to_vec()));
+    }
+
+    #[test]
+    fn migrates_the_state_on_disk() {
+        let base = std::env::temp_dir().join(format!("torrents-migrate-{}", std::process::id()));
+        fs::create_dir_all(&base).unwrap();
+        let base_path = base.to_string_lossy();
+        let state_path = Config::get_path(&base_path).unwrap();
+        let path = cache::join("base", &["HTML", "1.HTML"]);
+        let doubled = path.replace(std::path::MAIN_SEPARATOR, "//");
+        let original =
+            serde_json::json!({"version": 1, "entries": ["a"], "statuses": {doubled: 200}});
+        fs::write(&state_path, original.to_string()).unwrap();
+
+        let migrated = migrate(&base_path);
+        let state = fs::read_to_string(&state_path);
+        let backup = fs::read_to_string(state_path.with_extension("JSON.PRE-MIGRATE"));
+        let again = migrate(&base_path);
+        fs::remove_dir_all(&base).unwrap();
+
+        migrated.unwrap();
+        again.unwrap();
+        let state = state.unwrap();
+        let value = serde_json::from_str::<Value>(&state).unwrap();
+        assert_eq!(config::version(&value), config::VERSION);
+        let config = serde_json::from_value::<Config>(value).unwrap();
+        assert_eq!(config.entries, ["a"]);
+        assert_eq!(config.statuses.get(&path), Some(&200));
+        assert_eq!(backup.unwrap(), original.to_string());
+    }
+
+    #[test]
+    fn leaves_an_unreadable_state_alone() {
+        let base = std::env::temp_dir().join(format!("torrents-broken-{}", std::process::id()));
+        fs::create_dir_all(&base).unwrap();
+        let base_path = base.to_string_lossy();
+        let state_path = Config::get_path(&base_path).unwrap();
+        fs::write(&state_path, r#"{"entries": "#).unwrap();
+
+        let migrated = migrate(&base_path);
+        let state = fs::read_to_string(&state_path);
+        fs::remove_dir_all(&base).unwrap();
+
+        assert!(migrated.is_err());
+        assert_eq!(state.unwrap(), r#"{"entries": "#);
+    }
+}
@@ -0,0 +1,180 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Utc};
+use walkdir::WalkDir;
+
+use crate::{adapter::Adapter, cache, config::Config};
+
+/* Totals of the local archive, read from the state and the disk without touching the network */
+pub fn stats(
+    adapter: &Adapter,
+    config: &Config,
+    html_path: &str,
+    torrents_path: &str,
+) -> Result<()> {
+    let pages = adapter.layout.pages_path(html_path);
+    let entries = adapter.layout.entries_path(html_path);
+
+    let downloaded = config
+        .torrents
+        .iter()
+        .filter_map(|url| adapter.torrent_path(torrents_path, url))
+        .filter(|path| Path::new(path).exists())
+        .count();
+    let pending = config.torrents.len() - downloaded;
+
+    println!("{:<24}  {:>10}", "Pages known", config.max_pages);
+    println!("{:<24}  {:>10}", "Pages cached", usage(&pages).0);
+    println!("{:<24}  {:>10}", "Entries known", config.entries.len());
+    println!("{:<24}  {:>10}", "Entries cached", usage(&entries).0);
+    println!("{:<24}  {:>10}", "Entries retired", config.tombstones.len());
+    println!("{:<24}  {:>10}", "Entries archived", config.archived.len());
+    println!("{:<24}  {:>10}", "Torrents known", config.torrents.len());
+    println!("{:<24}  {:>10}", "Torrents downloaded", downloaded);
+    println!("{:<24}  {:>10}", "Torrents pending", pending);
+    println!("{:<24}  {:>10}", "Torrents sent", config.sent.len());
+    println!("{:<24}  {:>10}", "Torrents adopted", config.adopted.len());
+
+    println!();
+    println!("{:<40}  {:>10}  {:>12}", "DIRECTORY", "FILES", "BYTES");
+    for dir in [pages.as_str(), entries.as_str(), torrents_path] {
+        let (files, bytes) = usage(dir);
+        println!("{dir:<40}  {files:>10}  {bytes:>12}");
+    }
+
+    println!();
+    match &config.last_run {
+        Some(last_run) => {
+            println!("{:<24}  {}", "Last run", last_run.run_id);
+            println!("{:<24}  {}", "Started", time(last_run.started));
+            println!("{:<24}  {}", "Finished", time(last_run.finished));
+            println!("{:<24}  {:>10}", "Requested", last_run.total.requested);
+            println!("{:<24}  {:>10}", "Succeeded", last_run.total.succeeded);
+            println!("{:<24}  {:>10}", "Failed", last_run.total.failed);
+            println!("{:<24}  {:>10}", "Skipped", last_run.total.skipped);
+        }
+        None => println!("No run recorded yet"),
+    }
+
+    Ok(())
+}
+
+/* File count and total size under dir */
+fn usage(dir: &str) -> (usize, u64) {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .fold((0, 0), |(files, bytes), metadata| {
+            (files + 1, bytes + metadata.len())
+        })
+}
+
+fn time(time: Option<DateTime<Utc>>) -> String {
+    time.map_or("-".to_string(), |time| time.to_rfc3339())
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Coverage {
+    pub entries: usize,
+    pub metadata: usize,
+    pub downloaded: usize,
+    pub verified: usize,
+}
+
+impl Coverage {
+    fn add(&mut self, metadata: bool, downloaded: bool, verified: bool) {
+        self.entries += 1;
+        self.metadata += metadata as usize;
+        self.downloaded += downloaded as usize;
+        self.verified += verified as usize;
+    }
+
+    fn print(&self, group: &str) {
+        println!(
+            "{group:<12}  {:>8}  {:>8}  {:>10}  {:>8}",
+            self.entries,
+            percent(self.metadata, self.entries),
+            percent(self.downloaded, self.entries),
+            percent(self.verified, self.entries)
+        );
+    }
+}
+
+/* An entry counts as downloaded once every torrent it links is on disk, and verified once each has a checksum */
+pub fn coverage(
+    adapter: &Adapter,
+    config: &Config,
+    html_path: &str,
+    torrents_path: &str,
+) -> (
+    Coverage,
+    BTreeMap<String, Coverage>,
+    BTreeMap<String, Coverage>,
+) {
+    let mut total = Coverage::default();
+    let mut kinds = BTreeMap::<String, Coverage>::new();
+    let mut years = BTreeMap::<String, Coverage>::new();
+
+    for entry in config
+        .entries
+        .iter()
+        .filter(|entry| !config.tombstones.contains(*entry))
+    {
+        let metadata = config.metadata.get(entry);
+        let paths = config
+            .links
+            .get(entry)
+            .into_iter()
+            .flatten()
+            .filter_map(|url| adapter.torrent_path(torrents_path, url))
+            .collect::<Vec<_>>();
+        let downloaded = !paths.is_empty() && paths.iter().all(|path| Path::new(path).exists());
+        let verified = downloaded && paths.iter().all(|path| config.checksums.contains_key(path));
+
+        let kind = metadata.map_or("unscraped".to_string(), |metadata| {
+            format!("{:?}", metadata.kind).to_lowercase()
+        });
+        /* The site shows no dates, so the entry is dated by when it was first saved */
+        let year = cache::metadata(&adapter.entry_path(html_path, entry))
+            .and_then(|metadata| metadata.modified())
+            .map_or("unknown".to_string(), |modified| {
+                DateTime::<Utc>::from(modified).year().to_string()
+            });
+
+        for coverage in [
+            &mut total,
+            kinds.entry(kind).or_default(),
+            years.entry(year).or_default(),
+        ] {
+            coverage.add(metadata.is_some(), downloaded, verified);
+        }
+    }
+
+    (total, kinds, years)
+}
+
+pub fn print_coverage(adapter: &Adapter, config: &Config, html_path: &str, torrents_path: &str) {
+    let (total, kinds, years) = coverage(adapter, config, html_path, torrents_path);
+
+    for (title, groups) in [("CATEGORY", kinds), ("YEAR", years)] {
+        println!(
+            "{title:<12}  {:>8}  {:>8}  {:>10}  {:>8}",
+            "ENTRIES", "METADATA", "DOWNLOADED", "VERIFIED"
+        );
+        for (group, coverage) in &groups {
+            coverage.print(group);
+        }
+        total.print("total");
+        println!();
+    }
+}
+
+fn percent(part: usize, whole: usize) -> String {
+    match whole {
+        0 => "-".to_string(),
+        _ => format!("{:.1}%", part as f64 * 100.0 / whole as f64),
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+};
+
+use chrono::Utc;
+use scraper::Html;
+use tracing::warn;
+
+use crate::{
+    adapter::Adapter,
+    blocklist::Blocklist,
+    config::Config,
+    download::Downloader,
+    events::say,
+    hash::Algorithm,
+    metadata::Metadata,
+    settings::{Limits, Schedule},
+    summary::Tally,
+};
+
+/* What the entry stage scraped from one entry page */
+struct Scraped {
+    links: Vec<String>,
+    metadata: Metadata,
+    hash: String,
+}
+
+/* Fetches pages, entries and torrents at once, queueing what each page or entry links to as soon as it lands */
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    adapter: &Adapter,
+    downloader: &Downloader,
+    blocklist: &Blocklist,
+    config: &mut Config,
+    schedule: &Schedule,
+    limits: &Limits,
+    html_path: &str,
+    torrents_path: &str,
+    max_pages: usize,
+    direct: bool,
+) -> ([Tally; 3], Vec<(String, String)>) {
+    say!("Streaming {max_pages} pages, their entries and torrents...");
+
+    let known = config.entries.iter().cloned().collect::<HashSet<_>>();
+    let pages = Mutex::new(BTreeMap::new());
+    let entries = Mutex::new(BTreeMap::new());
+    /* Entry of each queued entry page, keyed by its cache path */
+    let queued_entries = Mutex::new(HashMap::new());
+    let queued_torrents = Mutex::new(HashSet::new());
+    let saved_torrents = Mutex::new(Vec::new());
+    let max_entries = AtomicUsize::new(limits.max_entries.unwrap_or(usize::MAX));
+    let max_torrents = AtomicUsize::new(limits.max_torrents.unwrap_or(usize::MAX));
+
+    let (pages_tx, pages_rx) = mpsc::channel();
+    let (entries_tx, entries_rx) = mpsc::channel();
+    let (torrents_tx, torrents_rx) = mpsc::channel();
+
+    for page in 1..=max_pages {
+        let path = adapter.layout.page_path(html_path, page);
+        let _ = pages_tx.send((adapter.page_url(page), path));
+    }
+    drop(pages_tx);
+
+    /* Limits count down as files are queued, so they cap this run the same way they cap the steps */
+    let take = |limit: &AtomicUsize| {
+        limit
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+    };
+
+    let tallies = thread::scope(|scope| {
+        let pages_stage = scope.spawn(|| {
+            let entries_tx = entries_tx;
+            downloader.stream_files(pages_rx, &schedule.pages, |(url, path), contents| {
+                let Some(page) = page_number(path) else {
+                    warn!(url, "Failed to read the page number of a streamed page");
+                    return;
+                };
+                let links = adapter.entry_links(&Html::parse_document(contents));
+
+                for entry in &links {
+                    let path = adapter.entry_path(html_path, entry);
+                    if config.tombstones.contains(entry)
+                        || config.archived.contains_key(entry)
+                        || config.blocked.contains_key(entry)
+                        || blocklist.matches(&[entry.as_str()]).is_some()
+                        || downloader.is_cached(&path)
+                    {
+                        continue;
+                    }
+                    {
+                        let mut queued = queued_entries.lock().unwrap();
+                        if queued.contains_key(&path) || !take(&max_entries) {
+                            continue;
+                        }
+                        queued.insert(path.clone(), entry.clone());
+                    }
+
+                    let _ = entries_tx.send((adapter.entry_url(entry), path));
+                }
+
+                pages.lock().unwrap().insert(page, links);
+            })
+        });
+
+        let entries_stage = scope.spawn(|| {
+            let torrents_tx = torrents_tx;
+            downloader.stream_files(entries_rx, &schedule.entries, |(url, path), contents| {
+                let Some(entry) = queued_entries.lock().unwrap().get(path).cloned() else {
+                    warn!(url, "Failed to read the entry name of a streamed entry");
+                    return;
+                };
+                let html = Html::parse_document(contents);
+                let links = adapter.torrent_links(&html);
+                let metadata = Metadata::scrape(&html);
+                let blocked = blocklist.matches(&[metadata.title.as_str()]).is_some();
+
+                /* In direct mode step 7 hands the links to the client instead */
+                for url in links.iter().filter(|_| !direct && !blocked) {
+                    let Some(path) = adapter.torrent_path(torrents_path, url) else {
+                        continue;
+                    };
+                    if Path::new(&path).exists()
+                        || config.blocked.contains_key(url)
+                        || blocklist.matches(&[url.as_str()]).is_some()
+                        || !queued_torrents.lock().unwrap().insert(url.clone())
+                        || !take(&max_torrents)
+                    {
+                        continue;
+                    }
+
+                    let _ = torrents_tx.send((url.clone(), path));
+                }
+
+                let scraped = Scraped {
+                    links,
+                    metadata,
+                    hash: Algorithm::Blake3.digest(contents.as_bytes()),
+                };
+                entries.lock().unwrap().insert(entry, scraped);
+            })
+        });
+
+        let torrents_stage = scope.spawn(|| {
+            downloader.stream_files(torrents_rx, &schedule.torrents, |torrent, _contents| {
+                saved_torrents.lock().unwrap().push(torrent.clone());
+            })
+        });
+
+        [pages_stage, entries_stage, torrents_stage].map(|stage| stage.join().unwrap())
+    });
+
+    let [pages_tally, entries_tally, mut torrents_tally] = tallies;
+    let unwritten = downloader.flush();
+    torrents_tally.succeeded = torrents_tally.succeeded.saturating_sub(unwritten);
+    torrents_tally.failed += unwritten;
+
+    let mut new_entries = Vec::new();
+    for (page, links) in pages.into_inner().unwrap() {
+        new_entries.extend(links.iter().filter(|link| !known.contains(*link)).cloned());
+        config.pages.insert(page, links);
+    }
+    new_entries.sort();
+    new_entries.dedup();
+    say!("Found {} new entries", new_entries.len());
+
+    config.introduce(&new_entries);
+    config.entries.extend(new_entries);
+    config.entries.sort();
+    config.entries.dedup();
+
+    let fetched_at = Utc::now();
+    let mut torrents = Vec::new();
+    for (entry, scraped) in entries.into_inner().unwrap() {
+        let fetched = config.fetched.entry(entry.clone()).or_default();
+        fetched.at = Some(fetched_at);
+        fetched.hash = Some(scraped.hash);
+
+        torrents.extend(scraped.links.iter().cloned());
+        config.links.insert(entry.clone(), scraped.links);
+        config.metadata.insert(entry, scraped.metadata);
+    }
+
+    config.introduce(&torrents);
+    config.torrents.extend(torrents);
+    config.torrents.sort();
+    config.torrents.dedup();
+    config.max_pages = max_pages;
+
+    let tallies = [pages_tally, entries_tally, torrents_tally];
+
+    (tallies, saved_torrents.into_inner().unwrap())
+}
+
+fn page_number(path: &str) -> Option<usize> {
+    let name = Path::new(path).file_stem()?.to_str()?;
+
+    name.parse().ok()
+}
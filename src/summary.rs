@@ -0,0 +1,111 @@
+use std::{
+    ops::AddAssign,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Tally {
+    pub requested: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+impl AddAssign for Tally {
+    fn add_assign(&mut self, other: Self) {
+        self.requested += other.requested;
+        self.succeeded += other.succeeded;
+        self.failed += other.failed;
+        self.skipped += other.skipped;
+    }
+}
+
+/* What the last pipeline run did, kept in the state for `stats` */
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LastRun {
+    pub run_id: String,
+    pub started: Option<DateTime<Utc>>,
+    pub finished: Option<DateTime<Utc>>,
+    pub total: Tally,
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    steps: Vec<(usize, &'static str, Tally)>,
+    /* How long each step took, from its start to the start of the next */
+    timings: Vec<(usize, Duration)>,
+    current: Option<(usize, Instant)>,
+}
+
+impl Summary {
+    pub fn start(&mut self, step: usize) {
+        self.finish();
+        self.current = Some((step, Instant::now()));
+    }
+
+    /* Stops the clock of the step that is running, if any */
+    pub fn finish(&mut self) {
+        if let Some((step, started)) = self.current.take() {
+            self.timings.push((step, started.elapsed()));
+        }
+    }
+
+    pub fn timings(&self) -> &[(usize, Duration)] {
+        &self.timings
+    }
+
+    pub fn record(&mut self, step: usize, name: &'static str, tally: Tally) {
+        info!(
+            step,
+            name,
+            requested = tally.requested,
+            succeeded = tally.succeeded,
+            failed = tally.failed,
+            skipped = tally.skipped,
+            "Finished step"
+        );
+        self.steps.push((step, name, tally));
+    }
+
+    pub fn steps(&self) -> &[(usize, &'static str, Tally)] {
+        &self.steps
+    }
+
+    pub fn total(&self) -> Tally {
+        let mut total = Tally::default();
+        for (_step, _name, tally) in &self.steps {
+            total += *tally;
+        }
+
+        total
+    }
+
+    pub fn print(&self) {
+        print!("{}", self.render());
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "{:<28}  {:>9}  {:>9}  {:>9}  {:>9}\n",
+            "STEP", "REQUESTED", "SUCCEEDED", "FAILED", "SKIPPED"
+        );
+        for (step, name, tally) in &self.steps {
+            text += &format!(
+                "{:<28}  {:>9}  {:>9}  {:>9}  {:>9}\n",
+                format!("{step}. {name}"),
+                tally.requested,
+                tally.succeeded,
+                tally.failed,
+                tally.skipped
+            );
+        }
+
+        text
+    }
+}
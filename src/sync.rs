@@ -0,0 +1,317 @@
+use std::{
+    collections::HashSet,
+    env,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::{self, ExitStatus},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use kdam::{
+    rayon::{prelude::*, ThreadPoolBuilder},
+    BarExt, TqdmParallelIterator,
+};
+use reqwest::{blocking::Client, Proxy};
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    events::{self, say},
+    forward,
+    history::{self, RunRecord},
+    proxy::{self, Listing},
+    settings::{Mode, Settings},
+    summary::Tally,
+    throttle, tls,
+};
+
+/* A profile and the base path its state and files live under */
+struct Target {
+    name: String,
+    base_path: String,
+    /* The proxy lists it would load itself; None when it runs over Tor or without proxies */
+    proxies: Option<Vec<String>>,
+}
+
+/* How one target's run ended, with its history record when it got far enough to write one */
+struct Outcome {
+    status: Result<ExitStatus>,
+    record: Option<RunRecord>,
+}
+
+/* Runs the pipeline once per profile, each in a process of its own, then sums the runs up; apply lays the command line flags over each profile */
+pub fn sync(
+    settings: &Settings,
+    settings_path: &str,
+    root: &str,
+    base_path: &str,
+    profiles: &[String],
+    all: bool,
+    apply: impl Fn(&mut Settings),
+) -> Result<()> {
+    let names = match all {
+        true if settings.sync.targets.is_empty() => Settings::profiles(settings_path)?,
+        true => settings.sync.targets.clone(),
+        false => profiles.to_vec(),
+    };
+    if names.is_empty() {
+        bail!("Nothing to sync; name profiles, or give --all with [profiles] in {settings_path}");
+    }
+
+    let targets = names
+        .iter()
+        .map(|name| target(settings_path, root, name, &apply))
+        .collect::<Result<Vec<_>>>()?;
+    /* Two targets on one base path would share a state, and the second would only wait for the first */
+    let mut seen = HashSet::new();
+    for target in &targets {
+        if !seen.insert(&target.base_path) {
+            bail!(
+                "Profile {} shares the base path {} with another target; give each its own base_path",
+                target.name,
+                target.base_path
+            );
+        }
+    }
+
+    let pool = match settings.sync.share_proxies {
+        true => share_proxies(settings, base_path, &targets)?,
+        false => None,
+    };
+    /* Each target runs under its own profile, and on the shared pool in place of the proxy lists given here */
+    let drop: &[&str] = match pool {
+        Some(_) => &["profile", "proxies_path"],
+        None => &["profile"],
+    };
+    let arguments = forward::leading(&env::args().skip(1).collect::<Vec<_>>(), drop)?;
+
+    let concurrent = settings.sync.concurrency > 1;
+    let started = Utc::now();
+    let threads = ThreadPoolBuilder::new()
+        .num_threads(settings.sync.concurrency.max(1))
+        .build()?;
+    let outcomes = threads.install(|| {
+        targets
+            .par_iter()
+            .map(|target| {
+                if !concurrent {
+                    say!("Syncing {}...", target.name);
+                }
+                let status = run(&arguments, pool.as_deref(), target, concurrent);
+                let record = history::load(&target.base_path)
+                    .ok()
+                    .and_then(|runs| runs.into_iter().last())
+                    .filter(|run| run.started.is_some_and(|at| at >= started));
+
+                Outcome { status, record }
+            })
+            .collect::<Vec<_>>()
+    });
+
+    print(&targets, &outcomes);
+
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| !outcome.status.as_ref().is_ok_and(ExitStatus::success))
+        .count();
+    if failed > 0 {
+        bail!("{failed} of {} targets failed", targets.len());
+    }
+
+    Ok(())
+}
+
+/* Where a profile keeps its state, the way main works it out, and where it finds proxies */
+fn target(
+    settings_path: &str,
+    root: &str,
+    name: &str,
+    apply: &impl Fn(&mut Settings),
+) -> Result<Target> {
+    let mut settings = Settings::load(settings_path, Some(name))
+        .with_context(|| format!("Failed to load profile {name} from {settings_path}"))?;
+    apply(&mut settings);
+    let base_path = match &settings.base_path {
+        Some(path) => Path::new(root).join(path).display().to_string(),
+        None => root.to_string(),
+    };
+
+    let uses_proxies = !settings.tor.enabled && settings.network.mode != Mode::Direct;
+
+    Ok(Target {
+        name: name.to_string(),
+        base_path,
+        proxies: uses_proxies.then(|| settings.proxies.paths.clone()),
+    })
+}
+
+/* Loads the proxy lists of every target, checks each proxy once and writes the working ones to TORRENTS.SYNC.PROXIES, which targets on proxies then run on in place of their own lists */
+fn share_proxies(
+    settings: &Settings,
+    base_path: &str,
+    targets: &[Target],
+) -> Result<Option<PathBuf>> {
+    let mut sources = targets
+        .iter()
+        .filter_map(|target| target.proxies.as_ref())
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    sources.sort();
+    sources.dedup();
+    if sources.is_empty() {
+        return Ok(None);
+    }
+
+    let tls = tls::Policy::load(&settings.tls)?;
+    let direct = tls.direct_client(&settings.network)?;
+    let listings = match proxy::load_lists(&sources, &direct) {
+        Ok(listings) => listings,
+        Err(error) => {
+            warn!(%error, "Failed to load proxies, leaving each target to load its own");
+            return Ok(None);
+        }
+    };
+    let local = proxy::local_address(&direct)
+        .context("Failed to get the local address, which proxies are checked against")?;
+
+    let connect_timeout = Duration::from_secs(settings.network.connect_timeout);
+    let request_timeout = Duration::from_secs(settings.network.request_timeout);
+    let build_client = |listing: &Listing| -> reqwest::Result<Client> {
+        let builder = Client::builder()
+            .user_agent(&settings.network.user_agent)
+            .connect_timeout(connect_timeout)
+            .timeout(request_timeout)
+            .proxy(Proxy::all(&listing.proxy)?);
+
+        tls.apply(builder).build()
+    };
+
+    let max_checks = listings.len();
+    let mut bar = events::bar(max_checks);
+    bar.write(format!("Checking {max_checks} proxies for every target..."))?;
+    let threads = ThreadPoolBuilder::new()
+        .num_threads(settings.proxies.check_concurrency)
+        .build()?;
+    let checks = threads.install(|| {
+        listings
+            .into_par_iter()
+            .tqdm_with_bar(bar)
+            .map(|listing| {
+                let client = build_client(&listing);
+
+                proxy::check(listing, client, &local)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    /* Targets still check the pool at step 1, for their own anonymity needs, but only what worked here */
+    let working = checks
+        .iter()
+        .filter(|check| check.outcome.is_ok())
+        .map(|check| match check.labels.is_empty() {
+            true => check.proxy.clone(),
+            false => format!("{} # {}", check.proxy, check.labels.join(", ")),
+        })
+        .collect::<Vec<_>>();
+    if working.is_empty() {
+        warn!("No shared proxy works, leaving each target to its own lists");
+        return Ok(None);
+    }
+
+    let path = Config::get_path(base_path)?.with_extension("SYNC.PROXIES");
+    fs::write(&path, working.join("\n") + "\n")?;
+    say!(
+        "Sharing {} working proxies of {max_checks} with every target",
+        working.len()
+    );
+    info!(proxies = working.len(), path = %path.display(), "Shared proxy pool");
+
+    Ok(Some(path))
+}
+
+/* One run of the pipeline; concurrent runs write to TORRENTS.SYNC.LOG under their base path so their output does not interleave */
+fn run(
+    arguments: &[String],
+    pool: Option<&Path>,
+    target: &Target,
+    concurrent: bool,
+) -> Result<ExitStatus> {
+    let mut command = process::Command::new(env::current_exe()?);
+    command.args(arguments).args(["--profile", &target.name]);
+    if let (Some(pool), Some(_own)) = (pool, &target.proxies) {
+        command.arg("--proxies-path").arg(pool);
+    }
+
+    if concurrent {
+        fs::create_dir_all(&target.base_path)?;
+        let log = File::create(Config::get_path(&target.base_path)?.with_extension("SYNC.LOG"))?;
+        command.stdout(log.try_clone()?).stderr(log);
+    }
+
+    info!(profile = target.name, "Starting sync target");
+    let status = command.status()?;
+    info!(profile = target.name, %status, "Finished sync target");
+
+    Ok(status)
+}
+
+/* One line per target and their sum, from the history each run recorded */
+fn print(targets: &[Target], outcomes: &[Outcome]) {
+    say!(
+        "{:<20}  {:<12}  {:>9}  {:>9}  {:>9}  {:>9}  {:>11}",
+        "PROFILE",
+        "STATUS",
+        "SECONDS",
+        "REQUESTED",
+        "SUCCEEDED",
+        "FAILED",
+        "TRANSFERRED"
+    );
+
+    let mut total = Tally::default();
+    let mut seconds = 0.0;
+    let mut transferred = 0;
+    for (target, outcome) in targets.iter().zip(outcomes) {
+        let status = match &outcome.status {
+            Ok(status) if status.success() => "ok".to_string(),
+            Ok(status) => match status.code() {
+                Some(code) => format!("exit {code}"),
+                None => "killed".to_string(),
+            },
+            Err(error) => {
+                warn!(profile = target.name, %error, "Failed to start sync target");
+                "not started".to_string()
+            }
+        };
+
+        let Some(record) = &outcome.record else {
+            say!("{:<20}  {status:<12}  {:>9}", target.name, "-");
+            continue;
+        };
+        total += record.total;
+        seconds += record.seconds();
+        transferred += record.transferred;
+        say!(
+            "{:<20}  {status:<12}  {:>9.0}  {:>9}  {:>9}  {:>9}  {:>11}",
+            target.name,
+            record.seconds(),
+            record.total.requested,
+            record.total.succeeded,
+            record.total.failed,
+            throttle::format_bytes(record.transferred as f64)
+        );
+    }
+
+    say!(
+        "{:<20}  {:<12}  {seconds:>9.0}  {:>9}  {:>9}  {:>9}  {:>11}",
+        "TOTAL",
+        "",
+        total.requested,
+        total.succeeded,
+        total.failed,
+        throttle::format_bytes(transferred as f64)
+    );
+}
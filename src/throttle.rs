@@ -0,0 +1,122 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+
+/* A token bucket shared by every worker, so the cap holds for the whole run rather than per exit */
+pub struct Throttle {
+    rate: Option<u64>,
+    bucket: Mutex<(Instant, f64)>,
+    bytes: AtomicU64,
+    start: Instant,
+}
+
+impl Throttle {
+    pub fn new(rate: Option<u64>) -> Self {
+        let rate = rate.filter(|rate| *rate > 0);
+
+        Self {
+            rate,
+            bucket: Mutex::new((Instant::now(), rate.unwrap_or_default() as f64)),
+            bytes: AtomicU64::new(0),
+            start: Instant::now(),
+        }
+    }
+
+    /* Counts bytes just received and sleeps long enough to stay under the rate */
+    pub fn take(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+
+        let Some(rate) = self.rate else {
+            return;
+        };
+        let rate = rate as f64;
+
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let (last, tokens) = &mut *bucket;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+            *tokens -= bytes as f64;
+            *last = now;
+
+            (*tokens < 0.0).then(|| Duration::from_secs_f64(-*tokens / rate))
+        };
+
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /* Average since the start of the run, in bytes per second */
+    pub fn speed(&self) -> f64 {
+        self.bytes() as f64 / self.start.elapsed().as_secs_f64().max(1.0)
+    }
+}
+
+/* Accepts "5MB/s", "512K", "1.5 MiB/s" or a plain byte count; units are binary, and sizes read the same without the /s */
+pub fn parse_bytes(text: &str) -> Result<u64> {
+    let text = text.trim().trim_end_matches("/s").trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number = number
+        .parse::<f64>()
+        .with_context(|| format!("Invalid byte count {text}"))?;
+
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        unit => bail!("Unknown byte unit {unit}"),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_byte_counts() {
+        assert_eq!(parse_bytes("512").unwrap(), 512);
+        assert_eq!(parse_bytes("2K").unwrap(), 2048);
+        assert_eq!(parse_bytes("1.5 MiB/s").unwrap(), 3 << 19);
+        assert_eq!(parse_bytes("1gb").unwrap(), 1 << 30);
+        assert!(parse_bytes("fast").is_err());
+        assert!(parse_bytes("5 PB").is_err());
+    }
+
+    #[test]
+    fn formats_bytes_in_the_largest_unit() {
+        assert_eq!(format_bytes(512.0), "512.0 B");
+        assert_eq!(format_bytes((3 << 19) as f64), "1.5 MiB");
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fs, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use reqwest::{
+    blocking::{Client, ClientBuilder},
+    tls::Version,
+    Certificate,
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::settings::{Network, TlsSettings};
+
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+pub enum TlsVersion {
+    #[value(name = "1.2")]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[value(name = "1.3")]
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    fn version(&self) -> Version {
+        match self {
+            Self::Tls12 => Version::TLS_1_2,
+            Self::Tls13 => Version::TLS_1_3,
+        }
+    }
+}
+
+/* What every client trusts, loaded once so building a client per proxy never touches the disk */
+#[derive(Clone)]
+pub struct Policy {
+    insecure: bool,
+    certificates: Vec<Certificate>,
+    min_version: Option<TlsVersion>,
+}
+
+impl Policy {
+    pub fn load(settings: &TlsSettings) -> Result<Self> {
+        let certificates = match &settings.ca_bundle {
+            Some(path) => {
+                load_bundle(path).with_context(|| format!("Failed to load CA bundle {path}"))?
+            }
+            None => Vec::new(),
+        };
+
+        if settings.insecure {
+            warn!("Certificate checks are off, so any proxy or host can read and change traffic");
+        }
+
+        Ok(Self {
+            insecure: settings.insecure,
+            certificates,
+            min_version: settings.min_version,
+        })
+    }
+
+    /* For what a run fetches besides the site, such as proxy and tracker lists, without a proxy but with the same timeouts and user agent */
+    pub fn direct_client(&self, network: &Network) -> reqwest::Result<Client> {
+        let builder = Client::builder()
+            .user_agent(&network.user_agent)
+            .connect_timeout(Duration::from_secs(network.connect_timeout))
+            .timeout(Duration::from_secs(network.request_timeout))
+            .no_proxy();
+
+        self.apply(builder).build()
+    }
+
+    pub fn apply(&self, builder: ClientBuilder) -> ClientBuilder {
+        let builder = self
+            .certificates
+            .iter()
+            .cloned()
+            .fold(builder, ClientBuilder::add_root_certificate);
+        let builder = match self.min_version {
+            Some(version) => builder.min_tls_version(version.version()),
+            None => builder,
+        };
+
+        builder.danger_accept_invalid_certs(self.insecure)
+    }
+}
+
+/* A PEM file of one or more certificates, or a single DER certificate */
+fn load_bundle(path: &str) -> Result<Vec<Certificate>> {
+    let bytes = fs::read(path)?;
+    let Ok(text) = std::str::from_utf8(&bytes) else {
+        return Ok(vec![Certificate::from_der(&bytes)?]);
+    };
+
+    let certificates = text
+        .split_inclusive(PEM_END)
+        .filter(|block| block.contains(PEM_END))
+        .map(|block| Certificate::from_pem(block.trim().as_bytes()))
+        .collect::<reqwest::Result<Vec<_>>>()?;
+    if certificates.is_empty() {
+        bail!("No certificates found");
+    }
+
+    Ok(certificates)
+}
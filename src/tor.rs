@@ -0,0 +1,46 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+};
+
+use anyhow::{bail, Result};
+
+/* Consecutive failures on one worker before asking Tor for a new circuit */
+pub const MAX_FAILURES: usize = 5;
+
+#[derive(Debug)]
+pub struct Tor {
+    pub proxy: String,
+    pub control: String,
+    pub password: Option<String>,
+}
+
+impl Tor {
+    /* Tor isolates circuits by SOCKS credentials, so a unique username per worker gets its own circuit */
+    pub fn proxy_scheme(&self, worker: Option<usize>) -> String {
+        match worker {
+            Some(worker) => format!("socks5h://worker{worker}:torrents@{}", self.proxy),
+            None => format!("socks5h://{}", self.proxy),
+        }
+    }
+
+    pub fn new_identity(&self) -> Result<()> {
+        let password = self.password.as_deref().unwrap_or_default();
+        let password = password.replace('\\', "\\\\").replace('"', "\\\"");
+
+        let mut stream = TcpStream::connect(&self.control)?;
+        write!(
+            stream,
+            "AUTHENTICATE \"{password}\"\r\nSIGNAL NEWNYM\r\nQUIT\r\n"
+        )?;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if !line.starts_with("250") {
+                bail!("Tor control port refused request: {line}");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use kdam::{rayon::prelude::*, BarExt, TqdmParallelIterator};
+use reqwest::blocking::Client;
+use tracing::warn;
+
+use crate::{
+    bencode::{self, Value},
+    config::Config,
+    events::{self, say},
+    hash::Algorithm,
+    settings::TrackerSettings,
+};
+
+/* Replaces or extends the announce URLs of saved torrents; the info dictionary, and so the infohash, is left alone */
+pub struct Rewriter {
+    strip: bool,
+    trackers: Vec<String>,
+}
+
+impl Rewriter {
+    /* None when the settings ask for no change; a list given as a URL is fetched through client */
+    pub fn new(settings: &TrackerSettings, client: &Client) -> Result<Option<Self>> {
+        let mut trackers = settings.add.clone();
+        if let Some(list) = &settings.list {
+            let text = match list.starts_with("http://") || list.starts_with("https://") {
+                true => client.get(list).send()?.error_for_status()?.text()?,
+                false => {
+                    fs::read_to_string(list).with_context(|| format!("Failed to read {list}"))?
+                }
+            };
+            let lines = text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from);
+            trackers.extend(lines);
+        }
+        trackers.dedup();
+
+        if !settings.strip && trackers.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            strip: settings.strip,
+            trackers,
+        }))
+    }
+
+    /* The rewritten bytes, or None when the file already had the asked trackers */
+    pub fn rewrite(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let bytes = fs::read(path)?;
+        let Value::Dict(mut dict) = bencode::decode(&bytes)? else {
+            bail!("Torrent is not a dictionary");
+        };
+
+        let mut tiers = Vec::new();
+        if !self.strip {
+            if let Some(Value::List(list)) = dict.get(b"announce-list".as_slice()) {
+                tiers.extend(list.iter().filter_map(|tier| {
+                    match tier {
+                        Value::List(tier) => Some(
+                            tier.iter()
+                                .filter_map(Value::as_str)
+                                .map(String::from)
+                                .collect::<Vec<_>>(),
+                        ),
+                        _ => None,
+                    }
+                }));
+            } else if let Some(announce) = dict.get(b"announce".as_slice()).and_then(Value::as_str)
+            {
+                tiers.push(vec![announce.to_string()]);
+            }
+        }
+        for tracker in &self.trackers {
+            if !tiers.iter().flatten().any(|known| known == tracker) {
+                tiers.push(vec![tracker.clone()]);
+            }
+        }
+
+        dict.remove(b"announce".as_slice());
+        dict.remove(b"announce-list".as_slice());
+        if let Some(first) = tiers.iter().flatten().next() {
+            dict.insert(b"announce".to_vec(), bytes_value(first));
+            let list = tiers
+                .iter()
+                .map(|tier| Value::List(tier.iter().map(|url| bytes_value(url)).collect()))
+                .collect();
+            dict.insert(b"announce-list".to_vec(), Value::List(list));
+        }
+
+        let rewritten = bencode::encode(&Value::Dict(dict));
+        if rewritten == bytes {
+            return Ok(None);
+        }
+
+        /* Re-encoding a non-canonical info dictionary would change the infohash, so such files are left as they are */
+        if bencode::info_hash(&rewritten)? != bencode::info_hash(&bytes)? {
+            bail!("Rewriting would change the infohash");
+        }
+
+        let temp = format!("{path}.tmp");
+        fs::write(&temp, &rewritten)?;
+        fs::rename(&temp, path)?;
+
+        Ok(Some(rewritten))
+    }
+
+    /* Rewrites torrents by URL and path, recording the size, checksum and infohash of each rewritten file so verify still trusts it; the caller saves the state */
+    pub fn rewrite_all(
+        &self,
+        config: &mut Config,
+        algorithm: Algorithm,
+        torrents: Vec<(String, String)>,
+    ) -> Result<usize> {
+        let mut bar = events::bar(torrents.len());
+        bar.write(format!(
+            "Rewriting trackers of {} torrents...",
+            torrents.len()
+        ))?;
+
+        let rewritten = torrents
+            .into_par_iter()
+            .tqdm_with_bar(bar)
+            .filter(|(_url, path)| Path::new(path).exists())
+            .filter_map(|(url, path)| match self.rewrite(&path) {
+                Ok(bytes) => {
+                    let bytes = bytes?;
+                    let info_hash = bencode::info_hash(&bytes).ok()?;
+                    Some((
+                        url,
+                        path,
+                        bytes.len() as u64,
+                        algorithm.digest(&bytes),
+                        info_hash,
+                    ))
+                }
+                Err(error) => {
+                    warn!(path, %error, "Failed to rewrite trackers");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let count = rewritten.len();
+        for (url, path, size, checksum, info_hash) in rewritten {
+            config.sizes.insert(path.clone(), size);
+            config.checksums.insert(path, checksum);
+            config.infohashes.insert(url, info_hash);
+        }
+        say!("Rewrote trackers of {count} torrents");
+
+        Ok(count)
+    }
+}
+
+fn bytes_value(text: &str) -> Value {
+    Value::Bytes(text.as_bytes().to_vec())
+}
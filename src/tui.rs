@@ -0,0 +1,304 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Debug,
+    io::{self, Stdout},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Result;
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor::{Hide, Show},
+        execute,
+        terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style},
+    widgets::{Block, Borders, Gauge, List, ListItem, Row, Sparkline, Table},
+    Frame, Terminal,
+};
+use tracing::{
+    field::{Field, Visit},
+    span::Attributes,
+    Event, Id, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::throttle::format_bytes;
+
+const MAX_ERRORS: usize = 50;
+const MAX_SAMPLES: usize = 300;
+const TICK: Duration = Duration::from_secs(1);
+
+/* Everything the dashboard shows, fed from tracing events rather than threaded through the pipeline */
+#[derive(Debug, Default)]
+struct Board {
+    step: Option<u64>,
+    total: u64,
+    done: u64,
+    failed: u64,
+    exits: BTreeMap<String, (u64, u64)>,
+    errors: VecDeque<String>,
+    bytes: u64,
+    sampled: u64,
+    throughput: VecDeque<u64>,
+}
+
+/* Fields the dashboard cares about, from either a span or an event */
+#[derive(Debug, Default)]
+struct Fields {
+    message: String,
+    step: Option<u64>,
+    total: Option<u64>,
+    bytes: Option<u64>,
+    exit: Option<String>,
+    url: Option<String>,
+    error: Option<String>,
+}
+
+impl Visit for Fields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "step" => self.step = Some(value),
+            "total" => self.total = Some(value),
+            "bytes" => self.bytes = Some(value),
+            _ => {}
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record_u64(field, value.max(0) as u64);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_text(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record_text(field, format!("{value:?}"));
+    }
+}
+
+impl Fields {
+    fn record_text(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "message" => self.message = value,
+            "exit" => self.exit = Some(value),
+            "url" => self.url = Some(value),
+            "error" => self.error = Some(value),
+            _ => {}
+        }
+    }
+}
+
+/* Remembers the exit of each request span, so events inside it can be counted against that exit */
+struct SpanExit(String);
+
+pub struct BoardLayer(Arc<Mutex<Board>>);
+
+impl<S> Layer<S> for BoardLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+
+        if let (Some(exit), Some(span)) = (fields.exit, ctx.span(id)) {
+            span.extensions_mut().insert(SpanExit(exit));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let exit = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .into_iter()
+                .find_map(|span| Some(span.extensions().get::<SpanExit>()?.0.clone()))
+        });
+
+        let mut board = self.0.lock().unwrap();
+        match fields.message.as_str() {
+            "Starting step" => {
+                board.step = fields.step;
+                board.total = 0;
+                board.done = 0;
+                board.failed = 0;
+            }
+            "Fetching files" => board.total = fields.total.unwrap_or_default(),
+            "Saved file" => {
+                board.done += 1;
+                board.bytes += fields.bytes.unwrap_or_default();
+                if let Some(exit) = exit {
+                    board.exits.entry(exit).or_default().0 += 1;
+                }
+            }
+            "Failed to save file" => {
+                if let Some(exit) = exit {
+                    board.exits.entry(exit).or_default().1 += 1;
+                }
+            }
+            "Giving up on file" => board.failed += 1,
+            _ => {}
+        }
+
+        if *event.metadata().level() <= Level::WARN {
+            let detail = [fields.url, fields.error].into_iter().flatten();
+            let line = std::iter::once(fields.message)
+                .chain(detail)
+                .collect::<Vec<_>>()
+                .join(": ");
+            board.errors.push_front(line);
+            board.errors.truncate(MAX_ERRORS);
+        }
+    }
+}
+
+/* Takes over the terminal until dropped; the normal output resumes after it */
+pub struct Dashboard {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+pub fn start() -> Result<(Dashboard, BoardLayer)> {
+    let board = Arc::new(Mutex::new(Board::default()));
+    let running = Arc::new(AtomicBool::new(true));
+
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, Hide)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let handle = {
+        let board = Arc::clone(&board);
+        let running = Arc::clone(&running);
+        thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                /* Progress bars and prints still reach the terminal, so repaint everything each tick */
+                let _ = terminal.clear();
+                let _ = draw(&mut terminal, &board);
+                thread::sleep(TICK);
+            }
+        })
+    };
+
+    let dashboard = Dashboard {
+        running,
+        handle: Some(handle),
+    };
+
+    Ok((dashboard, BoardLayer(board)))
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+    }
+}
+
+fn draw(terminal: &mut Terminal<CrosstermBackend<Stdout>>, board: &Mutex<Board>) -> Result<()> {
+    let mut board = board.lock().unwrap();
+    let sample = board.bytes - board.sampled;
+    board.sampled = board.bytes;
+    board.throughput.push_back(sample);
+    if board.throughput.len() > MAX_SAMPLES {
+        board.throughput.pop_front();
+    }
+
+    terminal.draw(|frame| render(frame, &board))?;
+
+    Ok(())
+}
+
+fn render(frame: &mut Frame, board: &Board) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(8),
+            Constraint::Length(6),
+        ])
+        .split(frame.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+        .split(rows[1]);
+
+    let title = match board.step {
+        Some(step) => format!("Step {step}"),
+        None => "Starting".to_string(),
+    };
+    let ratio = match board.total {
+        0 => 0.0,
+        total => (board.done as f64 / total as f64).min(1.0),
+    };
+    let label = format!("{} / {} ({} failed)", board.done, board.total, board.failed);
+    let gauge = Gauge::default()
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .gauge_style(Style::default().fg(Color::Green))
+        .ratio(ratio)
+        .label(label);
+    frame.render_widget(gauge, rows[0]);
+
+    let mut exits = board.exits.iter().collect::<Vec<_>>();
+    exits.sort_by_key(|(_exit, (successes, failures))| std::cmp::Reverse(successes + failures));
+    let exits = exits.into_iter().map(|(exit, (successes, failures))| {
+        let rate = *successes as f64 * 100.0 / (successes + failures).max(1) as f64;
+        Row::new(vec![
+            exit.clone(),
+            successes.to_string(),
+            failures.to_string(),
+            format!("{rate:.0}%"),
+        ])
+    });
+    let widths = [
+        Constraint::Min(20),
+        Constraint::Length(8),
+        Constraint::Length(8),
+        Constraint::Length(6),
+    ];
+    let table = Table::new(exits, widths)
+        .header(Row::new(vec!["EXIT", "OK", "FAILED", "RATE"]))
+        .block(Block::default().title("Exits").borders(Borders::ALL));
+    frame.render_widget(table, columns[0]);
+
+    let errors = board
+        .errors
+        .iter()
+        .map(|error| ListItem::new(error.as_str()))
+        .collect::<Vec<_>>();
+    let errors = List::new(errors).block(
+        Block::default()
+            .title("Recent errors")
+            .borders(Borders::ALL),
+    );
+    frame.render_widget(errors, columns[1]);
+
+    /* Newest samples on the right, as many as fit */
+    let width = rows[2].width.saturating_sub(2) as usize;
+    let samples = board.throughput.iter().copied().collect::<Vec<_>>();
+    let samples = &samples[samples.len().saturating_sub(width)..];
+    let speed = samples.last().copied().unwrap_or_default();
+    let title = format!(
+        "Throughput {}/s, {} total",
+        format_bytes(speed as f64 / TICK.as_secs_f64()),
+        format_bytes(board.bytes as f64)
+    );
+    let sparkline = Sparkline::default()
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .data(samples)
+        .style(Style::default().fg(Color::Cyan));
+    frame.render_widget(sparkline, rows[2]);
+}
@@ -0,0 +1,425 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use anyhow::Result;
+use reqwest::Url;
+
+use crate::{adapter::Adapter, config::Config, queue};
+
+/* Rows on one page of the entry list */
+const PAGE_SIZE: usize = 200;
+
+/* Filter options, where the empty one, first so it is the default, means any */
+const KINDS: [&str; 7] = ["", "movie", "tv", "software", "music", "book", "unknown"];
+const STATUSES: [&str; 10] = [
+    "",
+    "downloaded",
+    "pending",
+    "queued",
+    "not scraped",
+    "archived",
+    "dead",
+    "superseded",
+    "blocked",
+    "retired",
+];
+
+pub struct Request {
+    pub method: String,
+    pub url: Url,
+    /* Names in lowercase */
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _value)| key == name)
+            .map(|(_key, value)| value.as_str())
+    }
+
+    /* Whether the page that sent it was served from this host; browsers send Origin with every form post, so any other page the user visits cannot queue entries */
+    fn is_same_origin(&self) -> bool {
+        let host = self.header("host");
+        let origin = self.header("origin").or_else(|| self.header("referer"));
+        let origin_host = origin
+            .and_then(|origin| Url::parse(origin).ok())
+            .and_then(|origin| {
+                let host = origin.host_str()?.to_string();
+                Some(match origin.port() {
+                    Some(port) => format!("{host}:{port}"),
+                    None => host,
+                })
+            });
+
+        host.is_some_and(|host| origin_host.as_deref() == Some(host))
+    }
+}
+
+pub struct Response {
+    pub status: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn html(body: String) -> Self {
+        Self {
+            status: "200 OK",
+            headers: vec![("Content-Type", "text/html; charset=utf-8".to_string())],
+            body: body.into_bytes(),
+        }
+    }
+
+    /* See Other, so reloading the page after queueing does not post again */
+    fn redirect(location: &str) -> Self {
+        Self {
+            status: "303 See Other",
+            headers: vec![("Location", location.to_string())],
+            body: Vec::new(),
+        }
+    }
+
+    fn forbidden() -> Self {
+        Self {
+            status: "403 Forbidden",
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self {
+            status: "404 Not Found",
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+}
+
+/* Browses the archive from the state on disk, read again on every request; queueing is the only thing it changes */
+pub struct Ui {
+    /* Behind a lock because its placements are refreshed from the state on every request */
+    adapter: Mutex<Adapter>,
+    base_path: String,
+    torrents_path: String,
+}
+
+impl Ui {
+    pub fn new(adapter: Adapter, base_path: &str, torrents_path: &str) -> Self {
+        Self {
+            adapter: Mutex::new(adapter),
+            base_path: base_path.to_string(),
+            torrents_path: torrents_path.to_string(),
+        }
+    }
+
+    /* Entries waiting for the next run */
+    pub fn queue_depth(&self) -> Result<usize> {
+        Ok(queue::load(&self.base_path)?.len())
+    }
+
+    pub fn handle(&self, request: &Request) -> Result<Response> {
+        match (request.method.as_str(), request.url.path()) {
+            ("GET", "/") => self.index(request),
+            ("GET", "/torrent") => self.torrent(request),
+            ("POST", "/queue") => self.queue(request),
+            _ => Ok(Response::not_found()),
+        }
+    }
+
+    fn index(&self, request: &Request) -> Result<Response> {
+        let config = Config::load(&self.base_path)?;
+        let queued = queue::load(&self.base_path)?;
+        let adapter = self.adapter(&config);
+
+        let param = |name: &str| {
+            request
+                .url
+                .query_pairs()
+                .find(|(key, _value)| key == name)
+                .map(|(_key, value)| value.into_owned())
+                .unwrap_or_default()
+        };
+        let search = param("q").to_lowercase();
+        let kind = param("kind");
+        let status = param("status");
+        let page = param("page").parse::<usize>().unwrap_or(1).max(1);
+
+        let mut rows = Vec::new();
+        for entry in &config.entries {
+            let metadata = config.metadata.get(entry).cloned().unwrap_or_default();
+            let entry_kind = format!("{:?}", metadata.kind).to_lowercase();
+            let torrents = config.links.get(entry).cloned().unwrap_or_default();
+            let saved = torrents
+                .iter()
+                .filter(|url| {
+                    adapter
+                        .torrent_path(&self.torrents_path, url)
+                        .is_some_and(|path| Path::new(&path).exists())
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            let entry_status = status_of(&config, &queued, entry, &torrents, &saved);
+
+            let matches = search.is_empty()
+                || entry.to_lowercase().contains(&search)
+                || metadata.title.to_lowercase().contains(&search);
+            if matches
+                && (kind.is_empty() || kind == entry_kind)
+                && (status.is_empty() || status == entry_status)
+            {
+                rows.push((entry, metadata.title, entry_kind, entry_status, saved));
+            }
+        }
+
+        let total = rows.len();
+        let first = (page - 1) * PAGE_SIZE;
+        let back = match request.url.query() {
+            Some(query) => format!("/?{query}"),
+            None => "/".to_string(),
+        };
+
+        let mut html = String::from(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Torrents</title>\
+             <style>body{font-family:sans-serif;margin:1em}table{border-collapse:collapse;width:100%}\
+             td,th{padding:4px 8px;border-bottom:1px solid #ddd;text-align:left}form{display:inline}</style>\
+             </head><body><h1>Torrents</h1>",
+        );
+        html += &format!(
+            "<form method=\"get\" action=\"/\"><input name=\"q\" value=\"{}\" placeholder=\"Search titles\"> ",
+            escape(&param("q"))
+        );
+        html += &select("kind", &kind, &KINDS);
+        html += &select("status", &status, &STATUSES);
+        html += " <button>Filter</button></form>";
+
+        html +=
+            "<table><tr><th>Title</th><th>Kind</th><th>Status</th><th>Torrents</th><th></th></tr>";
+        for (entry, title, kind, status, saved) in rows.iter().skip(first).take(PAGE_SIZE) {
+            let title = match title.is_empty() {
+                true => entry.as_str(),
+                false => title.as_str(),
+            };
+            html += &format!(
+                "<tr><td><a href=\"{}\">{}</a></td><td>{kind}</td><td>{status}</td><td>",
+                escape(&adapter.entry_url(entry)),
+                escape(title)
+            );
+            for url in saved {
+                let name = adapter.torrent_name(url).unwrap_or(url);
+                let mut link = Url::parse("http://localhost/torrent")?;
+                link.query_pairs_mut().append_pair("url", url);
+                html += &format!(
+                    "<a href=\"/torrent?{}\">{}</a> ",
+                    escape(link.query().unwrap_or_default()),
+                    escape(name)
+                );
+            }
+            html += "</td><td>";
+            if matches!(
+                *status,
+                "pending" | "not scraped" | "archived" | "dead" | "superseded"
+            ) {
+                html += &format!(
+                    "<form method=\"post\" action=\"/queue\">\
+                     <input type=\"hidden\" name=\"entry\" value=\"{}\">\
+                     <input type=\"hidden\" name=\"back\" value=\"{}\">\
+                     <button>Queue for download</button></form>",
+                    escape(entry),
+                    escape(&back)
+                );
+            }
+            html += "</td></tr>";
+        }
+        html += "</table>";
+
+        let last = (first + PAGE_SIZE).min(total);
+        html += &format!(
+            "<p>Showing {} to {last} of {total} entries",
+            (first + 1).min(total)
+        );
+        for (label, target) in [("Previous", page - 1), ("Next", page + 1)] {
+            if target >= 1 && (target - 1) * PAGE_SIZE < total {
+                let mut link = request.url.clone();
+                let pairs = request
+                    .url
+                    .query_pairs()
+                    .filter(|(key, _value)| key != "page")
+                    .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                    .collect::<Vec<_>>();
+                link.query_pairs_mut()
+                    .clear()
+                    .extend_pairs(pairs)
+                    .append_pair("page", &target.to_string());
+                html += &format!(
+                    " <a href=\"/?{}\">{label}</a>",
+                    escape(link.query().unwrap_or_default())
+                );
+            }
+        }
+        html += "</p></body></html>";
+
+        Ok(Response::html(html))
+    }
+
+    /* Only torrents the state knows are served, so no path can be asked for directly */
+    fn torrent(&self, request: &Request) -> Result<Response> {
+        let Some((_key, url)) = request.url.query_pairs().find(|(key, _value)| key == "url") else {
+            return Ok(Response::not_found());
+        };
+
+        let config = Config::load(&self.base_path)?;
+        if !config.torrents.iter().any(|known| *known == url) {
+            return Ok(Response::not_found());
+        }
+        let path = self
+            .adapter(&config)
+            .torrent_path(&self.torrents_path, &url);
+        let Some(body) = path.as_ref().and_then(|path| fs::read(path).ok()) else {
+            return Ok(Response::not_found());
+        };
+
+        let name = path
+            .as_deref()
+            .and_then(|path| Path::new(path).file_name())
+            .map(|name| name.to_string_lossy().replace('"', ""))
+            .unwrap_or_default();
+
+        Ok(Response {
+            status: "200 OK",
+            headers: vec![
+                ("Content-Type", "application/x-bittorrent".to_string()),
+                (
+                    "Content-Disposition",
+                    format!("attachment; filename=\"{name}\""),
+                ),
+            ],
+            body,
+        })
+    }
+
+    fn queue(&self, request: &Request) -> Result<Response> {
+        if !request.is_same_origin() {
+            return Ok(Response::forbidden());
+        }
+
+        let form = Url::parse(&format!("http://localhost/?{}", request.body))?;
+        let field = |name: &str| {
+            form.query_pairs()
+                .find(|(key, _value)| key == name)
+                .map(|(_key, value)| value.into_owned())
+                .unwrap_or_default()
+        };
+        let entry = field("entry");
+        let back = field("back");
+
+        let config = Config::load(&self.base_path)?;
+        if config.entries.binary_search(&entry).is_err() {
+            return Ok(Response::not_found());
+        }
+        queue::push(&self.base_path, &entry)?;
+
+        /* Only back to the list, never off the site */
+        match back.starts_with("/?") {
+            true => Ok(Response::redirect(&back)),
+            false => Ok(Response::redirect("/")),
+        }
+    }
+
+    fn adapter(&self, config: &Config) -> MutexGuard<'_, Adapter> {
+        let mut adapter = self.adapter.lock().unwrap();
+        adapter.placements = config.placements.clone();
+
+        adapter
+    }
+}
+
+/* What the pipeline has done with an entry, as the UI shows it */
+fn status_of(
+    config: &Config,
+    queued: &BTreeSet<String>,
+    entry: &str,
+    torrents: &[String],
+    saved: &[String],
+) -> &'static str {
+    let downloaded = !torrents.is_empty()
+        && torrents
+            .iter()
+            .all(|url| saved.contains(url) || config.sent.contains(url));
+
+    if config.tombstones.contains(entry) {
+        "retired"
+    } else if config.blocked.contains_key(entry) {
+        "blocked"
+    } else if downloaded {
+        "downloaded"
+    } else if queued.contains(entry) {
+        "queued"
+    } else if config.archived.contains_key(entry) {
+        "archived"
+    } else if config.dead.contains_key(entry) {
+        "dead"
+    } else if config.superseded.contains_key(entry) {
+        "superseded"
+    } else if torrents.is_empty() {
+        "not scraped"
+    } else {
+        "pending"
+    }
+}
+
+/* A drop-down that filters on name */
+fn select(name: &str, selected: &str, options: &[&str]) -> String {
+    let mut html = format!("<select name=\"{name}\">");
+    for option in options {
+        let label = if option.is_empty() { "any" } else { option };
+        let mark = if option == &selected { " selected" } else { "" };
+        html += &format!("<option value=\"{option}\"{mark}>{name}: {label}</option>");
+    }
+    html += "</select> ";
+
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "POST".to_string(),
+            url: Url::parse("http://localhost/queue").unwrap(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn queues_only_from_its_own_pages() {
+        let host = ("host", "127.0.0.1:8080");
+
+        assert!(post(&[host, ("origin", "http://127.0.0.1:8080")]).is_same_origin());
+        assert!(post(&[host, ("referer", "http://127.0.0.1:8080/?page=2")]).is_same_origin());
+        assert!(!post(&[host, ("origin", "https://example.com")]).is_same_origin());
+        assert!(!post(&[host, ("origin", "null")]).is_same_origin());
+        assert!(!post(&[host]).is_same_origin());
+    }
+}
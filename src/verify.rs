@@ -0,0 +1,139 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use kdam::{rayon::prelude::*, BarExt, TqdmParallelIterator};
+
+use crate::{adapter::Adapter, bencode, config::Config, events, hash::Algorithm};
+
+/* What is wrong with an archived torrent, if anything */
+fn check(config: &Config, url: &str, path: &str) -> Option<String> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        /* Never archived, so step 7 has yet to fetch it rather than it having gone missing */
+        Err(_error) if !config.checksums.contains_key(path) => return None,
+        Err(error) => return Some(format!("missing ({error})")),
+    };
+
+    if let Some(&size) = config.sizes.get(path) {
+        let actual = bytes.len() as u64;
+        if actual < size {
+            return Some(format!("truncated ({actual} of {size} bytes)"));
+        }
+        if actual != size {
+            return Some(format!("size changed ({actual}, was {size} bytes)"));
+        }
+    }
+
+    if let Some(checksum) = config.checksums.get(path) {
+        let algorithm = Algorithm::of(checksum).unwrap_or_default();
+        if algorithm.digest(&bytes) != *checksum {
+            return Some("checksum mismatch".to_string());
+        }
+    }
+
+    let info_hash = match bencode::info_hash(&bytes) {
+        Ok(info_hash) => info_hash,
+        Err(error) => return Some(format!("invalid bencode ({error})")),
+    };
+    match config.infohashes.get(url) {
+        Some(recorded) if *recorded != info_hash => Some("infohash mismatch".to_string()),
+        _ => None,
+    }
+}
+
+/* Checks every archived torrent against its recorded size, checksum and infohash */
+pub fn verify(
+    adapter: &Adapter,
+    config: &mut Config,
+    base_path: &str,
+    torrents_path: &str,
+    requeue: bool,
+    manifest: Option<&str>,
+) -> Result<()> {
+    let torrents = config
+        .torrents
+        .iter()
+        .filter_map(|url| Some((url.clone(), adapter.torrent_path(torrents_path, url)?)))
+        .collect::<Vec<_>>();
+
+    let mut bar = events::bar(torrents.len());
+    bar.write(format!("Verifying {} torrents...", torrents.len()))?;
+
+    let results = torrents
+        .into_par_iter()
+        .tqdm_with_bar(bar)
+        .map(|(url, path)| {
+            let problem = check(config, &url, &path);
+            (url, path, problem)
+        })
+        .collect::<Vec<_>>();
+
+    let bad = results
+        .iter()
+        .filter_map(|(url, path, problem)| Some((url, path, problem.as_ref()?)))
+        .collect::<Vec<_>>();
+    for (_url, path, problem) in &bad {
+        println!("{problem:<40}  {path}");
+    }
+    println!(
+        "Verified {} torrents, {} bad",
+        results.len() - bad.len(),
+        bad.len()
+    );
+
+    /* Bare digests and paths relative to the torrent directory, one file per algorithm, e.g. MANIFEST.b3 for `b3sum -c` */
+    if let Some(manifest) = manifest {
+        let mut lines = BTreeMap::<_, Vec<_>>::new();
+        for (_url, path, _problem) in results.iter().filter(|(_, _, problem)| problem.is_none()) {
+            let Some(checksum) = config.checksums.get(path) else {
+                continue;
+            };
+            let (Some(algorithm), Some((_name, hex))) =
+                (Algorithm::of(checksum), checksum.split_once(':'))
+            else {
+                continue;
+            };
+            let relative = Path::new(path)
+                .strip_prefix(torrents_path)
+                .unwrap_or(Path::new(path));
+            let line = format!("{hex}  {}", relative.display());
+            lines.entry(algorithm.extension()).or_default().push(line);
+        }
+
+        for (extension, lines) in lines {
+            let path = format!("{manifest}.{extension}");
+            let mut writer = BufWriter::new(File::create(&path)?);
+            for line in lines {
+                writeln!(writer, "{line}")?;
+            }
+            writer.flush()?;
+            println!("Wrote manifest to {path}");
+        }
+    }
+
+    if !requeue || bad.is_empty() {
+        return Ok(());
+    }
+
+    /* Step 7 fetches every torrent whose file is missing, so removing a bad one queues it again */
+    for (url, path, _problem) in &bad {
+        let _ = fs::remove_file(path);
+        config.checksums.remove(*path);
+        config.sizes.remove(*path);
+        config.validators.remove(*path);
+        config.statuses.remove(*path);
+        config.infohashes.remove(*url);
+    }
+    config.save(base_path)?;
+    println!(
+        "Removed {} bad torrents for the next run to fetch",
+        bad.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use reqwest::Url;
+use tracing::{info, warn};
+
+use crate::{
+    cache, forward,
+    metrics::Metrics,
+    ui::{Request, Response, Ui},
+};
+
+/* Form posts are a single entry, so anything larger is not one */
+const MAX_BODY: usize = 64 << 10;
+/* Request line and headers together; a browser sends a few kilobytes */
+const MAX_HEAD: u64 = 16 << 10;
+/* A client that stalls this long mid-request is dropped */
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+struct Status {
+    running: bool,
+    successes: u64,
+    failures: u64,
+    last_success: Option<u64>,
+    last_duration: f64,
+}
+
+/* Runs the pipeline as a child process every interval, and serves /metrics and the web UI between runs */
+pub fn watch(
+    interval: u64,
+    listen: &str,
+    metrics_file: Option<&str>,
+    base_path: &str,
+    ui: Ui,
+) -> Result<()> {
+    let metrics_file = metrics_file
+        .map(String::from)
+        .unwrap_or_else(|| cache::join(base_path, &["METRICS.PROM"]));
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let run_args = run_args(&args, &metrics_file)?;
+
+    let status = Arc::new(Mutex::new(Status::default()));
+    let listener =
+        TcpListener::bind(listen).with_context(|| format!("Failed to listen on {listen}"))?;
+    println!("Serving the archive on http://{listen}/ and metrics on http://{listen}/metrics");
+    {
+        let status = Arc::clone(&status);
+        let metrics_file = Arc::new(metrics_file.clone());
+        let ui = Arc::new(ui);
+        thread::spawn(move || {
+            /* A thread per connection, so one slow client never holds up the rest */
+            for stream in listener.incoming().filter_map(Result::ok) {
+                let status = Arc::clone(&status);
+                let metrics_file = Arc::clone(&metrics_file);
+                let ui = Arc::clone(&ui);
+                thread::spawn(move || {
+                    if let Err(error) = serve(stream, &status, &metrics_file, &ui) {
+                        warn!(%error, "Failed to serve request");
+                    }
+                });
+            }
+        });
+    }
+
+    let exe = env::current_exe()?;
+    loop {
+        status.lock().unwrap().running = true;
+        let start = SystemTime::now();
+        let result = Command::new(&exe).args(&run_args).status();
+        let finished = SystemTime::now();
+
+        let mut status = status.lock().unwrap();
+        status.running = false;
+        status.last_duration = finished.duration_since(start)?.as_secs_f64();
+        match result {
+            Ok(exit) if exit.success() => {
+                status.successes += 1;
+                status.last_success = Some(finished.duration_since(UNIX_EPOCH)?.as_secs());
+                info!("Run succeeded");
+            }
+            Ok(exit) => {
+                status.failures += 1;
+                warn!(%exit, "Run failed");
+            }
+            Err(error) => {
+                status.failures += 1;
+                warn!(%error, "Failed to start run");
+            }
+        }
+        drop(status);
+
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
+
+/* Global flags are everything before the subcommand, and are passed on to every run, which writes its metrics where /metrics reads them */
+fn run_args(args: &[String], metrics_file: &str) -> Result<Vec<String>> {
+    let mut run_args = forward::leading(args, &["metrics_file"])?;
+    run_args.extend(["--metrics-file".to_string(), metrics_file.to_string()]);
+
+    Ok(run_args)
+}
+
+fn serve(mut stream: TcpStream, status: &Mutex<Status>, metrics_file: &str, ui: &Ui) -> Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let request = read_request(&stream)?;
+    if request.url.path() != "/metrics" {
+        let response = ui.handle(&request).unwrap_or_else(|error| {
+            warn!(url = %request.url, %error, "Failed to serve the web UI");
+            Response::not_found()
+        });
+        return respond(&mut stream, response);
+    }
+
+    let mut metrics = Metrics::default();
+    {
+        let status = status.lock().unwrap();
+
+        metrics.family(
+            "torrents_watch_running",
+            "gauge",
+            "Whether a run is in progress",
+        );
+        metrics.sample("torrents_watch_running", &[], status.running as u8);
+        metrics.family(
+            "torrents_watch_runs_total",
+            "counter",
+            "Finished runs by result",
+        );
+        metrics.sample(
+            "torrents_watch_runs_total",
+            &[("result", "success")],
+            status.successes,
+        );
+        metrics.sample(
+            "torrents_watch_runs_total",
+            &[("result", "failure")],
+            status.failures,
+        );
+        metrics.family(
+            "torrents_watch_last_run_duration_seconds",
+            "gauge",
+            "Duration of the last finished run",
+        );
+        metrics.sample(
+            "torrents_watch_last_run_duration_seconds",
+            &[],
+            status.last_duration,
+        );
+        metrics.family(
+            "torrents_watch_queue_depth",
+            "gauge",
+            "Entries queued from the web UI for the next run",
+        );
+        metrics.sample("torrents_watch_queue_depth", &[], ui.queue_depth()?);
+        if let Some(last_success) = status.last_success {
+            metrics.family(
+                "torrents_watch_last_success_timestamp_seconds",
+                "gauge",
+                "End of the last successful run",
+            );
+            metrics.sample(
+                "torrents_watch_last_success_timestamp_seconds",
+                &[],
+                last_success,
+            );
+        }
+    }
+
+    /* The last run's own metrics follow */
+    metrics.extend(&fs::read_to_string(metrics_file).unwrap_or_default());
+    let body = metrics.render();
+
+    let response = Response {
+        status: "200 OK",
+        headers: vec![(
+            "Content-Type",
+            "text/plain; version=0.0.4; charset=utf-8".to_string(),
+        )],
+        body: body.into_bytes(),
+    };
+
+    respond(&mut stream, response)
+}
+
+/* The request line, the headers up to the blank line, and as much body as Content-Length announces */
+fn read_request(stream: &TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream.take(MAX_HEAD + MAX_BODY as u64));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or("/");
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _value)| name == "content-length")
+        .and_then(|(_name, value)| value.parse::<usize>().ok())
+        .unwrap_or_default();
+
+    let mut body = vec![0; length.min(MAX_BODY)];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method,
+        url: Url::parse("http://localhost")?.join(target)?,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn respond(stream: &mut TcpStream, response: Response) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head += &format!("{name}: {value}\r\n");
+    }
+    head += "\r\n";
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_the_flags_before_watch_to_every_run() {
+        let args = [
+            "--profile",
+            "watch",
+            "--metrics-file",
+            "OLD.PROM",
+            "watch",
+            "--interval",
+            "60",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            run_args(&args, "M.PROM").unwrap(),
+            ["--profile", "watch", "--metrics-file", "M.PROM"]
+        );
+        assert!(run_args(&args[..2], "M.PROM").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use tracing::warn;
+
+type Job = (String, Vec<u8>);
+
+#[derive(Default)]
+struct Pending {
+    count: Mutex<usize>,
+    done: Condvar,
+    failures: AtomicUsize,
+}
+
+/* Disk writes go through a bounded queue, so a slow disk holds back the fetchers instead of piling up bodies in memory */
+pub struct Writer {
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    pending: Arc<Pending>,
+}
+
+impl Writer {
+    pub fn new(threads: usize, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let pending = Arc::new(Pending::default());
+
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let pending = Arc::clone(&pending);
+                thread::spawn(move || work(&receiver, &pending))
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            workers,
+            pending,
+        }
+    }
+
+    /* Blocks while the queue is full */
+    pub fn write(&self, path: String, contents: Vec<u8>) {
+        *self.pending.count.lock().unwrap() += 1;
+
+        let sender = self.sender.as_ref().unwrap();
+        if let Err(mpsc::SendError((path, contents))) = sender.send((path, contents)) {
+            /* Every worker is gone, so write on the caller's thread */
+            save(&path, &contents, &self.pending);
+        }
+    }
+
+    /* Waits for every queued write and returns how many failed since the last flush */
+    pub fn flush(&self) -> usize {
+        let count = self.pending.count.lock().unwrap();
+        let _count = self
+            .pending
+            .done
+            .wait_while(count, |count| *count > 0)
+            .unwrap();
+
+        self.pending.failures.swap(0, Ordering::Relaxed)
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>, pending: &Pending) {
+    loop {
+        let job = receiver.lock().unwrap().recv();
+        let Ok((path, contents)) = job else {
+            return;
+        };
+
+        save(&path, &contents, pending);
+    }
+}
+
+fn save(path: &str, contents: &[u8], pending: &Pending) {
+    let result = Path::new(path)
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(path, contents));
+
+    if let Err(error) = result {
+        warn!(path, %error, "Failed to write file");
+        pending.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mut count = pending.count.lock().unwrap();
+    *count -= 1;
+    if *count == 0 {
+        pending.done.notify_all();
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::Config;
+
+pub type Sources = BTreeMap<String, BTreeSet<String>>;
+
+/* Groups torrent links by content: the infohash when one is known, otherwise the entry title */
+pub fn index(config: &Config, sources: &mut Sources) {
+    for (entry, links) in &config.links {
+        let title = config
+            .metadata
+            .get(entry)
+            .map(|metadata| normalize(&metadata.title))
+            .filter(|title| !title.is_empty());
+
+        for url in links {
+            let keys = [config.infohashes.get(url).cloned(), title.clone()];
+            for key in keys.into_iter().flatten() {
+                sources.entry(key).or_default().insert(url.clone());
+            }
+        }
+    }
+}
+
+/* Every other link known to carry the same content as url */
+pub fn alternatives(sources: &Sources, url: &str) -> Vec<String> {
+    let alternatives = sources
+        .values()
+        .filter(|urls| urls.contains(url))
+        .flatten()
+        .filter(|alternative| *alternative != url)
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    alternatives.into_iter().collect()
+}
+
+pub fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}